@@ -0,0 +1,90 @@
+//! Measures the memory retained by a parsed document and the time taken to
+//! parse it, for a large generated fixture. Run with `cargo bench --bench
+//! memory`.
+//!
+//! The retained memory is compared with the baseline measured at revision
+//! 421b672, before names were interned and parsed documents compacted. The
+//! parse time depends on the machine, so its baseline has to be measured
+//! again: check out that revision, copy this file and `Cargo.lock` into it,
+//! add the `[[bench]]` section for `memory` to `Cargo.toml`, and run the
+//! bench there. On the machine where the retained memory fell from 774 to
+//! 336 bytes per element, the fastest parse went from 1.81–1.93 s to
+//! 1.31–1.77 s over three runs each.
+
+extern crate rquery;
+
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+
+use rquery::Document;
+
+const RECORDS: usize = 50_000;
+
+/// The bytes retained by the fixture's document at the baseline revision.
+const BASELINE_RETAINED: usize = 271_048_814;
+
+/// Wraps the system allocator to keep count of the bytes allocated.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(pointer, layout)
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(pointer, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn new_fixture() -> String {
+    let mut xml = String::from(r#"<catalog xmlns="http://example.com/catalog" xmlns:xlink="http://www.w3.org/1999/xlink">"#);
+
+    for index in 0..RECORDS {
+        xml.push_str(&format!(
+            r#"<record id="record-{0}" type="book" status="available"><title lang="en">Record {0}</title><author role="primary">Author {0}</author><link xlink:href="http://example.com/{0}" xlink:type="simple"/><tags><tag>one</tag><tag>two</tag></tags></record>"#,
+            index,
+        ));
+    }
+
+    xml.push_str("</catalog>");
+    xml
+}
+
+fn main() {
+    let xml = new_fixture();
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let document = Document::new_from_xml_string(&xml).unwrap();
+    let retained = ALLOCATED.load(Ordering::Relaxed) - before;
+    let elements = document.number_of_elements();
+
+    drop(document);
+
+    let mut fastest = Duration::MAX;
+
+    for _ in 0..5 {
+        let start = Instant::now();
+        let document = Document::new_from_xml_string(&xml).unwrap();
+        fastest = fastest.min(start.elapsed());
+        drop(document);
+    }
+
+    println!("fixture: {} bytes, {} elements", xml.len(), elements);
+    println!("retained: {} bytes ({} bytes/element)", retained, retained / elements);
+    println!("baseline: {} bytes ({} bytes/element), {:.0}% more than now", BASELINE_RETAINED, BASELINE_RETAINED / elements, 100.0 * (BASELINE_RETAINED as f64 / retained as f64 - 1.0));
+    println!("parse time: {:.2?} (fastest of 5)", fastest);
+}
@@ -0,0 +1,101 @@
+//! Compares querying with selector strings, which are parsed on every call,
+//! against querying with compiled `SelectorList`s, and lookups with and
+//! without indexes. Run with `cargo bench`.
+
+extern crate rquery;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use rquery::{ Document, ParseOptions, SelectorList };
+
+const RECORDS: usize = 100_000;
+
+fn new_document(options: &ParseOptions) -> Document {
+    let mut xml = String::from("<records>");
+
+    for index in 0..RECORDS {
+        xml.push_str(&format!(
+            r#"<record id="record-{0}"><title lang="en">Record {0}</title><ref>http://example.com/{0}</ref><note/></record>"#,
+            index,
+        ));
+    }
+
+    xml.push_str("</records>");
+
+    Document::new_from_xml_string_with_options(&xml, options).unwrap()
+}
+
+fn bench<F: FnMut() -> usize>(name: &str, iterations: usize, mut run: F) {
+    let start = Instant::now();
+    let matches = black_box(run());
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<44} {:>10.2?} total {:>10.0} ns/iteration ({} matches)",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        matches,
+    );
+}
+
+fn main() {
+    let document = new_document(&ParseOptions::default());
+    let records: Vec<_> = document.select_all("record").unwrap().collect();
+
+    bench("select_all(\"title\")", RECORDS, || {
+        records.iter().map(|record| record.select_all("title").unwrap().count()).sum()
+    });
+
+    let title = SelectorList::parse("title").unwrap();
+
+    bench("select_all_compiled(title)", RECORDS, || {
+        records.iter().map(|record| record.select_all_compiled(&title).count()).sum()
+    });
+
+    bench("select(\"title[lang=en], ref\")", RECORDS, || {
+        records.iter().filter(|record| record.select("title[lang=en], ref").is_ok()).count()
+    });
+
+    let title_or_ref = SelectorList::parse("title[lang=en], ref").unwrap();
+
+    bench("select_compiled(title[lang=en], ref)", RECORDS, || {
+        records.iter().filter(|record| record.select_compiled(&title_or_ref).is_some()).count()
+    });
+
+    bench("closest(\"records > record\")", RECORDS, || {
+        records.iter().filter(|record| record.closest("records > record").is_ok()).count()
+    });
+
+    let direct_record = SelectorList::parse("records > record").unwrap();
+
+    bench("matches(records > record)", RECORDS, || {
+        records.iter().filter(|record| record.matches(&direct_record)).count()
+    });
+
+    let indexed_document = new_document(&ParseOptions {
+        index: true,
+        ..ParseOptions::default()
+    });
+
+    // unindexed lookups walk the whole document, so only some of the
+    // records are looked up
+    let ids: Vec<String> = (0..RECORDS).step_by(1000).map(|index| format!("#record-{}", index)).collect();
+
+    bench("select(\"#record-n\") without indexes", ids.len(), || {
+        ids.iter().filter(|id| document.select(id).is_ok()).count()
+    });
+
+    bench("select(\"#record-n\") with indexes", ids.len(), || {
+        ids.iter().filter(|id| indexed_document.select(id).is_ok()).count()
+    });
+
+    bench("get_element_by_id(\"record-n\") with indexes", ids.len(), || {
+        ids.iter().filter(|id| indexed_document.get_element_by_id(&id[1..]).is_some()).count()
+    });
+
+    bench("select_all(\"record > title\") with indexes", RECORDS, || {
+        indexed_document.select_all("record > title").unwrap().count()
+    });
+}
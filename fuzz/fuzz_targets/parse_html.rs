@@ -0,0 +1,10 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rquery;
+
+use rquery::Document;
+
+fuzz_target!(|data: &[u8]| {
+    let document = Document::new_from_html_stream(data).unwrap();
+    let _ = document.to_string();
+});
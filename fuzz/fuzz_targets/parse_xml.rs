@@ -0,0 +1,20 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rquery;
+
+use rquery::{ Document, ParseOptions };
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(document) = Document::new_from_xml_stream(data) {
+        let _ = document.to_string();
+    }
+
+    let options = ParseOptions {
+        lossless: true,
+        ..ParseOptions::default()
+    };
+
+    if let Ok(document) = Document::new_from_xml_stream_with_options(data, &options) {
+        assert_eq!(document.to_string().as_bytes(), data);
+    }
+});
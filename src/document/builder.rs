@@ -0,0 +1,366 @@
+use std::error::Error;
+use std::fmt;
+
+use super::Document;
+use super::interner::Interner;
+use super::super::{ Attribute, ChildNode, ElementData };
+
+const XML_NAMESPACE_URI: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Errors which can be returned when building a document.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildError {
+    /// Returned when the document has no top-level element.
+    NoTopLevelElement,
+    /// Returned when the document has more than one top-level element, which
+    /// couldn’t be written as well-formed XML.
+    MultipleTopLevelElements,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::NoTopLevelElement => formatter.write_str("the document has no top-level element"),
+            BuildError::MultipleTopLevelElements => formatter.write_str("the document has more than one top-level element"),
+        }
+    }
+}
+
+impl Error for BuildError { }
+
+/// A node to be added to a built document.
+#[derive(Clone, Debug)]
+enum NodeBuilder {
+    Element(ElementBuilder),
+    Text(String),
+    Comment(String),
+    CData(String),
+    ProcessingInstruction(String, Option<String>),
+}
+
+/// Builds an element, along with its attributes and child nodes, for a
+/// `DocumentBuilder`.
+///
+/// Qualified names (e.g. `xlink:href`) are resolved against the `xmlns`
+/// attributes of the element and its ancestors, just like when parsing.
+#[derive(Clone, Debug)]
+pub struct ElementBuilder {
+    tag_name: String,
+    attributes: Vec<(String, String)>,
+    child_nodes: Vec<NodeBuilder>,
+}
+
+impl ElementBuilder {
+    /// Starts building an element with the given qualified name.
+    pub fn new(tag_name: &str) -> ElementBuilder {
+        ElementBuilder {
+            tag_name: tag_name.to_string(),
+            attributes: Vec::new(),
+            child_nodes: Vec::new(),
+        }
+    }
+
+    /// Adds an attribute with the given qualified name and value.
+    pub fn attr(mut self, attr_name: &str, value: &str) -> ElementBuilder {
+        self.attributes.push((attr_name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Appends a child element.
+    pub fn child(mut self, child: ElementBuilder) -> ElementBuilder {
+        self.child_nodes.push(NodeBuilder::Element(child));
+        self
+    }
+
+    /// Appends a text node.
+    pub fn text(mut self, text: &str) -> ElementBuilder {
+        self.child_nodes.push(NodeBuilder::Text(text.to_string()));
+        self
+    }
+
+    /// Appends a comment.
+    pub fn comment(mut self, comment: &str) -> ElementBuilder {
+        self.child_nodes.push(NodeBuilder::Comment(comment.to_string()));
+        self
+    }
+
+    /// Appends a CDATA section.
+    pub fn cdata(mut self, data: &str) -> ElementBuilder {
+        self.child_nodes.push(NodeBuilder::CData(data.to_string()));
+        self
+    }
+
+    /// Appends a processing instruction.
+    pub fn processing_instruction(mut self, target: &str, data: Option<&str>) -> ElementBuilder {
+        self.child_nodes.push(NodeBuilder::ProcessingInstruction(target.to_string(), data.map(|data| data.to_string())));
+        self
+    }
+
+    /// Builds a document with the element as its top-level element.
+    pub fn build(self) -> Document {
+        build_document(vec!(NodeBuilder::Element(self)))
+    }
+}
+
+/// Builds a `Document` from its top-level nodes, assigning node indices in
+/// document order just like when parsing.
+#[derive(Clone, Debug, Default)]
+pub struct DocumentBuilder {
+    child_nodes: Vec<NodeBuilder>,
+}
+
+impl DocumentBuilder {
+    /// Starts building an empty document.
+    pub fn new() -> DocumentBuilder {
+        DocumentBuilder::default()
+    }
+
+    /// Appends a top-level element. Documents must have exactly one.
+    pub fn child(mut self, child: ElementBuilder) -> DocumentBuilder {
+        self.child_nodes.push(NodeBuilder::Element(child));
+        self
+    }
+
+    /// Appends a top-level comment.
+    pub fn comment(mut self, comment: &str) -> DocumentBuilder {
+        self.child_nodes.push(NodeBuilder::Comment(comment.to_string()));
+        self
+    }
+
+    /// Appends a top-level processing instruction.
+    pub fn processing_instruction(mut self, target: &str, data: Option<&str>) -> DocumentBuilder {
+        self.child_nodes.push(NodeBuilder::ProcessingInstruction(target.to_string(), data.map(|data| data.to_string())));
+        self
+    }
+
+    /// Builds the document, unless it doesn’t have exactly one top-level
+    /// element.
+    pub fn build(self) -> Result<Document, BuildError> {
+        let top_level_elements = self.child_nodes.iter()
+            .filter(|node| matches!(node, NodeBuilder::Element(_)))
+            .count();
+
+        match top_level_elements {
+            0 => return Err(BuildError::NoTopLevelElement),
+            1 => { },
+            _ => return Err(BuildError::MultipleTopLevelElements),
+        }
+
+        Ok(build_document(self.child_nodes))
+    }
+}
+
+/// Builds a document from its top-level nodes.
+fn build_document(child_nodes: Vec<NodeBuilder>) -> Document {
+    let mut elements = vec!(ElementData {
+        tag_name: "[root]".into(),
+        prefix: None,
+        namespace_uri: None,
+        namespace_declarations: Vec::new(),
+        parent: None,
+        children: Vec::new(),
+        child_nodes: Vec::new(),
+        attributes: Vec::new(),
+        source_spans: None,
+    });
+
+    let mut namespaces = vec!((Some("xml".to_string()), XML_NAMESPACE_URI.to_string()));
+    let mut interner = Interner::default();
+
+    for node in child_nodes {
+        add_node(&mut elements, &mut namespaces, &mut interner, 0, node);
+    }
+
+    Document {
+        elements,
+        source: None,
+        indexes: None,
+    }
+}
+
+/// Adds the node as the last child node of the given element, adding any
+/// elements in document order.
+fn add_node(elements: &mut Vec<ElementData>, namespaces: &mut Vec<(Option<String>, String)>, interner: &mut Interner, parent_index: usize, node: NodeBuilder) {
+    let child_node = match node {
+        NodeBuilder::Element(element) => ChildNode::Element(add_element(elements, namespaces, interner, parent_index, element)),
+
+        NodeBuilder::Text(text) => ChildNode::Text(text),
+
+        NodeBuilder::Comment(comment) => ChildNode::Comment(comment),
+        NodeBuilder::CData(data) => ChildNode::CData(data),
+        NodeBuilder::ProcessingInstruction(target, data) => ChildNode::ProcessingInstruction(target, data),
+    };
+
+    if let ChildNode::Element(node_index) = child_node {
+        elements[parent_index].children.push(node_index);
+    }
+
+    elements[parent_index].child_nodes.push(child_node);
+}
+
+fn add_element(elements: &mut Vec<ElementData>, namespaces: &mut Vec<(Option<String>, String)>, interner: &mut Interner, parent_index: usize, element: ElementBuilder) -> usize {
+    let (namespace_declarations, attributes): (Vec<_>, Vec<_>) = element.attributes.into_iter()
+        .partition(|(attr_name, _)| attr_name == "xmlns" || attr_name.starts_with("xmlns:"));
+
+    let namespace_declarations: Vec<(Option<String>, String)> = namespace_declarations.into_iter()
+        .map(|(attr_name, uri)| (attr_name.split_once(':').map(|(_, prefix)| prefix.to_string()), uri))
+        .collect();
+
+    let number_of_namespaces = namespaces.len();
+    namespaces.extend(namespace_declarations.iter().cloned());
+
+    let (prefix, tag_name) = split_qualified_name(&element.tag_name);
+
+    let attributes = attributes.into_iter()
+        .map(|(attr_name, value)| {
+            let (prefix, name) = split_qualified_name(&attr_name);
+            let namespace_uri = prefix.and_then(|prefix| lookup_namespace_uri(namespaces, Some(prefix)));
+
+            Attribute {
+                name: interner.intern(name),
+                prefix: interner.intern_option(prefix),
+                namespace_uri: interner.intern_option(namespace_uri),
+                value,
+            }
+        })
+        .collect();
+
+    let node_index = elements.len();
+
+    elements.push(ElementData {
+        tag_name: interner.intern(tag_name),
+        prefix: interner.intern_option(prefix),
+        namespace_uri: interner.intern_option(lookup_namespace_uri(namespaces, prefix)),
+        namespace_declarations,
+        parent: Some(parent_index),
+        children: Vec::new(),
+        child_nodes: Vec::new(),
+        attributes,
+        source_spans: None,
+    });
+
+    for node in element.child_nodes {
+        add_node(elements, namespaces, interner, node_index, node);
+    }
+
+    namespaces.truncate(number_of_namespaces);
+
+    node_index
+}
+
+fn split_qualified_name(qualified_name: &str) -> (Option<&str>, &str) {
+    match qualified_name.split_once(':') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, qualified_name),
+    }
+}
+
+/// Returns the namespace URI bound to the prefix (or the default namespace
+/// for `None`) by the innermost declaration in scope.
+fn lookup_namespace_uri<'n>(namespaces: &'n [(Option<String>, String)], prefix: Option<&str>) -> Option<&'n str> {
+    namespaces.iter().rev()
+        .find(|(declared_prefix, _)| declared_prefix.as_deref() == prefix)
+        .map(|(_, uri)| uri.as_str())
+        .filter(|uri| !uri.is_empty())
+}
+
+/// Builds a `Document` from a tree of elements, e.g.
+///
+/// ```
+/// # #[macro_use] extern crate rquery;
+/// # fn main() {
+/// let document = xml! {
+///     sample(type = "simple") {
+///         title { "Simple Sample" }
+///         item(id = "id-1", "data-index" = 1)
+///         empty
+///     }
+/// };
+///
+/// assert_eq!(document.select("item").unwrap().attr("data-index").unwrap(), "1");
+/// # }
+/// ```
+///
+/// Elements are written as their name, followed by their attributes in
+/// parentheses and their child nodes in braces, both of which are optional.
+/// Child nodes are either elements or string literals for text. Names which
+/// aren’t identifiers (e.g. `data-index`) can be written as string literals.
+///
+/// The document must have exactly one top-level element, so anything else
+/// doesn’t compile:
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate rquery;
+/// # fn main() {
+/// let document = xml! { first second };
+/// # }
+/// ```
+#[macro_export]
+macro_rules! xml {
+    ($name:ident $(($($attributes:tt)*))? $({ $($children:tt)* })?) => {
+        $crate::__xml_nodes!(
+            $crate::__xml_attributes!($crate::ElementBuilder::new(stringify!($name)); $($($attributes)*)?);
+            $($($children)*)?
+        ).build()
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __xml_nodes {
+    ($builder:expr;) => {
+        $builder
+    };
+
+    ($builder:expr; $text:literal $($rest:tt)*) => {
+        $crate::__xml_nodes!($builder.text($text); $($rest)*)
+    };
+
+    ($builder:expr; $name:ident ($($attributes:tt)*) { $($children:tt)* } $($rest:tt)*) => {
+        $crate::__xml_nodes!($builder.child($crate::__xml_nodes!(
+            $crate::__xml_attributes!($crate::ElementBuilder::new(stringify!($name)); $($attributes)*);
+            $($children)*
+        )); $($rest)*)
+    };
+
+    ($builder:expr; $name:ident ($($attributes:tt)*) $($rest:tt)*) => {
+        $crate::__xml_nodes!($builder.child(
+            $crate::__xml_attributes!($crate::ElementBuilder::new(stringify!($name)); $($attributes)*)
+        ); $($rest)*)
+    };
+
+    ($builder:expr; $name:ident { $($children:tt)* } $($rest:tt)*) => {
+        $crate::__xml_nodes!($builder.child($crate::__xml_nodes!(
+            $crate::ElementBuilder::new(stringify!($name));
+            $($children)*
+        )); $($rest)*)
+    };
+
+    ($builder:expr; $name:ident $($rest:tt)*) => {
+        $crate::__xml_nodes!($builder.child($crate::ElementBuilder::new(stringify!($name))); $($rest)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __xml_attributes {
+    ($builder:expr;) => {
+        $builder
+    };
+
+    ($builder:expr; $name:ident = $value:expr) => {
+        $builder.attr(stringify!($name), &$value.to_string())
+    };
+
+    ($builder:expr; $name:literal = $value:expr) => {
+        $builder.attr($name, &$value.to_string())
+    };
+
+    ($builder:expr; $name:ident = $value:expr, $($rest:tt)*) => {
+        $crate::__xml_attributes!($builder.attr(stringify!($name), &$value.to_string()); $($rest)*)
+    };
+
+    ($builder:expr; $name:literal = $value:expr, $($rest:tt)*) => {
+        $crate::__xml_attributes!($builder.attr($name, &$value.to_string()); $($rest)*)
+    };
+}
@@ -0,0 +1,417 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{ self, Read };
+use std::str;
+
+use xml::common::TextPosition;
+use xml::reader::ErrorKind;
+
+/// The various errors that can happen when creating a document.
+#[derive(Debug)]
+pub enum DocumentError {
+    /// Returned when the file at the given path could not be opened.
+    UnableToOpenFile(String, io::Error),
+    /// Returned when the stream could not be read.
+    UnableToRead(io::Error),
+    /// Returned when the document is malformed.
+    ParseError(SyntaxError),
+}
+
+/// Describes where and why a document is malformed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxError {
+    /// What is wrong with the document.
+    pub kind: SyntaxErrorKind,
+    /// The line of the failure, counting from 1.
+    pub line: usize,
+    /// The column of the failure in characters, counting from 1.
+    pub column: usize,
+    /// The byte offset of the failure from the start of the document.
+    pub offset: usize,
+    /// The line of the document containing the failure, if it is available.
+    pub excerpt: Option<String>,
+}
+
+/// The different ways in which a document can be malformed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxErrorKind {
+    /// The document is not valid UTF-8.
+    InvalidEncoding,
+    /// The document has no top-level element.
+    EmptyDocument,
+    /// The document has text outside of its top-level element.
+    TextOutsideRoot,
+    /// The document has another element after its top-level element.
+    MultipleRootElements,
+    /// An end tag doesn’t match the open element.
+    MismatchedTag {
+        /// The name of the open element.
+        expected: String,
+        /// The name in the end tag.
+        found: String,
+    },
+    /// The document ends before all of its elements are closed.
+    UnexpectedEndOfDocument,
+    /// Any other error found by the underlying XML parser, with its message.
+    Other(String),
+}
+
+impl DocumentError {
+    /// Converts the error returned by the XML parser, locating it in the
+    /// source read so far. Syntax errors are told apart by the state of the
+    /// parser and the markup found at the error, given the qualified name of
+    /// the innermost open element, whether any element was started and the
+    /// position of the last markup or text read at the top level.
+    pub(crate) fn from_xml_error<R: Read>(error: &::xml::reader::Error, source: &RecordingReader<R>, open_element: Option<&str>, has_elements: bool, top_level: TextPosition) -> DocumentError {
+        let position = ::xml::common::Position::position(error);
+
+        let kind = match *error.kind() {
+            ErrorKind::Io(ref io_error) => return DocumentError::UnableToRead(io::Error::new(io_error.kind(), io_error.to_string())),
+            ErrorKind::Utf8(_) => return source.error_at_invalid_encoding(),
+            ErrorKind::UnexpectedEof => SyntaxErrorKind::UnexpectedEndOfDocument,
+
+            ErrorKind::Syntax(ref message) => {
+                source.syntax_error_kind(position, open_element, has_elements, top_level)
+                    .unwrap_or_else(|| SyntaxErrorKind::Other(message.to_string()))
+            },
+        };
+
+        source.error_at(kind, position)
+    }
+
+    /// Returns the error for the byte offset into the source.
+    pub(crate) fn at_offset(kind: SyntaxErrorKind, source: &[u8], offset: usize) -> DocumentError {
+        syntax_error(kind, source, Location::default(), offset.min(source.len()))
+    }
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DocumentError::UnableToOpenFile(ref path, ref error) => write!(formatter, "unable to open {}: {}", path, error),
+            DocumentError::UnableToRead(ref error) => write!(formatter, "unable to read the document: {}", error),
+            DocumentError::ParseError(ref error) => error.fmt(formatter),
+        }
+    }
+}
+
+impl Error for DocumentError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DocumentError::UnableToOpenFile(_, ref error) | DocumentError::UnableToRead(ref error) => Some(error),
+            DocumentError::ParseError(ref error) => Some(error),
+        }
+    }
+}
+
+/// Writes the error like a compiler diagnostic, e.g.
+///
+/// ```text
+/// 1:10: mismatched end tag, expected </b> but found </a>
+///   |
+/// 1 | <a><b></a></b>
+///   |          ^
+/// ```
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}:{}: {}", self.line, self.column, self.kind)?;
+
+        if let Some(ref excerpt) = self.excerpt {
+            let gutter = " ".repeat(self.line.to_string().len());
+            let indent: String = excerpt.chars()
+                .take(self.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            write!(formatter, "\n{} |\n{} | {}\n{} | {}^", gutter, self.line, excerpt, gutter, indent)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for SyntaxError { }
+
+impl fmt::Display for SyntaxErrorKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyntaxErrorKind::InvalidEncoding => formatter.write_str("the document is not valid UTF-8"),
+            SyntaxErrorKind::EmptyDocument => formatter.write_str("the document has no top-level element"),
+            SyntaxErrorKind::TextOutsideRoot => formatter.write_str("text outside of the top-level element"),
+            SyntaxErrorKind::MultipleRootElements => formatter.write_str("more than one top-level element"),
+            SyntaxErrorKind::MismatchedTag { ref expected, ref found } => write!(formatter, "mismatched end tag, expected </{}> but found </{}>", expected, found),
+            SyntaxErrorKind::UnexpectedEndOfDocument => formatter.write_str("unexpected end of the document"),
+            SyntaxErrorKind::Other(ref message) => formatter.write_str(message),
+        }
+    }
+}
+
+/// A location in the source, with the line and column counting from 0 and
+/// the column counting characters.
+#[derive(Clone, Copy, Debug, Default)]
+struct Location {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    /// Returns the location following the byte found at this location.
+    fn after(self, byte: u8) -> Location {
+        match byte {
+            b'\n' => Location {
+                offset: self.offset + 1,
+                line: self.line + 1,
+                column: 0,
+            },
+
+            byte if is_continuation_byte(byte) => Location {
+                offset: self.offset + 1,
+                ..self
+            },
+
+            _ => Location {
+                offset: self.offset + 1,
+                column: self.column + 1,
+                ..self
+            },
+        }
+    }
+
+    fn advance(self, bytes: &[u8]) -> Location {
+        bytes.iter().fold(self, |location, &byte| location.after(byte))
+    }
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// Returns the error for the byte at the index into the bytes, which start
+/// at the given location. The line containing the error is quoted if it
+/// starts within the bytes.
+fn syntax_error(kind: SyntaxErrorKind, bytes: &[u8], start: Location, index: usize) -> DocumentError {
+    let location = start.advance(&bytes[..index]);
+    let line_start = match bytes[..index].iter().rposition(|&byte| byte == b'\n') {
+        Some(position) => Some(position + 1),
+        None if start.column == 0 => Some(0),
+        None => None,
+    };
+    let line_end = bytes[index..].iter().position(|&byte| byte == b'\n').map_or(bytes.len(), |position| index + position);
+
+    DocumentError::ParseError(SyntaxError {
+        kind,
+        line: location.line + 1,
+        column: location.column + 1,
+        offset: location.offset,
+        excerpt: line_start.map(|line_start| {
+            String::from_utf8_lossy(&bytes[line_start..line_end]).trim_end_matches('\r').to_string()
+        }),
+    })
+}
+
+/// Tells whether the bytes, which start at the top level of the document,
+/// end in text between markup rather than within a tag, comment, processing
+/// instruction or document type declaration.
+fn ends_between_markup(mut bytes: &[u8]) -> bool {
+    while let Some(start) = bytes.iter().position(|&byte| byte == b'<') {
+        match markup_length(&bytes[start..]) {
+            Some(length) => bytes = &bytes[start + length..],
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// Returns the length of the markup the bytes start with, if it ends within
+/// them. Quoted values and the internal subset of a document type
+/// declaration may contain `>`.
+fn markup_length(bytes: &[u8]) -> Option<usize> {
+    let find = |from: usize, end: &[u8]| {
+        bytes.get(from..)?.windows(end.len())
+            .position(|window| window == end)
+            .map(|position| from + position + end.len())
+    };
+
+    if bytes.starts_with(b"<!--") {
+        return find(4, b"-->");
+    } else if bytes.starts_with(b"<![CDATA[") {
+        return find(9, b"]]>");
+    } else if bytes.starts_with(b"<?") {
+        return find(2, b"?>");
+    }
+
+    let mut quote = None;
+    let mut depth = 0usize;
+
+    for (index, &byte) in bytes.iter().enumerate().skip(1) {
+        match (quote, byte) {
+            (Some(open), _) if byte == open => quote = None,
+            (Some(_), _) => { },
+            (None, b'"') | (None, b'\'') => quote = Some(byte),
+            (None, b'[') => depth += 1,
+            (None, b']') => depth = depth.saturating_sub(1),
+            (None, b'>') if depth == 0 => return Some(index + 1),
+            _ => { },
+        }
+    }
+
+    None
+}
+
+/// The number of bytes kept by the `RecordingReader` before it drops the
+/// oldest lines.
+const WINDOW_LENGTH: usize = 64 * 1024;
+
+/// Keeps the most recently read part of the stream, so that errors can be
+/// located and quoted in the source without keeping a copy of the whole
+/// document. The parser reports errors at or just before the last character
+/// read, so they fall within the window.
+pub(crate) struct RecordingReader<R: Read> {
+    inner: R,
+    window: Vec<u8>,
+    window_start: Location,
+    reached_end: bool,
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub(crate) fn new(inner: R) -> RecordingReader<R> {
+        RecordingReader {
+            inner,
+            window: Vec::new(),
+            window_start: Location::default(),
+            reached_end: false,
+        }
+    }
+
+    /// Returns the error for the position reported by the XML parser, which
+    /// counts lines and characters from 0.
+    pub(crate) fn error_at(&self, kind: SyntaxErrorKind, position: TextPosition) -> DocumentError {
+        match self.index_of(position) {
+            Some(index) => syntax_error(kind, &self.window, self.window_start, index),
+
+            // the position was dropped from the window, so only the line and
+            // column are known
+            None => DocumentError::ParseError(SyntaxError {
+                kind,
+                line: position.row as usize + 1,
+                column: position.column as usize + 1,
+                offset: self.window_start.offset,
+                excerpt: None,
+            }),
+        }
+    }
+
+    /// Returns the error for the invalid UTF-8 sequence which stopped the
+    /// parser, at the end of the window.
+    fn error_at_invalid_encoding(&self) -> DocumentError {
+        let line_start = self.window.iter().rposition(|&byte| byte == b'\n').map_or(0, |position| position + 1);
+        let index = match str::from_utf8(&self.window[line_start..]) {
+            Ok(_) => self.window.len(),
+            Err(error) => line_start + error.valid_up_to(),
+        };
+
+        syntax_error(SyntaxErrorKind::InvalidEncoding, &self.window, self.window_start, index)
+    }
+
+    /// Tells the kind of a syntax error apart from the markup at its
+    /// position, returning `None` for the kinds without a variant of their
+    /// own. Outside the top-level element, the markup is read again from the
+    /// last position known to be at the top level, to tell whether the error
+    /// is within markup or in text between it.
+    fn syntax_error_kind(&self, position: TextPosition, open_element: Option<&str>, has_elements: bool, top_level: TextPosition) -> Option<SyntaxErrorKind> {
+        let index = self.index_of(position)?;
+        let between_markup = open_element.is_none() && self.index_of(top_level)
+            .is_some_and(|start| start <= index && ends_between_markup(&self.window[start..index]));
+
+        if self.reached_end && index == self.window.len() {
+            return Some(if has_elements || !between_markup {
+                SyntaxErrorKind::UnexpectedEndOfDocument
+            } else {
+                SyntaxErrorKind::EmptyDocument
+            });
+        }
+
+        let before = &self.window[..index];
+
+        match open_element {
+            None if between_markup => match self.window.get(index) {
+                Some(&byte) if byte != b'<' && !byte.is_ascii_whitespace() => Some(SyntaxErrorKind::TextOutsideRoot),
+                _ => None,
+            },
+
+            None => None,
+
+            Some(expected) => {
+                let tag_start = before.iter().rposition(|&byte| byte == b'<')?;
+                let name = before[tag_start..].strip_prefix(b"</")?
+                    .split(|byte| byte.is_ascii_whitespace())
+                    .next()?;
+
+                if name.is_empty() || name == expected.as_bytes() || name.contains(&b'>') {
+                    return None;
+                }
+
+                Some(SyntaxErrorKind::MismatchedTag {
+                    expected: expected.to_string(),
+                    found: String::from_utf8_lossy(name).into_owned(),
+                })
+            },
+        }
+    }
+
+    /// Returns the index into the window of the character at the position
+    /// reported by the XML parser, if it wasn’t dropped from the window.
+    fn index_of(&self, position: TextPosition) -> Option<usize> {
+        let target = (position.row as usize, position.column as usize);
+
+        if target < (self.window_start.line, self.window_start.column) {
+            return None;
+        }
+
+        let mut location = self.window_start;
+
+        for (index, &byte) in self.window.iter().enumerate() {
+            if (location.line, location.column) >= target && !is_continuation_byte(byte) {
+                return Some(index);
+            }
+
+            location = location.after(byte);
+        }
+
+        Some(self.window.len())
+    }
+
+    /// Drops the oldest lines once the window grows past twice its length,
+    /// keeping at least the last `WINDOW_LENGTH` bytes.
+    fn shrink_window(&mut self) {
+        if self.window.len() < 2 * WINDOW_LENGTH {
+            return;
+        }
+
+        let limit = self.window.len() - WINDOW_LENGTH;
+        let end = match self.window[..limit].iter().rposition(|&byte| byte == b'\n') {
+            Some(position) => position + 1,
+            None => (0..=limit).rev().find(|&index| !is_continuation_byte(self.window[index])).unwrap_or(0),
+        };
+
+        self.window_start = self.window_start.advance(&self.window[..end]);
+        self.window.drain(..end);
+    }
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buffer)?;
+
+        if length == 0 && !buffer.is_empty() {
+            self.reached_end = true;
+        }
+
+        self.window.extend_from_slice(&buffer[..length]);
+        self.shrink_window();
+
+        Ok(length)
+    }
+}
@@ -0,0 +1,1220 @@
+use std::mem;
+use std::sync::Arc;
+
+use super::Document;
+use super::entities::{ CHARACTER_REFERENCES, LEGACY_CHARACTER_REFERENCES };
+use super::interner::Interner;
+use super::super::{ Attribute, ChildNode, ElementData };
+
+/// Elements which never have any content, and so are never left open.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input", "keygen", "link", "meta",
+    "param", "source", "track", "wbr",
+];
+
+/// Elements whose content is text which isn’t parsed for markup or
+/// character references.
+const RAW_TEXT_ELEMENTS: &[&str] = &["iframe", "noembed", "noframes", "script", "style", "xmp"];
+
+/// Elements whose content is text which isn’t parsed for markup but is
+/// parsed for character references.
+const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["textarea", "title"];
+
+/// Elements which belong in the `head` when they appear before the `body`.
+const HEAD_ELEMENTS: &[&str] = &["base", "basefont", "bgsound", "link", "meta", "noframes", "script", "style", "template", "title"];
+
+/// Elements whose start tag closes an open `p` element.
+const CLOSES_PARAGRAPH: &[&str] = &[
+    "address", "article", "aside", "blockquote", "center", "dd", "details", "dialog", "dir", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hgroup", "hr",
+    "li", "listing", "main", "menu", "nav", "ol", "p", "plaintext", "pre", "search", "section", "summary", "table",
+    "ul", "xmp",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Elements which limit how far up the open elements end tags and implied
+/// end tags reach.
+const SCOPE_BOUNDARIES: &[&str] = &["applet", "caption", "html", "marquee", "object", "table", "td", "template", "th"];
+
+/// Elements which stop an end tag for an ordinary element (e.g. `</span>`)
+/// from closing the elements around them.
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote", "body", "br",
+    "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div", "dl", "dt", "embed", "fieldset",
+    "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header",
+    "hgroup", "hr", "html", "iframe", "img", "input", "keygen", "li", "link", "listing", "main", "marquee", "menu",
+    "meta", "nav", "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext", "pre", "script",
+    "search", "section", "select", "source", "style", "summary", "table", "tbody", "td", "template", "textarea",
+    "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp",
+];
+
+/// Elements which are reopened when content follows the misnested tags
+/// which closed them, e.g. the `b` in `<b><p>a</b>b`.
+const FORMATTING_ELEMENTS: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+/// Elements which the formatting elements opened outside them aren’t
+/// reopened inside.
+const FORMATTING_BOUNDARIES: &[&str] = &["applet", "caption", "marquee", "object", "td", "template", "th"];
+
+/// Special elements which, like ordinary elements, reopen the formatting
+/// elements before they are inserted.
+const REOPENS_FORMATTING: &[&str] = &[
+    "applet", "area", "br", "button", "embed", "img", "input", "keygen", "marquee", "object", "select", "wbr", "xmp",
+];
+
+/// Elements which only contain table content. Anything else inserted into
+/// them is moved before the table instead (foster parenting).
+const TABLE_PARTS: &[&str] = &["table", "tbody", "tfoot", "thead", "tr"];
+
+/// Elements which are inserted into a table rather than before it.
+const TABLE_CONTENT: &[&str] = &[
+    "caption", "col", "colgroup", "form", "script", "style", "table", "tbody", "td", "template", "tfoot", "th", "thead",
+    "tr",
+];
+
+/// The SVG tag names which aren’t all lowercase. Tag names are lowercased
+/// when tokenizing, so their case is restored in SVG content.
+const SVG_TAG_NAMES: &[&str] = &[
+    "altGlyph", "altGlyphDef", "altGlyphItem", "animateColor", "animateMotion", "animateTransform", "clipPath",
+    "feBlend", "feColorMatrix", "feComponentTransfer", "feComposite", "feConvolveMatrix", "feDiffuseLighting",
+    "feDisplacementMap", "feDistantLight", "feDropShadow", "feFlood", "feFuncA", "feFuncB", "feFuncG", "feFuncR",
+    "feGaussianBlur", "feImage", "feMerge", "feMergeNode", "feMorphology", "feOffset", "fePointLight",
+    "feSpecularLighting", "feSpotLight", "feTile", "feTurbulence", "foreignObject", "glyphRef", "linearGradient",
+    "radialGradient", "textPath"
+];
+
+/// The SVG attribute names which aren’t all lowercase.
+const SVG_ATTRIBUTE_NAMES: &[&str] = &[
+    "attributeName", "attributeType", "baseFrequency", "baseProfile", "calcMode", "clipPathUnits", "diffuseConstant",
+    "edgeMode", "filterUnits", "glyphRef", "gradientTransform", "gradientUnits", "kernelMatrix", "kernelUnitLength",
+    "keyPoints", "keySplines", "keyTimes", "lengthAdjust", "limitingConeAngle", "markerHeight", "markerUnits",
+    "markerWidth", "maskContentUnits", "maskUnits", "numOctaves", "pathLength", "patternContentUnits",
+    "patternTransform", "patternUnits", "pointsAtX", "pointsAtY", "pointsAtZ", "preserveAlpha", "preserveAspectRatio",
+    "primitiveUnits", "refX", "refY", "repeatCount", "repeatDur", "requiredExtensions", "requiredFeatures",
+    "specularConstant", "specularExponent", "spreadMethod", "startOffset", "stdDeviation", "stitchTiles",
+    "surfaceScale", "systemLanguage", "tableValues", "targetX", "targetY", "textLength", "viewBox", "viewTarget",
+    "xChannelSelector", "yChannelSelector", "zoomAndPan"
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    StartTag(String, Vec<(String, String)>, bool),
+    EndTag(String),
+    Text(String),
+    Comment(String),
+}
+
+/// Splits HTML into tokens, recovering from malformed markup the way browsers
+/// do rather than failing.
+struct Tokenizer<'s> {
+    source: &'s str,
+    position: usize,
+    raw_text_end_tag: Option<String>,
+    is_plaintext: bool,
+}
+
+impl<'s> Tokenizer<'s> {
+    fn new(source: &'s str) -> Tokenizer<'s> {
+        Tokenizer {
+            source,
+            position: 0,
+            raw_text_end_tag: None,
+            is_plaintext: false,
+        }
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.source[self.position..]
+    }
+
+    /// Returns everything up to the terminator, moving past the terminator or
+    /// to the end of the source if there’s none.
+    fn take_until(&mut self, terminator: &str) -> &'s str {
+        let rest = self.rest();
+
+        match rest.find(terminator) {
+            Some(offset) => {
+                self.position += offset + terminator.len();
+                &rest[..offset]
+            },
+            None => {
+                self.position = self.source.len();
+                rest
+            },
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> &'s str {
+        let rest = self.rest();
+        let length = rest.find(|c: char| !predicate(c)).unwrap_or(rest.len());
+
+        self.position += length;
+        &rest[..length]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(is_whitespace);
+    }
+
+    fn raw_text(&mut self, end_tag: &str) -> Token {
+        let rest = self.rest();
+        let mut search_from = 0;
+
+        let length = loop {
+            match rest[search_from..].find("</") {
+                Some(offset) => {
+                    let start = search_from + offset;
+                    let name_end = start + 2 + end_tag.len();
+
+                    let is_end_tag = rest.get(start + 2..name_end).is_some_and(|name| name.eq_ignore_ascii_case(end_tag)) &&
+                        rest[name_end..].chars().next().is_none_or(|c| is_whitespace(c) || c == '/' || c == '>');
+
+                    if is_end_tag {
+                        break start;
+                    }
+
+                    search_from = start + 2;
+                },
+                None => break rest.len(),
+            }
+        };
+
+        self.position += length;
+
+        if ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&end_tag) {
+            Token::Text(decode_character_references(&rest[..length], false))
+        } else {
+            Token::Text(rest[..length].to_string())
+        }
+    }
+
+    fn start_tag(&mut self) -> Token {
+        let name = self.take_while(|c| !is_whitespace(c) && c != '/' && c != '>').to_ascii_lowercase();
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let mut is_self_closing = false;
+
+        loop {
+            self.skip_whitespace();
+
+            let rest = self.rest();
+
+            if rest.is_empty() {
+                break;
+            } else if rest.starts_with('>') {
+                self.position += 1;
+                break;
+            } else if rest.starts_with("/>") {
+                self.position += 2;
+                is_self_closing = true;
+                break;
+            } else if rest.starts_with('/') {
+                self.position += 1;
+                continue;
+            }
+
+            let first_length = rest.chars().next().unwrap().len_utf8();
+            self.position += first_length;
+            let attr_name = format!("{}{}", &rest[..first_length], self.take_while(|c| !is_whitespace(c) && c != '/' && c != '>' && c != '='));
+            self.skip_whitespace();
+
+            let value = if self.rest().starts_with('=') {
+                self.position += 1;
+                self.skip_whitespace();
+
+                let raw_value = match self.rest().chars().next() {
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        self.position += 1;
+                        self.take_until(if quote == '"' { "\"" } else { "'" })
+                    },
+                    _ => self.take_while(|c| !is_whitespace(c) && c != '>'),
+                };
+
+                decode_character_references(raw_value, true)
+            } else {
+                String::new()
+            };
+
+            let attr_name = attr_name.to_ascii_lowercase();
+
+            if !attributes.iter().any(|(existing_name, _)| *existing_name == attr_name) {
+                attributes.push((attr_name, value));
+            }
+        }
+
+        if RAW_TEXT_ELEMENTS.contains(&name.as_str()) || ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            self.raw_text_end_tag = Some(name.clone());
+        } else if name == "plaintext" {
+            self.is_plaintext = true;
+        }
+
+        Token::StartTag(name, attributes, is_self_closing)
+    }
+}
+
+impl<'s> Iterator for Tokenizer<'s> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        // Nothing ends a `plaintext` element, so the rest is all text.
+        if self.is_plaintext {
+            let rest = self.rest();
+            self.position = self.source.len();
+
+            return if rest.is_empty() { None } else { Some(Token::Text(rest.to_string())) };
+        }
+
+        if let Some(end_tag) = self.raw_text_end_tag.take() {
+            if !self.rest().is_empty() {
+                return Some(self.raw_text(&end_tag));
+            }
+        }
+
+        let rest = self.rest();
+        let mut chars = rest.chars();
+
+        match (chars.next(), chars.next()) {
+            (None, _) =>
+                None,
+
+            (Some('<'), Some(c)) if c.is_ascii_alphabetic() => {
+                self.position += 1;
+                Some(self.start_tag())
+            },
+
+            (Some('<'), Some('/')) => {
+                self.position += 2;
+
+                match self.rest().chars().next() {
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        let name = self.take_while(|c| !is_whitespace(c) && c != '/' && c != '>').to_ascii_lowercase();
+                        self.take_until(">");
+                        Some(Token::EndTag(name))
+                    },
+                    Some('>') => {
+                        self.position += 1;
+                        self.next()
+                    },
+                    _ => Some(Token::Comment(self.take_until(">").to_string())),
+                }
+            },
+
+            (Some('<'), Some('!')) if rest.starts_with("<!--") => {
+                self.position += 4;
+                Some(Token::Comment(self.take_until("-->").to_string()))
+            },
+
+            (Some('<'), Some('!')) if rest.get(..9).is_some_and(|start| start.eq_ignore_ascii_case("<!doctype")) => {
+                self.take_until(">");
+                self.next()
+            },
+
+            (Some('<'), Some('!')) | (Some('<'), Some('?')) => {
+                self.position += 2;
+                Some(Token::Comment(self.take_until(">").to_string()))
+            },
+
+            (Some('<'), _) => {
+                self.position += 1;
+                let text = self.take_while(|c| c != '<');
+                Some(Token::Text(format!("<{}", decode_character_references(text, false))))
+            },
+
+            _ => {
+                let text = self.take_while(|c| c != '<');
+                Some(Token::Text(decode_character_references(text, false)))
+            },
+        }
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\x0C' | '\r')
+}
+
+/// Replaces the character references in the text. References without their
+/// semicolon are only recognised for the legacy names, and not in attribute
+/// values where they are followed by something that could continue the name.
+fn decode_character_references(text: &str, is_attribute_value: bool) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(offset) = rest.find('&') {
+        decoded.push_str(&rest[..offset]);
+        rest = &rest[offset + 1..];
+
+        match decode_character_reference(rest, is_attribute_value, &mut decoded) {
+            Some(length) => rest = &rest[length..],
+            None => decoded.push('&'),
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decodes the character reference at the start of the text (after the `&`)
+/// into the decoded text, returning the length of the reference.
+fn decode_character_reference(text: &str, is_attribute_value: bool, decoded: &mut String) -> Option<usize> {
+    if let Some(number) = text.strip_prefix('#') {
+        let (digits, radix, prefix_length) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex.find(|c: char| !c.is_ascii_hexdigit()).map_or(hex, |end| &hex[..end]), 16, 2),
+            None => (number.find(|c: char| !c.is_ascii_digit()).map_or(number, |end| &number[..end]), 10, 1),
+        };
+
+        if digits.is_empty() {
+            return None;
+        }
+
+        let c = u32::from_str_radix(digits, radix).ok()
+            .filter(|&code_point| code_point != 0)
+            .and_then(::std::char::from_u32)
+            .unwrap_or('\u{FFFD}');
+        let length = prefix_length + digits.len();
+
+        decoded.push(c);
+        return Some(if text[length..].starts_with(';') { length + 1 } else { length });
+    }
+
+    let name_length = text.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(text.len());
+    let name = &text[..name_length];
+
+    if text[name_length..].starts_with(';') {
+        if let Some(characters) = lookup_character_reference(name) {
+            decoded.push_str(characters);
+            return Some(name_length + 1);
+        }
+    }
+
+    // Legacy references may be missing their semicolon, in which case the
+    // longest known name at the start is used.
+    let legacy_name = (2..name_length + 1).rev()
+        .map(|length| &name[..length])
+        .find(|name| LEGACY_CHARACTER_REFERENCES.binary_search(name).is_ok())
+        .filter(|legacy_name| !is_attribute_value || legacy_name.len() == name_length && !text[name_length..].starts_with('='))?;
+
+    decoded.push_str(lookup_character_reference(legacy_name)?);
+    Some(legacy_name.len())
+}
+
+fn lookup_character_reference(name: &str) -> Option<&'static str> {
+    CHARACTER_REFERENCES.binary_search_by(|&(reference_name, _)| reference_name.cmp(name))
+        .ok()
+        .map(|position| CHARACTER_REFERENCES[position].1)
+}
+
+/// Builds the element tree from the tokens, following a simplified version of
+/// the HTML tree construction rules. Misnested formatting elements and
+/// content which doesn’t belong in a table are handled as in a browser, but
+/// templates, frames and the rarer table parts aren’t given special care.
+struct TreeBuilder {
+    elements: Vec<ElementData>,
+    interner: Interner,
+    open_elements: Vec<usize>,
+    /// The formatting elements (e.g. `b`) which are reopened when content
+    /// follows the misnested tags that closed them. `None` marks a table
+    /// cell, caption or object, which the ones before it don’t reach into.
+    active_formatting_elements: Vec<Option<usize>>,
+    html: Option<usize>,
+    head: Option<usize>,
+    body: Option<usize>,
+    skips_next_newline: bool,
+    /// Set while processing a token whose content doesn’t belong in the
+    /// table it appears in, which moves the content before the table.
+    is_foster_parenting: bool,
+    /// Set once elements are moved or inserted out of document order.
+    needs_renumbering: bool,
+}
+
+/// Where a node is inserted: before one of the child elements of the parent,
+/// or after all of its child nodes.
+#[derive(Clone, Copy)]
+struct InsertionLocation {
+    parent_index: usize,
+    before: Option<usize>,
+}
+
+impl InsertionLocation {
+    fn end_of(parent_index: usize) -> InsertionLocation {
+        InsertionLocation {
+            parent_index,
+            before: None,
+        }
+    }
+}
+
+impl TreeBuilder {
+    fn new() -> TreeBuilder {
+        TreeBuilder {
+            elements: vec!(new_element_data("[root]".into(), None, Vec::new())),
+            interner: Interner::default(),
+            open_elements: Vec::new(),
+            active_formatting_elements: Vec::new(),
+            html: None,
+            head: None,
+            body: None,
+            skips_next_newline: false,
+            is_foster_parenting: false,
+            needs_renumbering: false,
+        }
+    }
+
+    fn build(self) -> Document {
+        let mut document = Document {
+            elements: self.elements,
+            source: None,
+            indexes: None,
+        };
+
+        if self.needs_renumbering {
+            document.renumber();
+        }
+
+        super::shrink_to_fit(&mut document.elements);
+        document
+    }
+
+    fn current_index(&self) -> usize {
+        self.open_elements.last().cloned().unwrap_or(0)
+    }
+
+    fn current_tag_name(&self) -> &str {
+        &self.elements[self.current_index()].tag_name
+    }
+
+    fn process(&mut self, token: Token) {
+        let skips_newline = self.skips_next_newline;
+        self.skips_next_newline = false;
+
+        match token {
+            Token::StartTag(name, attributes, is_self_closing) => self.start_tag(name, attributes, is_self_closing),
+            Token::EndTag(name) => self.end_tag(&name),
+
+            Token::Text(text) => {
+                let text = if skips_newline { text.strip_prefix('\n').unwrap_or(&text) } else { &text };
+                self.text(text);
+            },
+
+            Token::Comment(comment) => {
+                let parent_index = self.current_index();
+                self.append_child_node(parent_index, ChildNode::Comment(comment));
+            },
+        }
+
+        self.is_foster_parenting = false;
+    }
+
+    fn start_tag(&mut self, name: String, attributes: Vec<(String, String)>, is_self_closing: bool) {
+        match name.as_str() {
+            "html" => {
+                self.ensure_html(attributes);
+                return;
+            },
+            "head" => {
+                if self.head.is_none() && self.body.is_none() {
+                    self.ensure_head(attributes);
+                }
+                return;
+            },
+            "body" => {
+                if self.body.is_none() {
+                    self.ensure_body(attributes);
+                }
+                return;
+            },
+            _ => { },
+        }
+
+        if self.body.is_none() && HEAD_ELEMENTS.contains(&name.as_str()) {
+            self.ensure_head(Vec::new());
+        } else if self.body.is_none() {
+            self.ensure_body(Vec::new());
+        }
+
+        let is_in_table = TABLE_PARTS.contains(&self.current_tag_name());
+        let is_table_content = TABLE_CONTENT.contains(&name.as_str()) || name == "input" && attributes.iter()
+            .any(|(attr_name, value)| attr_name == "type" && value.eq_ignore_ascii_case("hidden"));
+
+        self.is_foster_parenting = is_in_table && !is_table_content;
+        self.close_implied_elements(&name);
+
+        let open_foreign_root = self.foreign_root();
+        let reopens_formatting = !SPECIAL_ELEMENTS.contains(&name.as_str()) || REOPENS_FORMATTING.contains(&name.as_str());
+
+        if reopens_formatting && open_foreign_root.is_none() && !(is_in_table && is_table_content) {
+            self.reconstruct_active_formatting_elements();
+        }
+
+        let foreign_root = if name == "svg" || name == "math" {
+            Some(self.interner.intern(&name))
+        } else {
+            open_foreign_root
+        };
+
+        // A form in a table is closed straight away, as it can’t wrap parts
+        // of the table.
+        let is_void = VOID_ELEMENTS.contains(&name.as_str()) || (foreign_root.is_some() && is_self_closing) ||
+            (is_in_table && name == "form");
+
+        self.skips_next_newline = ["pre", "listing", "textarea"].contains(&name.as_str());
+
+        let (name, attributes) = match foreign_root.as_deref() {
+            Some("svg") => (restore_case(name, SVG_TAG_NAMES), attributes.into_iter()
+                .map(|(attr_name, value)| (restore_case(attr_name, SVG_ATTRIBUTE_NAMES), value))
+                .collect()),
+            Some("math") => (name, attributes.into_iter()
+                .map(|(attr_name, value)| (restore_case(attr_name, &["definitionURL"]), value))
+                .collect()),
+            _ => (name, attributes),
+        };
+
+        let location = self.insertion_location(self.current_index());
+        let node_index = self.insert_element(location, &name, attributes);
+
+        if !is_void {
+            self.open_elements.push(node_index);
+        }
+
+        if foreign_root.is_none() && FORMATTING_BOUNDARIES.contains(&name.as_str()) {
+            self.active_formatting_elements.push(None);
+        } else if foreign_root.is_none() && FORMATTING_ELEMENTS.contains(&name.as_str()) {
+            self.push_active_formatting_element(node_index);
+        }
+    }
+
+    /// Closes the elements which the start tag implies the end of, and opens
+    /// the table sections and rows which it implies.
+    fn close_implied_elements(&mut self, name: &str) {
+        if CLOSES_PARAGRAPH.contains(&name) && self.has_in_scope("p", &["button"]) {
+            self.close_in_scope(&["p"], &["button"]);
+        }
+
+        match name {
+            "li" => self.close_in_scope(&["li"], &["ol", "ul"]),
+            "dd" | "dt" => self.close_in_scope(&["dd", "dt"], &["dl"]),
+            "button" | "form" => self.close_in_scope(&[name], &[]),
+            "table" if TABLE_PARTS.contains(&self.current_tag_name()) => self.close_in_table_scope(&["table"]),
+
+            // Links can’t be nested, so an open one is closed even when the
+            // tags are misnested.
+            "a" => {
+                if let Some(a) = self.active_formatting_element("a") {
+                    self.close_formatting_element("a");
+                    self.active_formatting_elements.retain(|&entry| entry != Some(a));
+                    self.open_elements.retain(|&node_index| node_index != a);
+                }
+            },
+
+            "nobr" => {
+                self.reconstruct_active_formatting_elements();
+
+                if self.has_in_scope("nobr", &[]) {
+                    self.close_formatting_element("nobr");
+                }
+            },
+
+            "caption" | "col" | "colgroup" => self.close_to_table_part(),
+
+            "tbody" | "tfoot" | "thead" => {
+                self.close_to_table_part();
+                self.close_in_table_scope(&["td", "th"]);
+                self.close_in_table_scope(&["tr"]);
+                self.close_in_table_scope(&["tbody", "tfoot", "thead"]);
+            },
+
+            "option" | "optgroup" => {
+                if self.current_tag_name() == "option" {
+                    self.open_elements.pop();
+                }
+                if name == "optgroup" && self.current_tag_name() == "optgroup" {
+                    self.open_elements.pop();
+                }
+            },
+
+            "tr" => {
+                self.close_to_table_part();
+                self.close_in_table_scope(&["td", "th"]);
+                self.close_in_table_scope(&["tr"]);
+                self.open_implied_element("table", "tbody");
+            },
+
+            "td" | "th" => {
+                self.close_to_table_part();
+                self.close_in_table_scope(&["td", "th"]);
+                self.open_implied_element("table", "tbody");
+                self.open_implied_element("tbody", "tr");
+                self.open_implied_element("thead", "tr");
+                self.open_implied_element("tfoot", "tr");
+            },
+
+            _ if HEADINGS.contains(&name) && HEADINGS.contains(&self.current_tag_name()) => {
+                self.open_elements.pop();
+            },
+
+            _ => { },
+        }
+    }
+
+    fn open_implied_element(&mut self, parent_tag_name: &str, tag_name: &str) {
+        if self.current_tag_name() == parent_tag_name {
+            let location = InsertionLocation::end_of(self.current_index());
+            let node_index = self.insert_element(location, tag_name, Vec::new());
+            self.open_elements.push(node_index);
+        }
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        match name {
+            "html" | "body" => { },
+
+            "head" => {
+                if self.current_tag_name() == "head" {
+                    self.open_elements.pop();
+                }
+            },
+
+            "p" if !self.has_in_scope("p", &["button"]) => {
+                self.start_tag("p".to_string(), Vec::new(), false);
+                self.close_in_scope(&["p"], &["button"]);
+            },
+
+            "br" => self.start_tag("br".to_string(), Vec::new(), false),
+            "table" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" => self.close_in_table_scope(&[name]),
+            "p" => self.close_in_scope(&["p"], &["button"]),
+            "li" => self.close_in_scope(&["li"], &["ol", "ul"]),
+            _ if SPECIAL_ELEMENTS.contains(&name) => self.close_in_scope(&[name], &[]),
+
+            _ if FORMATTING_ELEMENTS.contains(&name) => {
+                self.is_foster_parenting = TABLE_PARTS.contains(&self.current_tag_name());
+
+                if !self.close_formatting_element(name) {
+                    self.close_ordinary_element(name);
+                }
+            },
+
+            _ => self.close_ordinary_element(name),
+        }
+    }
+
+    /// Closes the innermost open element with the tag name, unless a special
+    /// element is open inside it.
+    fn close_ordinary_element(&mut self, name: &str) {
+        for position in (0..self.open_elements.len()).rev() {
+            let tag_name = self.elements[self.open_elements[position]].tag_name.as_ref();
+
+            // The case of names in SVG content is restored after
+            // tokenizing, which leaves it out of end tags.
+            if tag_name.eq_ignore_ascii_case(name) {
+                self.close_from(position);
+                return;
+            } else if SPECIAL_ELEMENTS.contains(&tag_name) {
+                return;
+            }
+        }
+    }
+
+    /// Closes the innermost active formatting element with the tag name,
+    /// following the adoption agency algorithm: the blocks opened inside it
+    /// are moved out of it, with their content wrapped in copies of it, so
+    /// that for example `<b>1<p>2</b>3</p>` becomes `<b>1</b><p><b>2</b>3</p>`.
+    /// Returns false if there’s no such formatting element, in which case the
+    /// end tag is handled like any other.
+    fn close_formatting_element(&mut self, tag_name: &str) -> bool {
+        let current_index = self.current_index();
+
+        if self.current_tag_name() == tag_name && !self.active_formatting_elements.contains(&Some(current_index)) {
+            self.open_elements.pop();
+            return true;
+        }
+
+        // Each round moves one block out of the formatting element. Their
+        // number is limited, as browsers do, to bound the cost of badly
+        // misnested markup.
+        for _ in 0..8 {
+            let formatting_element = match self.active_formatting_element(tag_name) {
+                Some(formatting_element) => formatting_element,
+                None => return false,
+            };
+
+            let formatting_element_position = match self.open_elements.iter().position(|&node_index| node_index == formatting_element) {
+                Some(position) => position,
+                None => {
+                    self.active_formatting_elements.retain(|&entry| entry != Some(formatting_element));
+                    return true;
+                },
+            };
+
+            if !self.has_element_in_scope(formatting_element) {
+                return true;
+            }
+
+            let furthest_block_position = (formatting_element_position + 1..self.open_elements.len())
+                .find(|&position| SPECIAL_ELEMENTS.contains(&self.elements[self.open_elements[position]].tag_name.as_ref()));
+
+            let furthest_block_position = match furthest_block_position {
+                Some(position) => position,
+                None => {
+                    self.close_from(formatting_element_position);
+                    self.active_formatting_elements.retain(|&entry| entry != Some(formatting_element));
+                    return true;
+                },
+            };
+
+            let furthest_block = self.open_elements[furthest_block_position];
+            let common_ancestor = self.open_elements[formatting_element_position - 1];
+
+            // The copy of the formatting element made last goes after this
+            // element in the active formatting elements, or in place of the
+            // formatting element if there’s none.
+            let mut bookmark = None;
+            let mut position = furthest_block_position;
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+
+            // Copies the formatting elements between the formatting element
+            // and the block, dropping the elements which aren’t formatting
+            // elements.
+            loop {
+                inner_loop_counter += 1;
+                position -= 1;
+
+                let node = self.open_elements[position];
+
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut active_position = self.active_formatting_elements.iter().position(|&entry| entry == Some(node));
+
+                if inner_loop_counter > 3 {
+                    if let Some(active_position) = active_position.take() {
+                        self.active_formatting_elements.remove(active_position);
+                    }
+                }
+
+                let active_position = match active_position {
+                    Some(active_position) => active_position,
+                    None => {
+                        self.open_elements.remove(position);
+                        continue;
+                    },
+                };
+
+                let node = self.copy_element(node);
+                self.active_formatting_elements[active_position] = Some(node);
+                self.open_elements[position] = node;
+
+                if last_node == furthest_block {
+                    bookmark = Some(node);
+                }
+
+                self.move_element(last_node, InsertionLocation::end_of(node));
+                last_node = node;
+            }
+
+            let location = self.insertion_location(common_ancestor);
+            self.move_element(last_node, location);
+
+            // The content of the block is wrapped in a copy of the formatting
+            // element.
+            let new_element = self.copy_element(formatting_element);
+            let child_nodes = mem::take(&mut self.elements[furthest_block].child_nodes);
+            let children = mem::take(&mut self.elements[furthest_block].children);
+
+            for &child_index in &children {
+                self.elements[child_index].parent = Some(new_element);
+            }
+
+            self.elements[new_element].child_nodes = child_nodes;
+            self.elements[new_element].children = children;
+            self.attach(InsertionLocation::end_of(furthest_block), new_element);
+
+            match bookmark {
+                Some(bookmark) => {
+                    self.active_formatting_elements.retain(|&entry| entry != Some(formatting_element));
+
+                    let active_position = self.active_formatting_elements.iter().position(|&entry| entry == Some(bookmark)).unwrap();
+                    self.active_formatting_elements.insert(active_position + 1, Some(new_element));
+                },
+                None => {
+                    let active_position = self.active_formatting_elements.iter().position(|&entry| entry == Some(formatting_element)).unwrap();
+                    self.active_formatting_elements[active_position] = Some(new_element);
+                },
+            }
+
+            self.open_elements.retain(|&node_index| node_index != formatting_element);
+
+            let position = self.open_elements.iter().position(|&node_index| node_index == furthest_block).unwrap();
+            self.open_elements.insert(position + 1, new_element);
+        }
+
+        true
+    }
+
+    fn text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let is_whitespace = text.chars().all(is_whitespace);
+
+        if self.body.is_none() {
+            let is_in_head = self.head.is_some_and(|head| self.open_elements.contains(&head));
+
+            if is_in_head && (is_whitespace || self.current_tag_name() != "head") {
+                self.insert_text(InsertionLocation::end_of(self.current_index()), text);
+                return;
+            } else if is_whitespace {
+                return;
+            }
+
+            self.ensure_body(Vec::new());
+        }
+
+        let current_tag_name = self.current_tag_name();
+        let is_in_table = TABLE_PARTS.contains(&current_tag_name);
+        let is_raw_text = RAW_TEXT_ELEMENTS.contains(&current_tag_name) || ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&current_tag_name);
+
+        // Only whitespace belongs in a table, so other text is moved before
+        // it, along with the formatting elements reopened for it.
+        if is_in_table && is_whitespace {
+            self.insert_text(InsertionLocation::end_of(self.current_index()), text);
+            return;
+        }
+
+        self.is_foster_parenting = is_in_table;
+
+        if !is_raw_text && self.foreign_root().is_none() {
+            self.reconstruct_active_formatting_elements();
+        }
+
+        let location = self.insertion_location(self.current_index());
+        self.insert_text(location, text);
+    }
+
+    /// Returns true if an element with the given tag name is open, without
+    /// any of the scope boundaries (or the extra ones) in between.
+    fn has_in_scope(&self, tag_name: &str, extra_boundaries: &[&str]) -> bool {
+        for &node_index in self.open_elements.iter().rev() {
+            let open_tag_name = self.elements[node_index].tag_name.as_ref();
+
+            if open_tag_name == tag_name {
+                return true;
+            } else if SCOPE_BOUNDARIES.contains(&open_tag_name) || extra_boundaries.contains(&open_tag_name) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Returns true if the element is open, without any of the scope
+    /// boundaries in between.
+    fn has_element_in_scope(&self, node_index: usize) -> bool {
+        for &open_index in self.open_elements.iter().rev() {
+            if open_index == node_index {
+                return true;
+            } else if SCOPE_BOUNDARIES.contains(&self.elements[open_index].tag_name.as_ref()) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Closes the innermost open element with one of the given tag names,
+    /// along with the elements inside it, if it is in scope.
+    fn close_in_scope(&mut self, tag_names: &[&str], extra_boundaries: &[&str]) {
+        for position in (0..self.open_elements.len()).rev() {
+            let open_tag_name = self.elements[self.open_elements[position]].tag_name.as_ref();
+
+            if tag_names.contains(&open_tag_name) {
+                self.close_from(position);
+                return;
+            } else if SCOPE_BOUNDARIES.contains(&open_tag_name) || extra_boundaries.contains(&open_tag_name) {
+                return;
+            }
+        }
+    }
+
+    /// Closes the innermost open table part with one of the given tag names,
+    /// along with the elements inside it, if it belongs to the innermost
+    /// table.
+    fn close_in_table_scope(&mut self, tag_names: &[&str]) {
+        for position in (0..self.open_elements.len()).rev() {
+            let open_tag_name = self.elements[self.open_elements[position]].tag_name.as_ref();
+
+            if tag_names.contains(&open_tag_name) {
+                self.close_from(position);
+                return;
+            } else if ["html", "table", "template"].contains(&open_tag_name) {
+                return;
+            }
+        }
+    }
+
+    /// Closes the elements open inside the innermost table part, such as
+    /// formatting elements moved before the table, unless they are inside a
+    /// table cell or caption.
+    fn close_to_table_part(&mut self) {
+        for position in (0..self.open_elements.len()).rev() {
+            let open_tag_name = self.elements[self.open_elements[position]].tag_name.as_ref();
+
+            if TABLE_PARTS.contains(&open_tag_name) {
+                self.close_from(position + 1);
+                return;
+            } else if ["caption", "td", "template", "th"].contains(&open_tag_name) {
+                return;
+            }
+        }
+    }
+
+    /// Closes the open element at the position along with the elements
+    /// inside it. Closing a table cell, caption or object also forgets the
+    /// formatting elements opened inside it.
+    fn close_from(&mut self, position: usize) {
+        for node_index in self.open_elements.split_off(position) {
+            if FORMATTING_BOUNDARIES.contains(&self.elements[node_index].tag_name.as_ref()) {
+                while let Some(Some(_)) = self.active_formatting_elements.pop() { }
+            }
+        }
+    }
+
+    /// Returns the tag name of the innermost open `svg` or `math` element.
+    fn foreign_root(&self) -> Option<Arc<str>> {
+        self.open_elements.iter().rev()
+            .map(|&node_index| &self.elements[node_index].tag_name)
+            .find(|tag_name| matches!(&***tag_name, "svg" | "math"))
+            .cloned()
+    }
+
+    /// Returns the innermost active formatting element with the tag name,
+    /// unless it comes before a marker.
+    fn active_formatting_element(&self, tag_name: &str) -> Option<usize> {
+        self.active_formatting_elements.iter().rev()
+            .map_while(|&entry| entry)
+            .find(|&node_index| &*self.elements[node_index].tag_name == tag_name)
+    }
+
+    /// Adds the formatting element to the active ones, keeping no more than
+    /// three with the same tag name and attributes after the last marker.
+    fn push_active_formatting_element(&mut self, node_index: usize) {
+        let data = &self.elements[node_index];
+
+        let earliest_of_three = self.active_formatting_elements.iter().enumerate().rev()
+            .map_while(|(position, &entry)| entry.map(|entry| (position, &self.elements[entry])))
+            .filter(|&(_, other)| {
+                other.tag_name == data.tag_name && other.attributes.len() == data.attributes.len() &&
+                    other.attributes.iter().all(|attribute| data.attributes.contains(attribute))
+            })
+            .skip(2)
+            .last();
+
+        if let Some((position, _)) = earliest_of_three {
+            self.active_formatting_elements.remove(position);
+        }
+
+        self.active_formatting_elements.push(Some(node_index));
+    }
+
+    /// Reopens the formatting elements which were closed by misnested tags,
+    /// by inserting copies of them for the content which follows.
+    fn reconstruct_active_formatting_elements(&mut self) {
+        let first_position = self.active_formatting_elements.iter()
+            .rposition(|entry| entry.is_none_or(|node_index| self.open_elements.contains(&node_index)))
+            .map_or(0, |position| position + 1);
+
+        for position in first_position..self.active_formatting_elements.len() {
+            let node_index = self.active_formatting_elements[position].unwrap();
+            let node_index = self.copy_element(node_index);
+            let location = self.insertion_location(self.current_index());
+
+            self.attach(location, node_index);
+            self.open_elements.push(node_index);
+            self.active_formatting_elements[position] = Some(node_index);
+        }
+    }
+
+    /// Returns where to insert a node into the target element. While foster
+    /// parenting, nodes which would go directly into a table are inserted
+    /// before the table instead.
+    fn insertion_location(&self, target: usize) -> InsertionLocation {
+        if self.is_foster_parenting && TABLE_PARTS.contains(&self.elements[target].tag_name.as_ref()) {
+            let table = self.open_elements.iter().rev().cloned()
+                .find(|&node_index| &*self.elements[node_index].tag_name == "table");
+
+            if let Some(table) = table {
+                return InsertionLocation {
+                    parent_index: self.elements[table].parent.unwrap(),
+                    before: Some(table),
+                };
+            }
+        }
+
+        InsertionLocation::end_of(target)
+    }
+
+    fn ensure_html(&mut self, attributes: Vec<(String, String)>) -> usize {
+        if let Some(html) = self.html {
+            return html;
+        }
+
+        let html = self.insert_element(InsertionLocation::end_of(0), "html", attributes);
+        self.html = Some(html);
+        self.open_elements = vec!(html);
+        html
+    }
+
+    fn ensure_head(&mut self, attributes: Vec<(String, String)>) {
+        let html = self.ensure_html(Vec::new());
+
+        let head = match self.head {
+            Some(head) => head,
+            None => self.insert_element(InsertionLocation::end_of(html), "head", attributes),
+        };
+
+        self.head = Some(head);
+
+        if !self.open_elements.contains(&head) {
+            self.open_elements.push(head);
+        }
+    }
+
+    fn ensure_body(&mut self, attributes: Vec<(String, String)>) {
+        if self.head.is_none() {
+            self.ensure_head(Vec::new());
+        }
+
+        let html = self.ensure_html(Vec::new());
+        let body = self.insert_element(InsertionLocation::end_of(html), "body", attributes);
+
+        self.body = Some(body);
+        self.open_elements = vec!(html, body);
+    }
+
+    fn insert_element(&mut self, location: InsertionLocation, tag_name: &str, attributes: Vec<(String, String)>) -> usize {
+        let node_index = self.elements.len();
+        let attributes = attributes.into_iter()
+            .map(|(name, value)| Attribute {
+                name: self.interner.intern(&name),
+                prefix: None,
+                namespace_uri: None,
+                value,
+            })
+            .collect();
+
+        let tag_name = self.interner.intern(tag_name);
+        self.elements.push(new_element_data(tag_name, None, attributes));
+        self.attach(location, node_index);
+
+        node_index
+    }
+
+    /// Creates an element with the tag name and attributes of the element,
+    /// without inserting it.
+    fn copy_element(&mut self, node_index: usize) -> usize {
+        let data = &self.elements[node_index];
+        let copy = new_element_data(data.tag_name.clone(), None, data.attributes.clone());
+
+        self.elements.push(copy);
+        self.elements.len() - 1
+    }
+
+    fn attach(&mut self, location: InsertionLocation, node_index: usize) {
+        self.elements[node_index].parent = Some(location.parent_index);
+
+        let parent = &mut self.elements[location.parent_index];
+
+        match location.before {
+            Some(before) => {
+                let position = child_node_position(parent, before);
+                parent.child_nodes.insert(position, ChildNode::Element(node_index));
+
+                let position = parent.children.iter().position(|&child_index| child_index == before).unwrap();
+                parent.children.insert(position, node_index);
+
+                self.needs_renumbering = true;
+            },
+            None => {
+                parent.child_nodes.push(ChildNode::Element(node_index));
+                parent.children.push(node_index);
+            },
+        }
+    }
+
+    fn move_element(&mut self, node_index: usize, location: InsertionLocation) {
+        if let Some(parent_index) = self.elements[node_index].parent {
+            let parent = &mut self.elements[parent_index];
+
+            parent.child_nodes.retain(|child_node| !matches!(*child_node, ChildNode::Element(child_index) if child_index == node_index));
+            parent.children.retain(|&child_index| child_index != node_index);
+        }
+
+        self.attach(location, node_index);
+        self.needs_renumbering = true;
+    }
+
+    fn insert_text(&mut self, location: InsertionLocation, text: &str) {
+        let parent = &mut self.elements[location.parent_index];
+        let position = location.before.map_or(parent.child_nodes.len(), |before| child_node_position(parent, before));
+
+        match position.checked_sub(1).map(|previous| &mut parent.child_nodes[previous]) {
+            Some(&mut ChildNode::Text(ref mut existing_text)) => existing_text.push_str(text),
+            _ => parent.child_nodes.insert(position, ChildNode::Text(text.to_string())),
+        }
+    }
+
+    fn append_child_node(&mut self, parent_index: usize, child_node: ChildNode) {
+        self.elements[parent_index].child_nodes.push(child_node);
+    }
+}
+
+/// Returns the position of the child element among the child nodes of the
+/// element.
+fn child_node_position(data: &ElementData, child_index: usize) -> usize {
+    data.child_nodes.iter()
+        .position(|child_node| matches!(*child_node, ChildNode::Element(index) if index == child_index))
+        .unwrap()
+}
+
+/// Returns the name from the list which the lowercased name stands for, if
+/// any.
+fn restore_case(name: String, names: &[&str]) -> String {
+    match names.iter().find(|known_name| known_name.eq_ignore_ascii_case(&name)) {
+        Some(known_name) => known_name.to_string(),
+        None => name,
+    }
+}
+
+fn new_element_data(tag_name: Arc<str>, parent: Option<usize>, attributes: Vec<Attribute>) -> ElementData {
+    ElementData {
+        tag_name,
+        prefix: None,
+        namespace_uri: None,
+        namespace_declarations: Vec::new(),
+        parent,
+        children: Vec::new(),
+        child_nodes: Vec::new(),
+        attributes,
+        source_spans: None,
+    }
+}
+
+/// Parses the HTML into a document. Parsing never fails: just like in a
+/// browser, malformed markup is recovered from.
+pub(crate) fn parse_html(source: &str) -> Document {
+    let source = source.replace("\r\n", "\n").replace('\r', "\n").replace('\0', "");
+    let mut tree_builder = TreeBuilder::new();
+
+    for token in Tokenizer::new(&source) {
+        tree_builder.process(token);
+    }
+
+    if tree_builder.body.is_none() {
+        tree_builder.ensure_body(Vec::new());
+    }
+
+    tree_builder.build()
+}
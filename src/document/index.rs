@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::{ Arc, OnceLock };
+
+use super::super::{ ElementData, Selector, MatchType, CaseSensitivity };
+
+/// The indexes a `Document` keeps of its elements, when enabled. The lists
+/// of node indices are sorted, so they are in document order.
+///
+/// The indexes are built from the elements when first needed, and dropped
+/// whenever the document is changed.
+#[derive(Clone, Debug)]
+pub(crate) struct Indexes {
+    attribute_names: Vec<String>,
+    tables: OnceLock<Tables>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Tables {
+    ids: HashMap<String, Vec<usize>>,
+    tag_names: HashMap<Arc<str>, Vec<usize>>,
+    attributes: HashMap<String, HashMap<String, Vec<usize>>>,
+}
+
+impl Indexes {
+    /// Creates indexes by id and tag name, and by value for the attributes
+    /// with the given names.
+    pub(crate) fn new(attribute_names: Vec<String>) -> Indexes {
+        Indexes {
+            attribute_names,
+            tables: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn tables(&self, elements: &[ElementData]) -> &Tables {
+        self.tables.get_or_init(|| Tables::build(elements, &self.attribute_names))
+    }
+
+    /// Drops the tables after the elements were changed, so that they are
+    /// built again on the next lookup.
+    pub(crate) fn invalidate(&mut self) {
+        self.tables.take();
+    }
+}
+
+impl Tables {
+    fn build(elements: &[ElementData], attribute_names: &[String]) -> Tables {
+        let mut tables = Tables {
+            attributes: attribute_names.iter()
+                .map(|name| (name.clone(), HashMap::new()))
+                .collect(),
+
+            ..Tables::default()
+        };
+
+        for (node_index, data) in elements.iter().enumerate().skip(1) {
+            tables.tag_names.entry(data.tag_name.clone()).or_default().push(node_index);
+
+            if let Some(id) = data.attributes.iter().find(|attribute| attribute.has_qualified_name("id")) {
+                tables.ids.entry(id.value.clone()).or_default().push(node_index);
+            }
+
+            for attribute in &data.attributes {
+                if attribute.namespace_uri.is_some() {
+                    continue;
+                }
+
+                if let Some(values) = tables.attributes.get_mut(&*attribute.name) {
+                    let node_indices = values.entry(attribute.value.clone()).or_default();
+
+                    if node_indices.last() != Some(&node_index) {
+                        node_indices.push(node_index);
+                    }
+                }
+            }
+        }
+
+        tables
+    }
+
+    /// Returns the node indices of the elements with the given id.
+    pub(crate) fn ids(&self, id: &str) -> &[usize] {
+        self.ids.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the node indices of the only elements which can match the
+    /// selector, if it can be looked up in the indexes.
+    pub(crate) fn candidates(&self, selector: &Selector) -> Option<&[usize]> {
+        match *selector {
+            Selector::Id(ref id) =>
+                Some(self.ids(id)),
+
+            Selector::TagName(ref name) =>
+                Some(self.tag_names.get(name.as_str()).map_or(&[], Vec::as_slice)),
+
+            Selector::Attribute(None, ref name, MatchType::Equals, ref value, CaseSensitivity::CaseSensitive) =>
+                self.attributes.get(name).map(|values| values.get(value).map_or(&[][..], Vec::as_slice)),
+
+            _ => None,
+        }
+    }
+}
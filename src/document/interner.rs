@@ -0,0 +1,29 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Shares a single allocation between all the occurrences of a name, such
+/// as a tag name, attribute name or namespace URI, while a document is
+/// being built. The names come from the document, so they’re hashed with
+/// the randomly keyed default hasher, which keeps a document crafted to
+/// collide from slowing down the parser.
+#[derive(Default)]
+pub(crate) struct Interner {
+    names: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+
+        interned
+    }
+
+    pub(crate) fn intern_option(&mut self, name: Option<&str>) -> Option<Arc<str>> {
+        name.map(|name| self.intern(name))
+    }
+}
@@ -1,95 +1,255 @@
 use std::io::{ BufReader, Read };
 use std::fs::File;
-use std::rc::Rc;
 use std::path::Path;
+use std::rc::Rc;
 use std::collections::HashMap;
 
-use xml::reader::{ EventReader, XmlEvent };
+use xml::namespace::{ Namespace, NamespaceStack };
+use xml::common::{ Position, TextPosition };
+use xml::reader::{ ParserConfig, XmlEvent };
 
-use super::{ Element, SelectError };
+use self::error::RecordingReader;
+use self::index::{ Indexes, Tables };
+use self::interner::Interner;
 
-/// The various errors that can happen when creating a document.
-#[derive(Clone, Debug)]
-pub enum DocumentError {
-    UnableToOpenFile(String),
-    ParseError(String),
+pub use self::writer::{ QuoteStyle, WriteOptions };
+
+pub use self::builder::{ BuildError, DocumentBuilder, ElementBuilder };
+pub use self::error::{ DocumentError, SyntaxError, SyntaxErrorKind };
+pub use self::mutation::{ ElementMut, MutationError, SelectionMut };
+
+pub(crate) use self::source::{ NodeSpan, SourceSpans };
+
+mod builder;
+mod entities;
+mod error;
+mod html;
+mod index;
+mod interner;
+mod mutation;
+mod source;
+mod writer;
+
+use super::{ Attribute, ChildNode, Element, ElementData, Node, SelectError, SelectorList };
+
+/// Options controlling how documents are parsed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParseOptions {
+    /// Keeps the original source of the document, so that writing it back
+    /// reproduces the input byte-for-byte, including whitespace, attribute
+    /// order, quote styles, comments and the prolog. Only the markup of nodes
+    /// which were changed is written anew.
+    pub lossless: bool,
+    /// Indexes the elements by id and tag name, so that selectors whose last
+    /// compound selector has an id or tag name (e.g. `item > #id-1`) only
+    /// check the elements which can match.
+    pub index: bool,
+    /// The names of attributes to index by value as well when `index` is
+    /// set, used for selectors like `[type="radio"]`. Only attributes
+    /// without a namespace are indexed.
+    pub indexed_attributes: Vec<String>,
 }
 
 /// The DOM tree representation of the parsed document.
 #[derive(Clone, Debug)]
 pub struct Document {
-    root: Element,
+    pub(crate) elements: Vec<ElementData>,
+    pub(crate) source: Option<String>,
+    pub(crate) indexes: Option<Indexes>,
 }
 
 impl Document {
     /// Creates a new document from a byte stream.
     pub fn new_from_xml_stream<R: Read>(stream: R) -> Result<Document, DocumentError> {
-        let event_reader = EventReader::new(stream);
+        Document::new_from_xml_stream_with_options(stream, &ParseOptions::default())
+    }
+
+    /// Creates a new document from a byte stream, using the given options.
+    pub fn new_from_xml_stream_with_options<R: Read>(mut stream: R, options: &ParseOptions) -> Result<Document, DocumentError> {
+        if !options.lossless {
+            let mut document = Document::parse_xml_stream(stream)?;
+
+            if options.index {
+                document.build_indexes(&options.indexed_attributes);
+            }
+
+            return Ok(document);
+        }
+
+        let mut bytes = Vec::new();
+
+        if let Err(error) = stream.read_to_end(&mut bytes) {
+            return Err(DocumentError::UnableToRead(error));
+        }
+
+        let source = match String::from_utf8(bytes) {
+            Ok(source) => source,
+            Err(error) => {
+                let offset = error.utf8_error().valid_up_to();
+                return Err(DocumentError::at_offset(SyntaxErrorKind::InvalidEncoding, error.as_bytes(), offset));
+            },
+        };
+
+        let mut document = Document::parse_xml_stream(source.as_bytes())?;
+
+        source::record_source_spans(&mut document.elements, &source);
+        document.source = Some(source);
+
+        if options.index {
+            document.build_indexes(&options.indexed_attributes);
+        }
+
+        Ok(document)
+    }
+
+    fn parse_xml_stream<R: Read>(stream: R) -> Result<Document, DocumentError> {
+        let mut event_reader = ParserConfig::new()
+            .ignore_comments(false)
+            .create_reader(RecordingReader::new(stream));
+
+        let mut elements = vec!(ElementData {
+            tag_name: "[root]".into(),
+            prefix: None,
+            namespace_uri: None,
+            namespace_declarations: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+            child_nodes: Vec::new(),
+            attributes: Vec::new(),
+            source_spans: None,
+        });
+        let mut open_elements: Vec<usize> = Vec::new();
+        let mut namespaces: Vec<Rc<Namespace>> = vec!(Rc::new(NamespaceStack::default().squash()));
+        let mut interner = Interner::default();
+        let mut top_level = TextPosition::new();
 
-        let mut elements: Vec<Element> = Vec::new();
-        let mut next_node_index = 1;
+        loop {
+            let event = event_reader.next();
+            let syntax_error = |kind: SyntaxErrorKind| {
+                event_reader.source().error_at(kind, event_reader.position())
+            };
+
+            let current_index = open_elements.last().cloned().unwrap_or(0);
 
-        for event in event_reader {
             match event {
-                Ok(XmlEvent::StartElement { ref name, ref attributes, .. }) => {
-                    let attr_map = attributes.iter()
-                        .fold(HashMap::new(), |mut hash_map, attribute| {
-                            hash_map.insert(attribute.name.local_name.clone(), attribute.value.clone());
-
-                            return hash_map;
-                        });
-
-                    elements.push(Element {
-                        node_index: next_node_index,
-                        children: None,
-                        tag_name: name.local_name.clone(),
-                        attr_map: attr_map,
-                        text: String::new(),
+                Ok(XmlEvent::StartElement { .. }) if open_elements.is_empty() && !elements[0].children.is_empty() => {
+                    return Err(syntax_error(SyntaxErrorKind::MultipleRootElements));
+                },
+
+                Ok(XmlEvent::StartElement { ref name, ref attributes, namespace }) => {
+                    // Most elements don’t declare any namespaces, so they share
+                    // the namespaces of their parent rather than copying them.
+                    let parent_namespace = namespaces.last().unwrap();
+                    let (namespace, namespace_declarations) = if **parent_namespace == namespace {
+                        (parent_namespace.clone(), Vec::new())
+                    } else {
+                        let namespace_declarations = new_namespace_declarations(parent_namespace, &namespace);
+                        (Rc::new(namespace), namespace_declarations)
+                    };
+
+                    // The values are copied rather than moved, which drops the
+                    // spare capacity left in them by the parser.
+                    let attributes = attributes.iter()
+                        .map(|attribute| Attribute {
+                            name: interner.intern(&attribute.name.local_name),
+                            prefix: interner.intern_option(attribute.name.prefix.as_deref()),
+                            namespace_uri: interner.intern_option(attribute.name.namespace.as_deref()),
+                            value: attribute.value.clone(),
+                        })
+                        .collect();
+
+                    let node_index = elements.len();
+
+                    elements[current_index].children.push(node_index);
+                    elements[current_index].child_nodes.push(ChildNode::Element(node_index));
+                    elements.push(ElementData {
+                        tag_name: interner.intern(&name.local_name),
+                        prefix: interner.intern_option(name.prefix.as_deref()),
+                        namespace_uri: interner.intern_option(name.namespace.as_deref()),
+                        namespace_declarations,
+                        parent: Some(current_index),
+                        children: Vec::new(),
+                        child_nodes: Vec::new(),
+                        attributes,
+                        source_spans: None,
                     });
-                    next_node_index = next_node_index + 1;
+                    open_elements.push(node_index);
+                    namespaces.push(namespace);
                 },
 
-                Ok(XmlEvent::EndElement { ref name, .. }) if elements.last().unwrap().tag_name() == name.local_name  => {
-                    let child_node = elements.pop().unwrap();
+                Ok(XmlEvent::EndElement { ref name }) => {
+                    let element = &elements[current_index];
 
-                    if let Some(mut parent) = elements.pop() {
-                        if let Some(ref mut children) = parent.children {
-                            children.push(Rc::new(child_node));
-                        } else {
-                            parent.children = Some(vec!(Rc::new(child_node)));
-                        }
+                    if open_elements.is_empty() || *element.tag_name != *name.local_name || element.prefix.as_deref() != name.prefix.as_deref() {
+                        return Err(syntax_error(SyntaxErrorKind::MismatchedTag {
+                            expected: qualified_name(element.prefix.as_deref(), &element.tag_name),
+                            found: qualified_name(name.prefix.as_deref(), &name.local_name),
+                        }));
+                    }
 
-                        elements.push(parent);
-                    } else {
-                        return Ok(Document {
-                            root: Element {
-                                node_index: 0,
-                                tag_name: "[root]".to_string(),
-                                children: Some(vec!(Rc::new(child_node))),
-                                attr_map: HashMap::new(),
-                                text: String::new(),
-                            }
-                        });
+                    open_elements.pop();
+                    namespaces.pop();
+                },
+
+                Ok(XmlEvent::Characters(_)) | Ok(XmlEvent::CData(_)) if open_elements.is_empty() => {
+                    return Err(syntax_error(SyntaxErrorKind::TextOutsideRoot));
+                },
+
+                Ok(XmlEvent::Characters(ref string)) | Ok(XmlEvent::Whitespace(ref string)) => {
+                    let element = &mut elements[current_index];
+
+                    match element.child_nodes.last_mut() {
+                        Some(&mut ChildNode::Text(ref mut text)) => text.push_str(string),
+                        _ => element.child_nodes.push(ChildNode::Text(string.clone())),
                     }
                 },
 
-                Ok(XmlEvent::Characters(string)) => {
-                    elements.last_mut().unwrap().text.push_str(&string);
+                Ok(XmlEvent::CData(string)) => {
+                    elements[current_index].child_nodes.push(ChildNode::CData(string));
+                },
+
+                Ok(XmlEvent::Comment(string)) => {
+                    elements[current_index].child_nodes.push(ChildNode::Comment(string));
+                },
+
+                Ok(XmlEvent::ProcessingInstruction { name, data }) => {
+                    elements[current_index].child_nodes.push(ChildNode::ProcessingInstruction(name, data));
+                },
+
+                Ok(XmlEvent::EndDocument) if !open_elements.is_empty() => {
+                    return Err(syntax_error(SyntaxErrorKind::UnexpectedEndOfDocument));
+                },
+
+                Ok(XmlEvent::EndDocument) if elements[0].children.is_empty() => {
+                    return Err(syntax_error(SyntaxErrorKind::EmptyDocument));
                 },
 
-                Ok(XmlEvent::Whitespace(string)) => {
-                    elements.last_mut().unwrap().text.push_str(&string);
+                Ok(XmlEvent::EndDocument) => {
+                    shrink_to_fit(&mut elements);
+
+                    return Ok(Document {
+                        elements,
+                        source: None,
+                        indexes: None,
+                    });
                 },
 
                 Err(error) => {
-                    return Err(DocumentError::ParseError(error.to_string()));
+                    let open_element = open_elements.last().map(|&node_index| {
+                        qualified_name(elements[node_index].prefix.as_deref(), &elements[node_index].tag_name)
+                    });
+
+                    return Err(DocumentError::from_xml_error(&error, event_reader.source(), open_element.as_deref(), elements.len() > 1, top_level));
                 },
 
                 Ok(_) => { },
             }
+
+            if open_elements.is_empty() {
+                top_level = event_reader.position();
+            }
         }
 
-        panic!("Root element was not properly returned!");
     }
 
     /// Creates a new document from a string.
@@ -97,33 +257,166 @@ impl Document {
         Document::new_from_xml_stream(string.as_bytes())
     }
 
+    /// Creates a new document from a string, using the given options.
+    pub fn new_from_xml_string_with_options(string: &str, options: &ParseOptions) -> Result<Document, DocumentError> {
+        Document::new_from_xml_stream_with_options(string.as_bytes(), options)
+    }
+
     /// Creates a new document from a file.
     pub fn new_from_xml_file(filename: &str) -> Result<Document, DocumentError> {
+        Document::new_from_xml_file_with_options(filename, &ParseOptions::default())
+    }
+
+    /// Creates a new document from a file, using the given options.
+    pub fn new_from_xml_file_with_options(filename: &str, options: &ParseOptions) -> Result<Document, DocumentError> {
         let path = Path::new(filename);
 
-        if let Ok(file) = File::open(path) {
-            let reader = BufReader::new(file);
+        match File::open(path) {
+            Ok(file) => Document::new_from_xml_stream_with_options(BufReader::new(file), options),
+            Err(error) => Err(DocumentError::UnableToOpenFile(path.to_string_lossy().into_owned(), error)),
+        }
+    }
+
+    /// Creates a new document from an HTML byte stream. Just like in a
+    /// browser, malformed HTML is recovered from rather than rejected: tags are
+    /// implied or closed where needed, and the document always has `html`,
+    /// `head` and `body` elements. Tag and attribute names are lowercased.
+    pub fn new_from_html_stream<R: Read>(mut stream: R) -> Result<Document, DocumentError> {
+        let mut bytes = Vec::new();
 
-            Document::new_from_xml_stream(reader)
-        } else {
-            Err(DocumentError::UnableToOpenFile(path.to_str().unwrap().to_string()))
+        if let Err(error) = stream.read_to_end(&mut bytes) {
+            return Err(DocumentError::UnableToRead(error));
+        }
+
+        Ok(html::parse_html(&String::from_utf8_lossy(&bytes)))
+    }
+
+    /// Creates a new document from an HTML string.
+    pub fn new_from_html_string(string: &str) -> Result<Document, DocumentError> {
+        Ok(html::parse_html(string))
+    }
+
+    /// Creates a new document from an HTML file.
+    pub fn new_from_html_file(filename: &str) -> Result<Document, DocumentError> {
+        let path = Path::new(filename);
+
+        match File::open(path) {
+            Ok(file) => Document::new_from_html_stream(BufReader::new(file)),
+            Err(error) => Err(DocumentError::UnableToOpenFile(path.to_string_lossy().into_owned(), error)),
         }
     }
 
     /// Returns the total number of elements in the document.
     pub fn number_of_elements(&self) -> usize {
-        self.root.subtree_size() - 1
+        self.elements.len() - 1
+    }
+
+    /// Returns an iterator over the nodes at the top level of the document,
+    /// such as the top-level element and any comments or processing
+    /// instructions around it.
+    pub fn child_nodes<'a>(&'a self) -> Box<dyn Iterator<Item=Node<'a>> + 'a> {
+        self.root().child_nodes()
     }
 
     /// Searches the document for elements matching the given CSS selector.
-    pub fn select_all<'a>(&'a self, selector: &str) -> Result<Box<Iterator<Item=&'a Element> + 'a>, SelectError> {
-        self.root.select_all(selector)
+    pub fn select_all<'a>(&'a self, selector: &str) -> Result<Box<dyn Iterator<Item=Element<'a>> + 'a>, SelectError> {
+        self.root().select_all(selector)
     }
 
     /// Just like `select_all` but only returns the first match.
-    pub fn select<'a>(&'a self, selector: &str) -> Result<&'a Element, SelectError> {
-        self.root.select(selector)
+    pub fn select<'a>(&'a self, selector: &str) -> Result<Element<'a>, SelectError> {
+        self.root().select(selector)
+    }
+
+    /// Just like `select_all`, but with a selector list which has already
+    /// been parsed, avoiding parsing the selector again on every call.
+    pub fn select_all_compiled<'a, 's>(&'a self, selector_list: &'s SelectorList) -> Box<dyn Iterator<Item=Element<'a>> + 's> where 'a: 's {
+        self.root().select_all_compiled(selector_list)
+    }
+
+    /// Just like `select_all_compiled` but only returns the first match.
+    pub fn select_compiled<'a>(&'a self, selector_list: &SelectorList) -> Option<Element<'a>> {
+        self.root().select_compiled(selector_list)
+    }
+
+    /// Searches the document for elements matching the given CSS selector,
+    /// resolving namespace prefixes in the selector with the given map from
+    /// prefix to namespace URI.
+    pub fn select_all_with_namespaces<'a>(&'a self, selector: &str, namespaces: &HashMap<String, String>) -> Result<Box<dyn Iterator<Item=Element<'a>> + 'a>, SelectError> {
+        self.root().select_all_with_namespaces(selector, namespaces)
+    }
+
+    /// Just like `select_all_with_namespaces` but only returns the first
+    /// match.
+    pub fn select_with_namespaces<'a>(&'a self, selector: &str, namespaces: &HashMap<String, String>) -> Result<Element<'a>, SelectError> {
+        self.root().select_with_namespaces(selector, namespaces)
+    }
+
+    /// Returns the first element in document order with the given id.
+    pub fn get_element_by_id(&self, id: &str) -> Option<Element<'_>> {
+        match self.index_tables() {
+            Some(tables) => tables.ids(id).first().map(|&node_index| Element {
+                document: self,
+                node_index,
+            }),
+
+            None => self.root().descendants().find(|element| element.attr("id").is_some_and(|value| value == id)),
+        }
     }
+
+    /// Indexes the elements by id and tag name, and by value for the
+    /// attributes with the given names, just like `ParseOptions::index`.
+    /// The indexes are kept up to date when the document is changed.
+    pub fn build_indexes<S: AsRef<str>>(&mut self, indexed_attributes: &[S]) {
+        let indexes = Indexes::new(indexed_attributes.iter().map(|name| name.as_ref().to_string()).collect());
+        indexes.tables(&self.elements);
+
+        self.indexes = Some(indexes);
+    }
+
+    pub(crate) fn index_tables(&self) -> Option<&Tables> {
+        self.indexes.as_ref().map(|indexes| indexes.tables(&self.elements))
+    }
+
+    pub(crate) fn root(&self) -> Element<'_> {
+        Element {
+            document: self,
+            node_index: 0,
+        }
+    }
+}
+
+fn qualified_name(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+/// Returns the namespace bindings which are new or changed in the element’s
+/// namespace compared to its parent’s, with `None` for the default namespace.
+fn new_namespace_declarations(parent_namespace: &Namespace, namespace: &Namespace) -> Vec<(Option<String>, String)> {
+    namespace.into_iter()
+        .filter(|&(prefix, uri)| parent_namespace.get(prefix) != Some(uri))
+        .map(|(prefix, uri)| {
+            if prefix.is_empty() {
+                (None, uri.to_string())
+            } else {
+                (Some(prefix.to_string()), uri.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Releases the spare capacity left over from parsing, since most documents
+/// are only read once parsed.
+fn shrink_to_fit(elements: &mut Vec<ElementData>) {
+    for data in elements.iter_mut() {
+        data.children.shrink_to_fit();
+        data.child_nodes.shrink_to_fit();
+    }
+
+    elements.shrink_to_fit();
 }
 
 #[test]
@@ -166,9 +459,9 @@ fn it_assigns_node_indices_in_monotonically_increasing_order() {
 </sample>
 "#).unwrap();
 
-    assert_eq!(document.root.node_index, 0);
+    assert_eq!(document.root().node_index, 0);
 
-    document.root.children_deep_iter().fold(0, |index, child| {
+    document.root().children_deep_iter().fold(0, |index, child| {
         assert!(index < child.node_index);
         child.node_index
     });
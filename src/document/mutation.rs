@@ -0,0 +1,563 @@
+use std::error::Error;
+use std::fmt;
+use std::iter::once;
+use std::mem;
+use std::sync::Arc;
+
+use super::{ Document, NodeSpan };
+use super::super::{ Attribute, ChildNode, Element, ElementData, Node, SelectError };
+
+/// Errors which can be returned when changing the structure of a document.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MutationError {
+    /// Returned when the edit would leave the document without its top-level
+    /// element, which couldn’t be written as well-formed XML.
+    RemovesTopLevelElement,
+    /// Returned when the edit would add an element or text beside the
+    /// top-level element, which couldn’t be written as well-formed XML.
+    AddsTopLevelNode,
+}
+
+impl fmt::Display for MutationError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MutationError::RemovesTopLevelElement => formatter.write_str("the top-level element can’t be removed"),
+            MutationError::AddsTopLevelNode => formatter.write_str("only comments and processing instructions can be added beside the top-level element"),
+        }
+    }
+}
+
+impl Error for MutationError { }
+
+/// A mutable handle to a single element, which borrows the `Document`
+/// exclusively while the element is being changed.
+///
+/// Changing the structure of the document renumbers the elements, so that
+/// node indices stay in document order. The handle follows its element when
+/// it is renumbered.
+#[derive(Debug)]
+pub struct ElementMut<'a> {
+    document: &'a mut Document,
+    node_index: usize,
+    /// Set for elements changed through a `SelectionMut`, which renumbers the
+    /// elements once after all of them were changed instead of after each
+    /// change to the structure of the document.
+    structure_changed: Option<&'a mut bool>,
+}
+
+impl<'a> ElementMut<'a> {
+    /// Returns a read-only handle to the element, e.g. to query it before
+    /// changing it.
+    pub fn as_element(&self) -> Element<'_> {
+        Element {
+            document: self.document,
+            node_index: self.node_index,
+        }
+    }
+
+    /// Returns the node index for the element.
+    pub fn node_index(&self) -> usize {
+        self.node_index
+    }
+
+    /// Sets the value of the attribute with the given qualified name (e.g.
+    /// `href` or `xlink:href`), adding the attribute if it doesn’t exist yet.
+    pub fn set_attr(&mut self, attr_name: &str, value: &str) {
+        let (prefix, name) = split_qualified_name(attr_name);
+        let namespace_uri = prefix.and_then(|prefix| self.lookup_namespace_uri(Some(prefix)));
+
+        let data = self.data_mut();
+        let existing_attribute = data.attributes.iter_mut()
+            .find(|attribute| attribute.has_qualified_name(attr_name));
+
+        if let Some(attribute) = existing_attribute {
+            attribute.value = value.to_string();
+        } else {
+            data.attributes.push(Attribute {
+                name: name.into(),
+                prefix: prefix.map(Arc::from),
+                namespace_uri: namespace_uri.map(Arc::from),
+                value: value.to_string(),
+            });
+        }
+
+        let document = &mut *self.document;
+
+        if let (Some(source), Some(spans)) = (&document.source, &mut document.elements[self.node_index].source_spans) {
+            let attribute_span = spans.attributes.iter_mut()
+                .find(|attribute_span| source[attribute_span.name.clone()] == *attr_name);
+
+            if let Some(attribute_span) = attribute_span {
+                attribute_span.value_modified = true;
+            }
+        }
+    }
+
+    /// Removes the attribute with the given qualified name, returning its
+    /// value if it existed.
+    pub fn remove_attr(&mut self, attr_name: &str) -> Option<String> {
+        let position = self.data_mut().attributes.iter()
+            .position(|attribute| attribute.has_qualified_name(attr_name))?;
+
+        Some(self.data_mut().attributes.remove(position).value)
+    }
+
+    /// Renames the element to the given qualified name (e.g. `item` or
+    /// `x:item`), resolving its namespace from the declarations in scope.
+    pub fn set_tag_name(&mut self, tag_name: &str) {
+        let (prefix, name) = split_qualified_name(tag_name);
+        let namespace_uri = self.lookup_namespace_uri(prefix);
+
+        let data = self.data_mut();
+        data.tag_name = name.into();
+        data.prefix = prefix.map(Arc::from);
+        data.namespace_uri = namespace_uri.map(Arc::from);
+
+        if let Some(ref mut spans) = data.source_spans {
+            spans.tag_name_modified = true;
+        }
+    }
+
+    /// Replaces all the child nodes of the element with the given text.
+    pub fn set_text(&mut self, text: &str) {
+        let data = self.data_mut();
+
+        data.child_nodes = if text.is_empty() {
+            Vec::new()
+        } else {
+            vec!(ChildNode::Text(text.to_string()))
+        };
+
+        let number_of_child_nodes = data.child_nodes.len();
+
+        if let Some(ref mut spans) = data.source_spans {
+            spans.child_nodes = vec!(NodeSpan::new_node(); number_of_child_nodes);
+            spans.trailing = 0..0;
+        }
+
+        self.update_structure();
+    }
+
+    /// Appends the node as the last child node of the element. Elements are
+    /// copied, along with their descendants, from the document they belong
+    /// to.
+    pub fn append_child(&mut self, node: Node) {
+        let node_index = self.node_index;
+        let child_node = self.document.copy_node(node, node_index);
+
+        let data = self.data_mut();
+        data.child_nodes.push(child_node);
+
+        if let Some(ref mut spans) = data.source_spans {
+            spans.child_nodes.push(NodeSpan::new_node());
+        }
+
+        self.update_structure();
+    }
+
+    /// Inserts the node as the previous sibling of the element. Elements are
+    /// copied, along with their descendants, from the document they belong
+    /// to. Only comments and processing instructions can be inserted before
+    /// the top-level element of the document.
+    pub fn insert_before(&mut self, node: Node) -> Result<(), MutationError> {
+        if self.is_top_level() && !matches!(node, Node::Comment(_) | Node::ProcessingInstruction(..)) {
+            return Err(MutationError::AddsTopLevelNode);
+        }
+
+        let (parent_index, position) = self.position_in_parent();
+        let child_node = self.document.copy_node(node, parent_index);
+
+        let parent = &mut self.document.elements[parent_index];
+        parent.child_nodes.insert(position, child_node);
+
+        if let Some(ref mut spans) = parent.source_spans {
+            let position = position.min(spans.child_nodes.len());
+            spans.child_nodes.insert(position, NodeSpan::new_node());
+        }
+
+        self.update_structure();
+
+        Ok(())
+    }
+
+    /// Removes the element, along with its descendants, from the document.
+    /// The top-level element of the document can’t be removed.
+    pub fn remove(self) -> Result<(), MutationError> {
+        if self.is_top_level() {
+            return Err(MutationError::RemovesTopLevelElement);
+        }
+
+        self.detach();
+
+        Ok(())
+    }
+
+    fn detach(self) {
+        let (parent_index, position) = self.position_in_parent();
+
+        let parent = &mut self.document.elements[parent_index];
+        parent.child_nodes.remove(position);
+
+        if let Some(ref mut spans) = parent.source_spans {
+            if position < spans.child_nodes.len() {
+                spans.child_nodes.remove(position);
+            }
+        }
+
+        update_child_nodes(parent);
+        self.finish_structure_change();
+    }
+
+    /// Replaces the element, along with its descendants, with the node.
+    /// Elements are copied, along with their descendants, from the document
+    /// they belong to. The top-level element of the document can only be
+    /// replaced with another element.
+    pub fn replace_with(self, node: Node) -> Result<(), MutationError> {
+        if self.is_top_level() && !matches!(node, Node::Element(_)) {
+            return Err(MutationError::RemovesTopLevelElement);
+        }
+
+        let (parent_index, position) = self.position_in_parent();
+        let child_node = self.document.copy_node(node, parent_index);
+
+        let parent = &mut self.document.elements[parent_index];
+        parent.child_nodes[position] = child_node;
+
+        if let Some(ref mut spans) = parent.source_spans {
+            if let Some(span) = spans.child_nodes.get_mut(position) {
+                span.node = None;
+            }
+        }
+
+        update_child_nodes(parent);
+        self.finish_structure_change();
+
+        Ok(())
+    }
+
+    /// Renumbers the elements after the element was removed or replaced,
+    /// unless that is left to the selection the element belongs to.
+    fn finish_structure_change(self) {
+        match self.structure_changed {
+            Some(structure_changed) => *structure_changed = true,
+            None => { self.document.renumber(); },
+        }
+    }
+
+    fn is_top_level(&self) -> bool {
+        self.document.elements[self.node_index].parent == Some(0)
+    }
+
+    fn data_mut(&mut self) -> &mut ElementData {
+        self.document.invalidate_indexes();
+
+        &mut self.document.elements[self.node_index]
+    }
+
+    /// Returns the index of the parent element and the position of the
+    /// element among the parent’s child nodes.
+    fn position_in_parent(&self) -> (usize, usize) {
+        let parent_index = self.document.elements[self.node_index].parent.unwrap();
+        let position = self.document.elements[parent_index].child_nodes.iter()
+            .position(|child_node| match *child_node {
+                ChildNode::Element(node_index) => node_index == self.node_index,
+                _ => false,
+            })
+            .unwrap();
+
+        (parent_index, position)
+    }
+
+    /// Updates the element and its parent after their child nodes changed,
+    /// and renumbers the elements unless that is left to the selection the
+    /// element belongs to.
+    fn update_structure(&mut self) {
+        update_child_nodes(self.data_mut());
+
+        if let Some(parent_index) = self.document.elements[self.node_index].parent {
+            update_child_nodes(&mut self.document.elements[parent_index]);
+        }
+
+        match self.structure_changed {
+            Some(ref mut structure_changed) => **structure_changed = true,
+            None => {
+                let new_node_indices = self.document.renumber();
+                self.node_index = new_node_indices[self.node_index].unwrap();
+            },
+        }
+    }
+
+    /// Returns the namespace URI bound to the prefix (or the default namespace
+    /// for `None`) by the declarations in scope of the element.
+    fn lookup_namespace_uri(&self, prefix: Option<&str>) -> Option<String> {
+        let mut node_index = Some(self.node_index);
+
+        while let Some(index) = node_index.filter(|&index| index != 0) {
+            let data = &self.document.elements[index];
+
+            let declaration = data.namespace_declarations.iter()
+                .find(|&(declared_prefix, _)| declared_prefix.as_deref() == prefix);
+
+            if let Some((_, uri)) = declaration {
+                return Some(uri.clone()).filter(|uri| !uri.is_empty());
+            }
+
+            node_index = data.parent;
+        }
+
+        None
+    }
+}
+
+/// A set of elements selected for changing them all at once, which borrows
+/// the `Document` exclusively.
+#[derive(Debug)]
+pub struct SelectionMut<'a> {
+    document: &'a mut Document,
+    node_indices: Vec<usize>,
+}
+
+impl<'a> SelectionMut<'a> {
+    /// Returns the number of selected elements.
+    pub fn len(&self) -> usize {
+        self.node_indices.len()
+    }
+
+    /// Returns true if no elements were selected.
+    pub fn is_empty(&self) -> bool {
+        self.node_indices.is_empty()
+    }
+
+    /// Calls the closure with each of the selected elements. The elements are
+    /// visited in reverse document order, so that changing the structure of
+    /// the document around an element doesn’t affect the elements still to
+    /// be visited.
+    ///
+    /// Changes to the structure of the document renumber the elements only
+    /// once all of them were visited, so node indices seen from the closure
+    /// may not be in document order until then.
+    pub fn for_each<F: FnMut(ElementMut)>(self, mut f: F) {
+        let mut structure_changed = false;
+
+        for node_index in self.node_indices.into_iter().rev() {
+            f(ElementMut {
+                document: &mut *self.document,
+                node_index,
+                structure_changed: Some(&mut structure_changed),
+            });
+        }
+
+        if structure_changed {
+            self.document.renumber();
+        }
+    }
+
+    /// Sets the value of the attribute on all of the selected elements.
+    pub fn set_attr(self, attr_name: &str, value: &str) {
+        self.for_each(|mut element| element.set_attr(attr_name, value));
+    }
+
+    /// Removes the attribute from all of the selected elements.
+    pub fn remove_attr(self, attr_name: &str) {
+        self.for_each(|mut element| { element.remove_attr(attr_name); });
+    }
+
+    /// Renames all of the selected elements.
+    pub fn set_tag_name(self, tag_name: &str) {
+        self.for_each(|mut element| element.set_tag_name(tag_name));
+    }
+
+    /// Replaces the child nodes of all of the selected elements with the
+    /// text.
+    pub fn set_text(self, text: &str) {
+        self.for_each(|mut element| element.set_text(text));
+    }
+
+    /// Removes all of the selected elements from the document. Nothing is
+    /// removed if the top-level element of the document was selected.
+    pub fn remove(self) -> Result<(), MutationError> {
+        if self.node_indices.iter().any(|&node_index| self.document.elements[node_index].parent == Some(0)) {
+            return Err(MutationError::RemovesTopLevelElement);
+        }
+
+        self.for_each(|element| element.detach());
+
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Returns a mutable handle to the element with the given node index.
+    pub fn element_mut(&mut self, node_index: usize) -> Option<ElementMut<'_>> {
+        if node_index == 0 || node_index >= self.elements.len() {
+            return None;
+        }
+
+        Some(ElementMut {
+            document: self,
+            node_index,
+            structure_changed: None,
+        })
+    }
+
+    /// Just like `select` but returns a mutable handle to the element.
+    pub fn select_mut(&mut self, selector: &str) -> Result<ElementMut<'_>, SelectError> {
+        let node_index = self.select(selector)?.node_index();
+
+        Ok(ElementMut {
+            document: self,
+            node_index,
+            structure_changed: None,
+        })
+    }
+
+    /// Searches the document for elements matching the given CSS selector,
+    /// selecting them to be changed all at once.
+    pub fn select_all_mut(&mut self, selector: &str) -> Result<SelectionMut<'_>, SelectError> {
+        let node_indices = self.select_all(selector)?
+            .map(|element| element.node_index())
+            .collect();
+
+        Ok(SelectionMut {
+            document: self,
+            node_indices,
+        })
+    }
+
+    fn invalidate_indexes(&mut self) {
+        if let Some(ref mut indexes) = self.indexes {
+            indexes.invalidate();
+        }
+    }
+
+    /// Copies the node into the document as a child node of the given
+    /// element, returning the new child node.
+    fn copy_node(&mut self, node: Node, parent_index: usize) -> ChildNode {
+        match node {
+            Node::Element(element) => {
+                let mut namespace_declarations = Vec::new();
+
+                // The copy needs to declare the namespaces which were in scope
+                // for the element in its own document.
+                for ancestor in once(element).chain(element.ancestors()) {
+                    for declaration in &ancestor.data().namespace_declarations {
+                        if !namespace_declarations.iter().any(|(prefix, _)| *prefix == declaration.0) {
+                            namespace_declarations.push(declaration.clone());
+                        }
+                    }
+                }
+
+                ChildNode::Element(self.copy_element(element, parent_index, namespace_declarations))
+            },
+
+            Node::Text(text) => ChildNode::Text(text.to_string()),
+            Node::Comment(comment) => ChildNode::Comment(comment.to_string()),
+            Node::CData(data) => ChildNode::CData(data.to_string()),
+
+            Node::ProcessingInstruction(target, data) =>
+                ChildNode::ProcessingInstruction(target.to_string(), data.map(|data| data.to_string())),
+        }
+    }
+
+    fn copy_element(&mut self, element: Element, parent_index: usize, namespace_declarations: Vec<(Option<String>, String)>) -> usize {
+        let data = element.data();
+        let node_index = self.elements.len();
+
+        self.elements.push(ElementData {
+            tag_name: data.tag_name.clone(),
+            prefix: data.prefix.clone(),
+            namespace_uri: data.namespace_uri.clone(),
+            namespace_declarations,
+            parent: Some(parent_index),
+            children: Vec::new(),
+            child_nodes: Vec::new(),
+            attributes: data.attributes.clone(),
+            source_spans: None,
+        });
+
+        let child_nodes = element.child_nodes()
+            .map(|node| match node {
+                Node::Element(child) => {
+                    let namespace_declarations = child.data().namespace_declarations.clone();
+                    ChildNode::Element(self.copy_element(child, node_index, namespace_declarations))
+                },
+                node => self.copy_node(node, node_index),
+            })
+            .collect();
+
+        self.elements[node_index].child_nodes = child_nodes;
+        update_child_nodes(&mut self.elements[node_index]);
+
+        node_index
+    }
+
+    /// Puts the elements back in document order after the structure of the
+    /// document changed, dropping the elements which were removed. Returns
+    /// the new node index for each of the old ones.
+    pub(super) fn renumber(&mut self) -> Vec<Option<usize>> {
+        self.invalidate_indexes();
+
+        let mut old_elements: Vec<Option<ElementData>> = mem::take(&mut self.elements)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        let mut new_node_indices = vec!(None; old_elements.len());
+        let mut order = Vec::with_capacity(old_elements.len());
+        let mut stack = vec!(0);
+
+        while let Some(node_index) = stack.pop() {
+            new_node_indices[node_index] = Some(order.len());
+            order.push(node_index);
+
+            let data = old_elements[node_index].as_ref().unwrap();
+            stack.extend(data.child_nodes.iter().rev().filter_map(|child_node| match *child_node {
+                ChildNode::Element(child_index) => Some(child_index),
+                _ => None,
+            }));
+        }
+
+        self.elements = order.into_iter()
+            .map(|node_index| {
+                let mut data = old_elements[node_index].take().unwrap();
+
+                data.parent = data.parent.and_then(|parent| new_node_indices[parent]);
+
+                for child_node in &mut data.child_nodes {
+                    if let ChildNode::Element(ref mut child_index) = *child_node {
+                        *child_index = new_node_indices[*child_index].unwrap();
+                    }
+                }
+
+                data.children = data.child_nodes.iter()
+                    .filter_map(|child_node| match *child_node {
+                        ChildNode::Element(child_index) => Some(child_index),
+                        _ => None,
+                    })
+                    .collect();
+
+                data
+            })
+            .collect();
+
+        new_node_indices
+    }
+}
+
+fn split_qualified_name(qualified_name: &str) -> (Option<&str>, &str) {
+    match qualified_name.split_once(':') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, qualified_name),
+    }
+}
+
+/// Updates the child elements of the element from its child nodes, so that
+/// the tree stays navigable until the elements are renumbered.
+fn update_child_nodes(data: &mut ElementData) {
+    data.children = data.child_nodes.iter()
+        .filter_map(|child_node| match *child_node {
+            ChildNode::Element(child_index) => Some(child_index),
+            _ => None,
+        })
+        .collect();
+}
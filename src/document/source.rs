@@ -0,0 +1,283 @@
+use std::ops::Range;
+
+use super::super::{ ChildNode, ElementData };
+
+/// The spans of an element’s markup in the source of a document parsed in
+/// lossless mode, which are written back as-is until the element is changed.
+#[derive(Clone, Debug)]
+pub(crate) struct SourceSpans {
+    pub(crate) start_tag: Range<usize>,
+    pub(crate) tag_name: Range<usize>,
+    pub(crate) attributes: Vec<AttributeSpan>,
+    pub(crate) end_tag: Range<usize>,
+    pub(crate) child_nodes: Vec<NodeSpan>,
+    pub(crate) trailing: Range<usize>,
+    pub(crate) tag_name_modified: bool,
+}
+
+/// The span of an attribute in the start tag, including the whitespace
+/// leading up to it, along with the span of its qualified name. Namespace
+/// declarations have no span of their own, and are kept as-is.
+#[derive(Clone, Debug)]
+pub(crate) struct AttributeSpan {
+    pub(crate) span: Range<usize>,
+    pub(crate) name: Range<usize>,
+    pub(crate) value_modified: bool,
+}
+
+/// The span of a child node in the source, along with the markup without a
+/// node of its own (such as indentation) leading up to it. The node span is
+/// `None` for nodes which were added or replaced since parsing.
+#[derive(Clone, Debug)]
+pub(crate) struct NodeSpan {
+    pub(crate) leading: Range<usize>,
+    pub(crate) node: Option<Range<usize>>,
+}
+
+impl SourceSpans {
+    fn new(start_tag: Range<usize>, end_tag: Range<usize>) -> SourceSpans {
+        SourceSpans {
+            trailing: start_tag.end..start_tag.end,
+            tag_name: start_tag.start..start_tag.start,
+            attributes: Vec::new(),
+            start_tag,
+            end_tag,
+            child_nodes: Vec::new(),
+            tag_name_modified: false,
+        }
+    }
+
+    /// Returns the spans of the element with the given start tag, finding
+    /// the tag name and attributes in it.
+    fn new_element(source: &str, start_tag: Range<usize>) -> SourceSpans {
+        let mut spans = SourceSpans::new(start_tag.clone(), start_tag.end..start_tag.end);
+        let tag = &source[start_tag.clone()];
+        let offset = start_tag.start;
+
+        let name_end = tag.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(tag.len());
+        spans.tag_name = offset + 1..offset + name_end;
+
+        let mut position = name_end;
+
+        loop {
+            let name_start = position + tag[position..].len() - tag[position..].trim_start().len();
+
+            if tag[name_start..].starts_with(['/', '>']) || name_start == tag.len() {
+                break;
+            }
+
+            let name_end = name_start + tag[name_start..].find(|c: char| c.is_whitespace() || c == '=').unwrap_or(tag.len() - name_start);
+            let quote_start = name_start + tag[name_start..].find(['"', '\'']).unwrap_or(tag.len() - name_start);
+            let quote = tag[quote_start..].chars().next().unwrap_or('"');
+            let value_end = tag.get(quote_start + 1..)
+                .and_then(|value| value.find(quote))
+                .map_or(tag.len(), |length| quote_start + length + 2);
+
+            let name = &tag[name_start..name_end];
+
+            if name != "xmlns" && !name.starts_with("xmlns:") {
+                spans.attributes.push(AttributeSpan {
+                    span: offset + position..offset + value_end,
+                    name: offset + name_start..offset + name_end,
+                    value_modified: false,
+                });
+            }
+
+            position = value_end;
+        }
+
+        spans
+    }
+
+    /// Returns the end of the attributes in the start tag, where the closing
+    /// `>` or `/>` starts.
+    pub(crate) fn attributes_end(&self) -> usize {
+        if self.is_empty_tag() {
+            self.start_tag.end - 2
+        } else {
+            self.start_tag.end - 1
+        }
+    }
+
+    /// Returns true if the element was written as an empty-element tag
+    /// (e.g. `<empty/>`) in the source.
+    pub(crate) fn is_empty_tag(&self) -> bool {
+        self.end_tag.start == self.end_tag.end
+    }
+}
+
+impl NodeSpan {
+    /// Returns the span for a node which was added since parsing.
+    pub(crate) fn new_node() -> NodeSpan {
+        NodeSpan {
+            leading: 0..0,
+            node: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TokenKind {
+    Text,
+    CData,
+    Comment,
+    ProcessingInstruction,
+    Declaration,
+    StartTag,
+    EmptyTag,
+    EndTag,
+}
+
+/// Records the source spans of every element and child node, by walking the
+/// markup of the source alongside the already parsed elements. Markup without
+/// a node, such as the XML declaration or whitespace around the top-level
+/// element, is kept as the leading or trailing markup of the nodes around it.
+pub(crate) fn record_source_spans(elements: &mut [ElementData], source: &str) {
+    elements[0].source_spans = Some(Box::new(SourceSpans::new(0..0, source.len()..source.len())));
+
+    // The index, the number of child nodes seen so far and the end of the
+    // last child node of each open element.
+    let mut open_elements: Vec<(usize, usize, usize)> = vec!((0, 0, 0));
+
+    for (kind, span) in Tokens::new(source) {
+        let (current_index, cursor, position) = *open_elements.last().unwrap();
+        let expected_node = elements[current_index].child_nodes.get(cursor).cloned();
+
+        let node_index = match (kind, expected_node) {
+            (TokenKind::Text, Some(ChildNode::Text(_))) |
+            (TokenKind::CData, Some(ChildNode::CData(_))) |
+            (TokenKind::Comment, Some(ChildNode::Comment(_))) |
+            (TokenKind::ProcessingInstruction, Some(ChildNode::ProcessingInstruction(..))) => {
+                push_child_node_span(elements, current_index, position..span.start, span.clone());
+                *open_elements.last_mut().unwrap() = (current_index, cursor + 1, span.end);
+                continue;
+            },
+
+            (TokenKind::StartTag, Some(ChildNode::Element(node_index))) |
+            (TokenKind::EmptyTag, Some(ChildNode::Element(node_index))) => node_index,
+
+            (TokenKind::EndTag, _) if open_elements.len() > 1 => {
+                open_elements.pop();
+
+                let element = &mut elements[current_index];
+                let spans = element.source_spans.as_mut().unwrap();
+                spans.end_tag = span.clone();
+                spans.trailing = position..span.start;
+
+                let (parent_index, parent_cursor, parent_position) = *open_elements.last().unwrap();
+                let start = spans.start_tag.start;
+                push_child_node_span(elements, parent_index, parent_position..start, start..span.end);
+                *open_elements.last_mut().unwrap() = (parent_index, parent_cursor, span.end);
+                continue;
+            },
+
+            _ => continue,
+        };
+
+        elements[node_index].source_spans = Some(Box::new(SourceSpans::new_element(source, span.clone())));
+        open_elements.last_mut().unwrap().1 += 1;
+
+        if kind == TokenKind::StartTag {
+            open_elements.push((node_index, 0, span.end));
+        } else {
+            push_child_node_span(elements, current_index, position..span.start, span.clone());
+            open_elements.last_mut().unwrap().2 = span.end;
+        }
+    }
+
+    let (_, _, position) = open_elements[0];
+    let spans = elements[0].source_spans.as_mut().unwrap();
+    spans.trailing = position..source.len();
+}
+
+fn push_child_node_span(elements: &mut [ElementData], node_index: usize, leading: Range<usize>, node: Range<usize>) {
+    if let Some(ref mut spans) = elements[node_index].source_spans {
+        spans.child_nodes.push(NodeSpan {
+            leading,
+            node: Some(node),
+        });
+    }
+}
+
+/// Splits well-formed XML into spans of markup and text.
+struct Tokens<'s> {
+    source: &'s str,
+    position: usize,
+}
+
+impl<'s> Tokens<'s> {
+    fn new(source: &'s str) -> Tokens<'s> {
+        Tokens {
+            source,
+            position: 0,
+        }
+    }
+
+    fn end_after(&self, terminator: &str) -> usize {
+        self.source[self.position..].find(terminator)
+            .map(|offset| self.position + offset + terminator.len())
+            .unwrap_or(self.source.len())
+    }
+
+    /// Finds the end of a tag or declaration, skipping over quoted values and
+    /// the internal subset of a document type declaration.
+    fn end_of_tag(&self) -> usize {
+        let mut quote = None;
+        let mut depth = 0;
+
+        for (offset, c) in self.source[self.position..].char_indices() {
+            match (quote, c) {
+                (Some(q), c) if q == c => quote = None,
+                (Some(_), _) => { },
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '[') => depth += 1,
+                (None, ']') => depth -= 1,
+                (None, '>') if depth == 0 => return self.position + offset + 1,
+                _ => { },
+            }
+        }
+
+        self.source.len()
+    }
+}
+
+impl<'s> Iterator for Tokens<'s> {
+    type Item = (TokenKind, Range<usize>);
+
+    fn next(&mut self) -> Option<(TokenKind, Range<usize>)> {
+        let rest = &self.source[self.position..];
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (kind, end) = if rest.starts_with("<!--") {
+            (TokenKind::Comment, self.end_after("-->"))
+        } else if rest.starts_with("<![CDATA[") {
+            (TokenKind::CData, self.end_after("]]>"))
+        } else if rest.starts_with("<!") {
+            (TokenKind::Declaration, self.end_of_tag())
+        } else if rest.starts_with("<?xml") && rest[5..].starts_with(|c: char| c.is_whitespace() || c == '?') {
+            (TokenKind::Declaration, self.end_after("?>"))
+        } else if rest.starts_with("<?") {
+            (TokenKind::ProcessingInstruction, self.end_after("?>"))
+        } else if rest.starts_with("</") {
+            (TokenKind::EndTag, self.end_of_tag())
+        } else if rest.starts_with('<') {
+            let end = self.end_of_tag();
+
+            if self.source[..end].ends_with("/>") {
+                (TokenKind::EmptyTag, end)
+            } else {
+                (TokenKind::StartTag, end)
+            }
+        } else {
+            (TokenKind::Text, rest.find('<').map(|offset| self.position + offset).unwrap_or(self.source.len()))
+        };
+
+        let span = self.position..end;
+        self.position = end;
+
+        Some((kind, span))
+    }
+}
@@ -0,0 +1,521 @@
+use std::fmt;
+use std::io;
+use std::iter::once;
+
+use super::{ Document, SourceSpans };
+use super::super::{ Element, ElementData, Node };
+
+/// The quote character used around attribute values when writing XML.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuoteStyle {
+    /// Attribute values are written in double quotes (e.g. `type="simple"`)
+    Double,
+    /// Attribute values are written in single quotes (e.g. `type='simple'`)
+    Single,
+}
+
+/// Options controlling how documents and elements are written as XML.
+///
+/// Documents parsed in lossless mode keep their original formatting, so the
+/// options only apply to the markup of nodes which were changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteOptions {
+    /// Places each child node on its own indented line. Elements containing
+    /// text are written as-is, so that their text is left untouched.
+    pub pretty_print: bool,
+    /// The string used for each level of indentation when pretty printing.
+    pub indent: String,
+    /// The quote character used around attribute values.
+    pub quote_style: QuoteStyle,
+    /// Writes the `<?xml version="1.0" encoding="UTF-8"?>` declaration at the
+    /// start of a document.
+    pub xml_declaration: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            pretty_print: false,
+            indent: "  ".to_string(),
+            quote_style: QuoteStyle::Double,
+            xml_declaration: true,
+        }
+    }
+}
+
+impl WriteOptions {
+    fn pretty() -> WriteOptions {
+        WriteOptions {
+            pretty_print: true,
+            ..WriteOptions::default()
+        }
+    }
+}
+
+struct XmlWriter<'o, W: fmt::Write> {
+    output: W,
+    options: &'o WriteOptions,
+}
+
+impl<'o, W: fmt::Write> XmlWriter<'o, W> {
+    fn write_document(&mut self, document: &Document) -> fmt::Result {
+        let root = document.root();
+
+        if let Some((source, spans)) = original_source(root) {
+            return self.write_original_child_nodes(root, source, spans, 0);
+        }
+
+        let mut is_first_node = true;
+
+        if self.options.xml_declaration {
+            self.output.write_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            is_first_node = false;
+        }
+
+        for node in document.child_nodes() {
+            if !is_first_node {
+                self.output.write_char('\n')?;
+            }
+
+            self.write_node(node, 0)?;
+            is_first_node = false;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the element as the outermost one of the output, declaring the
+    /// namespaces which it inherits from its ancestors on its start tag.
+    fn write_subtree(&mut self, element: Element) -> fmt::Result {
+        let declarations = inherited_namespace_declarations(element);
+        self.write_element_declaring(element, 0, &declarations)
+    }
+
+    fn write_element(&mut self, element: Element, depth: usize) -> fmt::Result {
+        self.write_element_declaring(element, depth, &[])
+    }
+
+    /// Writes the element, adding the namespace declarations to those of its
+    /// start tag.
+    fn write_element_declaring(&mut self, element: Element, depth: usize, declarations: &[(Option<&str>, &str)]) -> fmt::Result {
+        if let Some((source, spans)) = original_source(element) {
+            return self.write_original_element(element, source, spans, depth, declarations);
+        }
+
+        let data = element.data();
+
+        self.write_start_tag(data, declarations)?;
+
+        if data.child_nodes.is_empty() {
+            return self.output.write_str("/>");
+        }
+
+        self.output.write_char('>')?;
+        self.write_child_nodes(element, depth)?;
+        self.write_end_tag(data)
+    }
+
+    /// Writes the element from its original source, writing anew only the
+    /// parts which were changed since it was parsed.
+    fn write_original_element(&mut self, element: Element, source: &str, spans: &SourceSpans, depth: usize, declarations: &[(Option<&str>, &str)]) -> fmt::Result {
+        let data = element.data();
+
+        self.write_original_start_tag(data, source, spans, declarations)?;
+
+        if spans.is_empty_tag() {
+            if data.child_nodes.is_empty() {
+                return self.output.write_str(&source[spans.attributes_end()..spans.start_tag.end]);
+            }
+
+            self.output.write_char('>')?;
+        } else {
+            self.output.write_str(&source[spans.attributes_end()..spans.start_tag.end])?;
+        }
+
+        self.write_original_child_nodes(element, source, spans, depth + 1)?;
+
+        if spans.tag_name_modified || spans.is_empty_tag() {
+            self.write_end_tag(data)
+        } else {
+            self.output.write_str(&source[spans.end_tag.clone()])
+        }
+    }
+
+    /// Writes the start tag of the element from its original source, up to
+    /// the closing `>` or `/>`. Only the tag name and attributes which were
+    /// changed are written anew, and added namespace declarations and
+    /// attributes follow the original ones.
+    fn write_original_start_tag(&mut self, data: &ElementData, source: &str, spans: &SourceSpans, declarations: &[(Option<&str>, &str)]) -> fmt::Result {
+        self.output.write_str(&source[spans.start_tag.start..spans.tag_name.start])?;
+
+        if spans.tag_name_modified {
+            self.write_qualified_name(data.prefix.as_deref(), &data.tag_name)?;
+        } else {
+            self.output.write_str(&source[spans.tag_name.clone()])?;
+        }
+
+        let mut position = spans.tag_name.end;
+
+        for attribute_span in &spans.attributes {
+            self.output.write_str(&source[position..attribute_span.span.start])?;
+            position = attribute_span.span.end;
+
+            let qualified_name = &source[attribute_span.name.clone()];
+
+            match data.attributes.iter().find(|attribute| attribute.has_qualified_name(qualified_name)) {
+                Some(attribute) if attribute_span.value_modified => {
+                    self.output.write_str(&source[attribute_span.span.start..attribute_span.name.end])?;
+                    self.write_attribute_value(attribute.value())?;
+                },
+
+                Some(_) => self.output.write_str(&source[attribute_span.span.clone()])?,
+
+                // the attribute was removed
+                None => { },
+            }
+        }
+
+        let remaining_markup = &source[position..spans.attributes_end()];
+        let trimmed_markup = remaining_markup.trim_end();
+        self.output.write_str(trimmed_markup)?;
+
+        self.write_namespace_declarations(declarations.iter().cloned())?;
+
+        let added_attributes = data.attributes.iter().filter(|attribute| {
+            !spans.attributes.iter().any(|attribute_span| attribute.has_qualified_name(&source[attribute_span.name.clone()]))
+        });
+
+        for attribute in added_attributes {
+            self.output.write_char(' ')?;
+            self.write_qualified_name(attribute.prefix(), attribute.name())?;
+            self.write_attribute_value(attribute.value())?;
+        }
+
+        self.output.write_str(&remaining_markup[trimmed_markup.len()..])
+    }
+
+    /// Writes the child nodes of the element from the original source,
+    /// along with the markup between them which has no node of its own.
+    fn write_original_child_nodes(&mut self, element: Element, source: &str, spans: &SourceSpans, child_depth: usize) -> fmt::Result {
+        for (position, node) in element.child_nodes().enumerate() {
+            let span = match spans.child_nodes.get(position) {
+                Some(span) => span,
+                None => {
+                    self.write_node(node, child_depth)?;
+                    continue;
+                },
+            };
+
+            self.output.write_str(&source[span.leading.clone()])?;
+
+            match (node, &span.node) {
+                (Node::Element(child), _) => self.write_element(child, child_depth)?,
+                (_, Some(node_span)) => self.output.write_str(&source[node_span.clone()])?,
+                (node, &None) => self.write_node(node, child_depth)?,
+            }
+        }
+
+        self.output.write_str(&source[spans.trailing.clone()])
+    }
+
+    fn write_start_tag(&mut self, data: &ElementData, declarations: &[(Option<&str>, &str)]) -> fmt::Result {
+        self.output.write_char('<')?;
+        self.write_qualified_name(data.prefix.as_deref(), &data.tag_name)?;
+
+        let own_declarations = data.namespace_declarations.iter().map(|(prefix, uri)| (prefix.as_deref(), uri.as_str()));
+        self.write_namespace_declarations(own_declarations.chain(declarations.iter().cloned()))?;
+
+        for attribute in &data.attributes {
+            self.output.write_char(' ')?;
+            self.write_qualified_name(attribute.prefix(), attribute.name())?;
+            self.write_attribute_value(attribute.value())?;
+        }
+
+        Ok(())
+    }
+
+    fn write_namespace_declarations<'d, I: Iterator<Item=(Option<&'d str>, &'d str)>>(&mut self, declarations: I) -> fmt::Result {
+        for (prefix, uri) in declarations {
+            self.output.write_char(' ')?;
+            self.write_qualified_name(Some("xmlns"), prefix.unwrap_or(""))?;
+            self.write_attribute_value(uri)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_end_tag(&mut self, data: &ElementData) -> fmt::Result {
+        self.output.write_str("</")?;
+        self.write_qualified_name(data.prefix.as_deref(), &data.tag_name)?;
+        self.output.write_char('>')
+    }
+
+    /// Writes the child nodes of the element without indentation, as for
+    /// the inner XML of the element.
+    fn write_inner(&mut self, element: Element) -> fmt::Result {
+        if let Some((source, spans)) = original_source(element) {
+            return self.write_original_child_nodes(element, source, spans, 0);
+        }
+
+        for node in element.child_nodes() {
+            self.write_node(node, 0)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_child_nodes(&mut self, element: Element, depth: usize) -> fmt::Result {
+        let indents_children = self.options.pretty_print && element.child_nodes().all(|node| {
+            match node {
+                Node::Text(text) => text.trim().is_empty(),
+                Node::CData(_) => false,
+                _ => true,
+            }
+        });
+
+        if !indents_children {
+            for node in element.child_nodes() {
+                self.write_node(node, depth + 1)?;
+            }
+
+            return Ok(());
+        }
+
+        for node in element.child_nodes() {
+            if let Node::Text(_) = node {
+                continue;
+            }
+
+            self.write_line_break(depth + 1)?;
+            self.write_node(node, depth + 1)?;
+        }
+
+        self.write_line_break(depth)
+    }
+
+    fn write_node(&mut self, node: Node, depth: usize) -> fmt::Result {
+        match node {
+            Node::Element(element) =>
+                self.write_element(element, depth),
+
+            Node::Text(text) =>
+                self.write_escaped(text, None),
+
+            Node::Comment(comment) =>
+                write!(self.output, "<!--{}-->", comment),
+
+            Node::CData(data) =>
+                write!(self.output, "<![CDATA[{}]]>", data.replace("]]>", "]]]]><![CDATA[>")),
+
+            Node::ProcessingInstruction(target, Some(data)) =>
+                write!(self.output, "<?{} {}?>", target, data),
+
+            Node::ProcessingInstruction(target, None) =>
+                write!(self.output, "<?{}?>", target),
+        }
+    }
+
+    fn write_line_break(&mut self, depth: usize) -> fmt::Result {
+        self.output.write_char('\n')?;
+
+        for _ in 0..depth {
+            self.output.write_str(&self.options.indent)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_qualified_name(&mut self, prefix: Option<&str>, name: &str) -> fmt::Result {
+        match prefix {
+            Some(prefix) if name.is_empty() => self.output.write_str(prefix),
+            Some(prefix) => write!(self.output, "{}:{}", prefix, name),
+            None => self.output.write_str(name),
+        }
+    }
+
+    fn write_attribute_value(&mut self, value: &str) -> fmt::Result {
+        let quote = match self.options.quote_style {
+            QuoteStyle::Double => '"',
+            QuoteStyle::Single => '\'',
+        };
+
+        self.output.write_char('=')?;
+        self.output.write_char(quote)?;
+        self.write_escaped(value, Some(quote))?;
+        self.output.write_char(quote)
+    }
+
+    /// Writes the text with the markup characters escaped, along with the
+    /// quote character and whitespace which would otherwise be normalized
+    /// when the text is an attribute value.
+    fn write_escaped(&mut self, text: &str, quote: Option<char>) -> fmt::Result {
+        for c in text.chars() {
+            match c {
+                '&' => self.output.write_str("&amp;")?,
+                '<' => self.output.write_str("&lt;")?,
+                '>' => self.output.write_str("&gt;")?,
+                '\r' => self.output.write_str("&#13;")?,
+                '"' if quote == Some('"') => self.output.write_str("&quot;")?,
+                '\'' if quote == Some('\'') => self.output.write_str("&apos;")?,
+                '\n' if quote.is_some() => self.output.write_str("&#10;")?,
+                '\t' if quote.is_some() => self.output.write_str("&#9;")?,
+                c => self.output.write_char(c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the original source of the element’s document along with the
+/// spans of the element, if the document was parsed in lossless mode.
+fn original_source<'a>(element: Element<'a>) -> Option<(&'a str, &'a SourceSpans)> {
+    match (&element.document.source, &element.data().source_spans) {
+        (Some(source), Some(spans)) => Some((source, spans)),
+        _ => None,
+    }
+}
+
+/// Returns the namespace declarations which the element and its descendants
+/// rely on but which are made by its ancestors, so that the element can be
+/// written on its own.
+fn inherited_namespace_declarations(element: Element<'_>) -> Vec<(Option<&str>, &str)> {
+    let mut declarations = Vec::new();
+    collect_inherited_namespace_declarations(element, &mut Vec::new(), &mut declarations);
+    declarations
+}
+
+fn collect_inherited_namespace_declarations<'a>(element: Element<'a>, declared_prefixes: &mut Vec<Option<&'a str>>, declarations: &mut Vec<(Option<&'a str>, &'a str)>) {
+    let data = element.data();
+    let number_of_declared_prefixes = declared_prefixes.len();
+
+    declared_prefixes.extend(data.namespace_declarations.iter().map(|(prefix, _)| prefix.as_deref()));
+
+    let names = once((element.prefix(), element.namespace_uri()))
+        .chain(data.attributes.iter()
+            .filter(|attribute| attribute.prefix().is_some())
+            .map(|attribute| (attribute.prefix(), attribute.namespace_uri())));
+
+    for (prefix, namespace_uri) in names {
+        if let Some(namespace_uri) = namespace_uri {
+            // The `xml` prefix is bound without being declared.
+            let is_declared = prefix == Some("xml") || declared_prefixes.contains(&prefix) ||
+                declarations.iter().any(|&(declared_prefix, _)| declared_prefix == prefix);
+
+            if !is_declared {
+                declarations.push((prefix, namespace_uri));
+            }
+        }
+    }
+
+    for child in element.children_iter() {
+        collect_inherited_namespace_declarations(child, declared_prefixes, declarations);
+    }
+
+    declared_prefixes.truncate(number_of_declared_prefixes);
+}
+
+/// Adapts an `io::Write` to the `fmt::Write` used by the `XmlWriter`, keeping
+/// hold of the underlying error.
+struct IoAdapter<W: io::Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<W> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.inner.write_all(string.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
+fn write_io<W, F>(writer: W, options: &WriteOptions, write: F) -> io::Result<()>
+    where W: io::Write, F: FnOnce(&mut XmlWriter<&mut IoAdapter<W>>) -> fmt::Result {
+    let mut adapter = IoAdapter {
+        inner: writer,
+        error: None,
+    };
+
+    let result = write(&mut XmlWriter {
+        output: &mut adapter,
+        options,
+    });
+
+    match (result, adapter.error) {
+        (Ok(()), _) => Ok(()),
+        (Err(_), Some(error)) => Err(error),
+        (Err(_), None) => Err(io::Error::other("formatter error")),
+    }
+}
+
+impl<'a> Element<'a> {
+    /// Returns the XML for the element, including its own tags. Namespaces
+    /// used in the element but declared by its ancestors are declared on its
+    /// start tag, so that the XML can be parsed on its own.
+    pub fn outer_xml(&self) -> String {
+        self.to_string()
+    }
+
+    /// Returns the XML for the element’s child nodes, excluding its own tags.
+    pub fn inner_xml(&self) -> String {
+        let options = WriteOptions::default();
+        let mut string = String::new();
+
+        XmlWriter {
+            output: &mut string,
+            options: &options,
+        }.write_inner(*self).unwrap();
+
+        string
+    }
+
+    /// Writes the XML for the element, including its own tags, to the writer,
+    /// just like `outer_xml`. The XML declaration option is ignored for
+    /// elements.
+    pub fn write_to<W: io::Write>(&self, writer: W, options: &WriteOptions) -> io::Result<()> {
+        write_io(writer, options, |xml_writer| xml_writer.write_subtree(*self))
+    }
+}
+
+impl Document {
+    /// Writes the document as XML to the writer.
+    pub fn write_to<W: io::Write>(&self, writer: W, options: &WriteOptions) -> io::Result<()> {
+        write_io(writer, options, |xml_writer| xml_writer.write_document(self))
+    }
+}
+
+/// Writes the element as XML. The alternate flag (`{:#}`) pretty prints the
+/// element.
+impl<'a> fmt::Display for Element<'a> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let options = if formatter.alternate() {
+            WriteOptions::pretty()
+        } else {
+            WriteOptions::default()
+        };
+
+        XmlWriter {
+            output: formatter,
+            options: &options,
+        }.write_subtree(*self)
+    }
+}
+
+/// Writes the document as XML, including the XML declaration. The alternate
+/// flag (`{:#}`) pretty prints the document.
+impl fmt::Display for Document {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let options = if formatter.alternate() {
+            WriteOptions::pretty()
+        } else {
+            WriteOptions::default()
+        };
+
+        XmlWriter {
+            output: formatter,
+            options: &options,
+        }.write_document(self)
+    }
+}
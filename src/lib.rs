@@ -17,7 +17,7 @@
 //!   assert_eq!(item_count, 2);
 //!
 //!   let item_titles = document.select_all("item > title").unwrap()
-//!     .map(|element| element.text().clone())
+//!     .map(|element| element.text().into_owned())
 //!     .collect::<Vec<String>>()
 //!     .join(", ");
 //!   assert_eq!(item_titles, "Another Sample, Other Sample");
@@ -28,25 +28,135 @@
 
 extern crate xml;
 
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
 mod selector;
 mod document;
+mod matching;
+
+#[cfg(feature = "rayon")]
+mod parallel;
 
-pub use self::document::{Document, DocumentError};
-pub use self::selector::{ CompoundSelector, MatchType, Scope, Selector, UnexpectedTokenError };
+pub use self::document::{ BuildError, Document, DocumentBuilder, DocumentError, ElementBuilder, ElementMut, MutationError, ParseOptions, QuoteStyle, SelectionMut, SyntaxError, SyntaxErrorKind, WriteOptions };
+pub use self::selector::{ CaseSensitivity, CompoundSelector, MatchType, NamespacePrefix, Nth, PseudoClass, Scope, Selector, SelectorList, UnexpectedTokenError };
 
-use std::rc::Rc;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
 use std::iter::{ empty, once };
-use std::marker::PhantomData;
 use std::collections::HashMap;
+use std::slice;
+use std::sync::Arc;
+
+use self::document::SourceSpans;
 
 /// Represents a single element in the DOM tree.
-#[derive(Clone, Debug)]
-pub struct Element {
+///
+/// Elements are lightweight handles into the `Document` which owns them, so
+/// they can be freely copied and used to navigate to related elements.
+#[derive(Clone, Copy)]
+pub struct Element<'a> {
+    document: &'a Document,
     node_index: usize,
-    tag_name: String,
-    children: Option<Vec<Rc<Element>>>,
-    attr_map: HashMap<String, String>,
-    text: String,
+}
+
+/// The data stored in the `Document` for each element. Tag names, attribute
+/// names and namespace URIs are interned when parsing, so that the elements
+/// of a document share a single copy of each.
+#[derive(Clone, Debug)]
+struct ElementData {
+    tag_name: Arc<str>,
+    prefix: Option<Arc<str>>,
+    namespace_uri: Option<Arc<str>>,
+    namespace_declarations: Vec<(Option<String>, String)>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    child_nodes: Vec<ChildNode>,
+    attributes: Vec<Attribute>,
+    source_spans: Option<Box<SourceSpans>>,
+}
+
+/// The data stored in the `Document` for each child node of an element, in
+/// document order. Child elements refer to their index in the `Document`.
+#[derive(Clone, Debug)]
+enum ChildNode {
+    Element(usize),
+    Text(String),
+    Comment(String),
+    CData(String),
+    ProcessingInstruction(String, Option<String>),
+}
+
+/// Represents a single node in the DOM tree, which can be an element or the
+/// text, comments, CDATA sections and processing instructions between
+/// elements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Node<'a> {
+    /// An element (e.g. `<title>Sample</title>`)
+    Element(Element<'a>),
+    /// A run of text, with entities already decoded (e.g. `a &amp; b`)
+    Text(&'a str),
+    /// A comment, without the delimiters (e.g. `<!-- comment -->`)
+    Comment(&'a str),
+    /// A CDATA section, without the delimiters (e.g. `<![CDATA[x < y]]>`)
+    CData(&'a str),
+    /// A processing instruction with its target and optional data (e.g.
+    /// `<?xml-stylesheet href="style.css"?>`)
+    ProcessingInstruction(&'a str, Option<&'a str>),
+}
+
+impl<'a> Node<'a> {
+    /// Returns the element if the node is an element.
+    pub fn as_element(&self) -> Option<Element<'a>> {
+        match *self {
+            Node::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+}
+
+/// Represents an attribute of an element.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    name: Arc<str>,
+    prefix: Option<Arc<str>>,
+    namespace_uri: Option<Arc<str>>,
+    value: String,
+}
+
+impl Attribute {
+    /// Returns the local name of the attribute, without the namespace prefix.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the namespace prefix of the attribute, if any.
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    /// Returns the namespace URI of the attribute, if any.
+    pub fn namespace_uri(&self) -> Option<&str> {
+        self.namespace_uri.as_deref()
+    }
+
+    /// Returns the value of the attribute.
+    pub fn value(&self) -> &String {
+        &self.value
+    }
+
+    /// Returns true if the attribute has the given qualified name (e.g.
+    /// `href` or `xlink:href`).
+    fn has_qualified_name(&self, qualified_name: &str) -> bool {
+        match self.prefix {
+            Some(ref prefix) =>
+                qualified_name.split_once(':') == Some((&**prefix, &*self.name)),
+
+            None =>
+                qualified_name == &*self.name,
+        }
+    }
 }
 
 /// Errors which can be returned when performing a select operation.
@@ -58,79 +168,72 @@ pub enum SelectError {
     NoMatchError,
 }
 
-struct UniqueElements<'a, I: Iterator<Item=&'a Element> + 'a> {
-    next_index: usize,
-    inner_iter: I,
-    phantom_data: PhantomData<&'a i32>,
+impl fmt::Display for SelectError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SelectError::ParseError(ref error) => write!(formatter, "invalid selector: {}", error),
+            SelectError::NoMatchError => formatter.write_str("no element matches the selector"),
+        }
+    }
 }
 
-impl<'a, I: Iterator<Item=&'a Element>> Iterator for UniqueElements<'a, I> {
-    type Item = &'a Element;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.inner_iter.next() {
-                Some(element) if element.node_index < self.next_index => {
-                    println!("SKIPPED");
-                    // do nothing
-                },
+impl Error for SelectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SelectError::ParseError(ref error) => Some(error),
+            SelectError::NoMatchError => None,
+        }
+    }
+}
 
-                Some(element) => {
-                    self.next_index = element.node_index + 1;
-                    return Some(element);
-                },
+impl<'a> Element<'a> {
+    fn data(&self) -> &'a ElementData {
+        &self.document.elements[self.node_index]
+    }
 
-                None => return None,
-            }
+    fn element_at(&self, node_index: usize) -> Element<'a> {
+        Element {
+            document: self.document,
+            node_index,
         }
     }
-}
 
-impl Element {
     /// Searches the elements children for elements matching the given CSS
-    /// selector.
-    pub fn select_all<'a>(&'a self, selector: &str) -> Result<Box<Iterator<Item=&'a Element> + 'a>, SelectError> {
-        CompoundSelector::parse(selector)
-            .map_err(|err| SelectError::ParseError(err))
-            .and_then(|compound_selectors| {
-                let initial_iterator: Box<Iterator<Item=&'a Element>> = Box::new(once(self));
-
-                let iterator = compound_selectors.into_iter()
-                    .fold(initial_iterator, |iter, compound_selector| {
-                        let scope = compound_selector.scope;
-
-                        let children_iter = iter
-                             .flat_map(move |child| {
-                                 match scope {
-                                     Scope::IndirectChild => child.children_deep_iter(),
-                                     Scope::DirectChild => child.children_iter(),
-                                 }
-                             });
-
-                        let matching_children_iter = children_iter
-                            .filter_map(move |child| {
-                                if child.matches(&compound_selector) {
-                                    Some(child)
-                                } else {
-                                    None
-                                }
-                            });
-
-                        let unique_children_iter = UniqueElements {
-                            next_index: 0,
-                            inner_iter: matching_children_iter,
-                            phantom_data: PhantomData,
-                        };
-
-                        Box::new(unique_children_iter)
-                    });
-
-                return Ok(iterator);
+    /// selector. Comma-separated selector lists (e.g. `title, ref`) return
+    /// the union of all matches in document order, without duplicates.
+    pub fn select_all(&self, selector: &str) -> Result<Box<dyn Iterator<Item=Element<'a>> + 'a>, SelectError> {
+        SelectorList::parse(selector)
+            .map_err(SelectError::ParseError)
+            .map(|selector_list| self.select_all_list(selector_list.selectors))
+    }
+
+    /// Just like `select_all`, but namespace prefixes in the selector (e.g.
+    /// `svg|rect`) are resolved to namespace URIs using the given map from
+    /// prefix to URI. Prefixes missing from the map match the prefix used in
+    /// the document.
+    pub fn select_all_with_namespaces(&self, selector: &str, namespaces: &HashMap<String, String>) -> Result<Box<dyn Iterator<Item=Element<'a>> + 'a>, SelectError> {
+        SelectorList::parse(selector)
+            .map_err(SelectError::ParseError)
+            .map(|mut selector_list| {
+                selector_list.resolve_namespaces(namespaces);
+
+                self.select_all_list(selector_list.selectors)
             })
     }
 
+    /// Just like `select_all`, but with a selector list which has already
+    /// been parsed, avoiding parsing the selector again on every call.
+    pub fn select_all_compiled<'s>(&self, selector_list: &'s SelectorList) -> Box<dyn Iterator<Item=Element<'a>> + 's> where 'a: 's {
+        self.select_all_list(selector_list.selectors.as_slice())
+    }
+
+    /// Just like `select_all_compiled` but only returns the first match.
+    pub fn select_compiled(&self, selector_list: &SelectorList) -> Option<Element<'a>> {
+        self.select_all_compiled(selector_list).next()
+    }
+
     /// Just like `select_all` but only returns the first match.
-    pub fn select<'a>(&'a self, selector: &str) -> Result<&'a Element, SelectError> {
+    pub fn select(&self, selector: &str) -> Result<Element<'a>, SelectError> {
         self.select_all(selector).and_then(|mut iterator| {
             if let Some(element) = iterator.next() {
                 Ok(element)
@@ -140,66 +243,307 @@ impl Element {
         })
     }
 
+    /// Just like `select_all_with_namespaces` but only returns the first
+    /// match.
+    pub fn select_with_namespaces(&self, selector: &str, namespaces: &HashMap<String, String>) -> Result<Element<'a>, SelectError> {
+        self.select_all_with_namespaces(selector, namespaces).and_then(|mut iterator| {
+            if let Some(element) = iterator.next() {
+                Ok(element)
+            } else {
+                Err(SelectError::NoMatchError)
+            }
+        })
+    }
+
     /// Returns an iterator over the element’s direct children.
-    pub fn children_iter<'a>(&'a self) -> Box<Iterator<Item=&'a Element> + 'a> {
-        if let Some(ref children) = self.children {
-            Box::new(children.iter().map(|node| -> &'a Element { node }))
-        } else {
-            Box::new(empty::<&'a Element>())
-        }
+    pub fn children_iter(&self) -> Box<dyn Iterator<Item=Element<'a>> + 'a> {
+        let element = *self;
+
+        Box::new(self.data().children.iter().map(move |&node_index| element.element_at(node_index)))
+    }
+
+    /// Returns an iterator over all of the element’s child nodes in document
+    /// order, including text, comments, CDATA sections and processing
+    /// instructions.
+    pub fn child_nodes(&self) -> Box<dyn Iterator<Item=Node<'a>> + 'a> {
+        let element = *self;
+
+        Box::new(self.data().child_nodes.iter().map(move |child_node| {
+            match *child_node {
+                ChildNode::Element(node_index) => Node::Element(element.element_at(node_index)),
+                ChildNode::Text(ref text) => Node::Text(text),
+                ChildNode::Comment(ref comment) => Node::Comment(comment),
+                ChildNode::CData(ref data) => Node::CData(data),
+                ChildNode::ProcessingInstruction(ref target, ref data) => Node::ProcessingInstruction(target, data.as_deref()),
+            }
+        }))
     }
 
     /// Returns an iterator over all the element’s children, including indirect
     /// child elements.
-    pub fn children_deep_iter<'a>(&'a self) -> Box<Iterator<Item=&'a Element> + 'a> {
-        let iterator = self.children_iter()
-            .flat_map(|child| once(child).chain(child.children_deep_iter()));
+    pub fn children_deep_iter(&self) -> Box<dyn Iterator<Item=Element<'a>> + 'a> {
+        Box::new(self.descendants())
+    }
+
+    /// Returns an iterator over all the element’s child nodes in document
+    /// order, including the nodes within child elements.
+    pub fn descendant_nodes(&self) -> Box<dyn Iterator<Item=Node<'a>> + 'a> {
+        let iterator = self.child_nodes()
+            .flat_map(|node| {
+                let descendants = match node {
+                    Node::Element(child) => child.descendant_nodes(),
+                    _ => Box::new(empty()),
+                };
+
+                once(node).chain(descendants)
+            });
+
+        Box::new(iterator)
+    }
+
+    /// Returns an iterator over the text of all the text nodes and CDATA
+    /// sections within the element and its descendants, in document order.
+    pub fn text_descendants(&self) -> Box<dyn Iterator<Item=&'a str> + 'a> {
+        let iterator = self.descendant_nodes()
+            .filter_map(|node| {
+                match node {
+                    Node::Text(text) | Node::CData(text) => Some(text),
+                    _ => None,
+                }
+            });
 
         Box::new(iterator)
     }
 
+    /// Returns the element’s parent, or `None` for the document’s top-level
+    /// element.
+    pub fn parent(&self) -> Option<Element<'a>> {
+        match self.data().parent {
+            Some(0) | None => None,
+            Some(parent_index) => Some(self.element_at(parent_index)),
+        }
+    }
+
+    /// Returns an iterator over the element’s ancestors, starting with its
+    /// parent and ending with the document’s top-level element.
+    pub fn ancestors(&self) -> Box<dyn Iterator<Item=Element<'a>> + 'a> {
+        let mut next_ancestor = self.parent();
+
+        Box::new(std::iter::from_fn(move || {
+            let ancestor = next_ancestor;
+            next_ancestor = ancestor.and_then(|element| element.parent());
+
+            ancestor
+        }))
+    }
+
+    /// Returns the first element matching the given CSS selector, starting
+    /// with the element itself and then its ancestors.
+    pub fn closest(&self, selector: &str) -> Result<Element<'a>, SelectError> {
+        SelectorList::parse(selector)
+            .map_err(SelectError::ParseError)
+            .and_then(|selector_list| {
+                once(*self).chain(self.ancestors())
+                    .find(|element| element.matches(&selector_list))
+                    .ok_or(SelectError::NoMatchError)
+            })
+    }
+
+    /// Returns the sibling immediately following the element, if any.
+    pub fn next_sibling(&self) -> Option<Element<'a>> {
+        self.following_siblings().next()
+    }
+
+    /// Returns the sibling immediately preceding the element, if any.
+    pub fn previous_sibling(&self) -> Option<Element<'a>> {
+        self.preceding_siblings().next()
+    }
+
+    /// Returns an iterator over the siblings preceding the element, starting
+    /// with the closest sibling.
+    pub fn preceding_siblings(&self) -> Box<dyn Iterator<Item=Element<'a>> + 'a> {
+        if let Some(parent_index) = self.data().parent {
+            let element = *self;
+            let siblings = &self.document.elements[parent_index].children;
+            let position = siblings.iter().position(|&node_index| node_index == self.node_index).unwrap();
+
+            Box::new(siblings[..position].iter().rev().map(move |&node_index| element.element_at(node_index)))
+        } else {
+            Box::new(empty())
+        }
+    }
+
+    /// Returns an iterator over the siblings following the element, in
+    /// document order.
+    pub fn following_siblings(&self) -> Box<dyn Iterator<Item=Element<'a>> + 'a> {
+        if let Some(parent_index) = self.data().parent {
+            let element = *self;
+            let siblings = &self.document.elements[parent_index].children;
+            let position = siblings.iter().position(|&node_index| node_index == self.node_index).unwrap();
+
+            Box::new(siblings[position + 1..].iter().map(move |&node_index| element.element_at(node_index)))
+        } else {
+            Box::new(empty())
+        }
+    }
+
     /// Returns the size of the DOM subtree, including the current element.
     pub fn subtree_size(&self) -> usize {
-        if let Some(ref children) = self.children {
-            children.iter().fold(1, |subtotal, child| child.subtree_size() + subtotal)
-        } else {
-            1
+        self.children_iter().fold(1, |subtotal, child| child.subtree_size() + subtotal)
+    }
+
+    /// Returns the name of the element’s tag, without the namespace prefix.
+    pub fn tag_name(&self) -> &'a str {
+        &self.data().tag_name
+    }
+
+    /// Returns the namespace prefix of the element’s tag, if any.
+    pub fn prefix(&self) -> Option<&'a str> {
+        self.data().prefix.as_deref()
+    }
+
+    /// Returns the namespace URI of the element, if any.
+    pub fn namespace_uri(&self) -> Option<&'a str> {
+        self.data().namespace_uri.as_deref()
+    }
+
+    /// Returns the value of the element attribute if found. Attributes in a
+    /// namespace are looked up by their qualified name (e.g. `xlink:href`).
+    pub fn attr(&self, attr_name: &str) -> Option<&'a String> {
+        self.data().attributes.iter()
+            .find(|attribute| attribute.has_qualified_name(attr_name))
+            .map(|attribute| &attribute.value)
+    }
+
+    /// Returns the value of the element attribute with the given namespace
+    /// URI and local name if found.
+    pub fn attr_ns(&self, namespace_uri: Option<&str>, attr_name: &str) -> Option<&'a String> {
+        self.data().attributes.iter()
+            .find(|attribute| attribute.namespace_uri() == namespace_uri && &*attribute.name == attr_name)
+            .map(|attribute| &attribute.value)
+    }
+
+    /// Returns an iterator over the element’s attributes, in the order they
+    /// appear in the document.
+    pub fn attributes(&self) -> slice::Iter<'a, Attribute> {
+        self.data().attributes.iter()
+    }
+
+    /// Returns the text contained directly within the element, excluding CDATA
+    /// sections. Use `child_nodes` to see how the text is interleaved with
+    /// child elements. The text is only copied if it is split across several
+    /// text nodes.
+    pub fn text(&self) -> Cow<'a, str> {
+        let mut texts = self.data().child_nodes.iter()
+            .filter_map(|child_node| match *child_node {
+                ChildNode::Text(ref text) => Some(text.as_str()),
+                _ => None,
+            });
+
+        match (texts.next(), texts.next()) {
+            (None, _) => Cow::Borrowed(""),
+            (Some(text), None) => Cow::Borrowed(text),
+            (Some(first), Some(second)) => Cow::Owned(once(first).chain(once(second)).chain(texts).collect()),
         }
     }
 
-    /// Returns the name of the element’s tag.
-    pub fn tag_name(&self) -> &str {
-        &self.tag_name
+    /// Returns all the text within the element and its descendants, including
+    /// CDATA sections, like the DOM’s `textContent`.
+    pub fn text_content(&self) -> String {
+        self.text_descendants().collect()
+    }
+
+    /// Just like `text_content`, but with runs of whitespace collapsed into a
+    /// single space and leading and trailing whitespace removed.
+    pub fn normalized_text(&self) -> String {
+        normalize_whitespace(&self.text_content())
     }
 
-    /// Returns the value of the element attribute if found.
-    pub fn attr(&self, attr_name: &str) -> Option<&String> {
-        self.attr_map.get(attr_name)
+    /// Returns the text directly within the element, including CDATA sections
+    /// but excluding the text of descendants, with runs of whitespace
+    /// collapsed into a single space and leading and trailing whitespace
+    /// removed.
+    pub fn own_text(&self) -> String {
+        let text: String = self.child_nodes()
+            .filter_map(|node| {
+                match node {
+                    Node::Text(text) | Node::CData(text) => Some(text),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        normalize_whitespace(&text)
     }
 
-    /// Returns the text contained within the element.
-    pub fn text(&self) -> &String {
-        &self.text
+    /// Returns true if the element matches any of the selectors in the list,
+    /// with its ancestors and siblings matching as required by the
+    /// combinators.
+    pub fn matches(&self, selector_list: &SelectorList) -> bool {
+        self.matches_list(&selector_list.selectors, None)
     }
 
-    /// Returns true if the element matches the given selector.
-    pub fn matches(&self, compound_selector: &CompoundSelector) -> bool {
+    /// Returns true if the element matches the given compound selector,
+    /// ignoring its scope.
+    pub fn matches_compound(&self, compound_selector: &CompoundSelector) -> bool {
         compound_selector.parts.iter().all(|part| {
-            match part {
-                &Selector::TagName(ref name) =>
+            match *part {
+                Selector::TagName(ref name) =>
                     self.tag_name() == name,
 
-                &Selector::Id(ref id) =>
+                Selector::Universal =>
+                    true,
+
+                Selector::Namespace(ref namespace_prefix) =>
+                    namespace_prefix.matches(self.prefix(), self.namespace_uri()),
+
+                Selector::Id(ref id) =>
                     self.attr("id") == Some(id),
 
-                &Selector::Attribute(ref attr, MatchType::Equals, ref value) =>
-                    self.attr(attr) == Some(value),
+                Selector::Class(ref class) =>
+                    self.attr("class").is_some_and(|classes| {
+                        classes.split_whitespace().any(|word| word == class)
+                    }),
+
+                Selector::Attribute(ref namespace_prefix, ref attr, match_type, ref value, case_sensitivity) =>
+                    self.attributes().any(|attribute| {
+                        *attribute.name == **attr &&
+                            namespace_prefix.as_ref().map_or(attribute.namespace_uri.is_none(), |namespace_prefix| {
+                                namespace_prefix.matches(attribute.prefix(), attribute.namespace_uri())
+                            }) &&
+                            match_type.matches(&attribute.value, value, case_sensitivity)
+                    }),
+
+                Selector::PseudoClass(ref pseudo_class) =>
+                    self.matches_pseudo_class(pseudo_class),
             }
         })
     }
-    
+
     /// Returns the node index for the element.
     pub fn node_index(&self) -> usize {
         self.node_index
     }
 }
+
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+impl<'a> PartialEq for Element<'a> {
+    fn eq(&self, other: &Element<'a>) -> bool {
+        std::ptr::eq(self.document, other.document) && self.node_index == other.node_index
+    }
+}
+
+impl<'a> Eq for Element<'a> {}
+
+impl<'a> fmt::Debug for Element<'a> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("Element")
+            .field("node_index", &self.node_index)
+            .field("tag_name", &self.tag_name())
+            .field("attributes", &self.data().attributes)
+            .finish()
+    }
+}
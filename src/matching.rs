@@ -0,0 +1,326 @@
+use std::borrow::Borrow;
+use std::iter::once;
+use std::slice;
+
+use super::{ ChildNode, CompoundSelector, Document, Element, PseudoClass, Scope };
+
+/// Iterates over the descendants of an element in document order, using an
+/// explicit stack so that each step takes constant time however deep the
+/// tree is.
+pub(crate) struct Descendants<'a> {
+    document: &'a Document,
+    stack: Vec<slice::Iter<'a, usize>>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = Element<'a>;
+
+    fn next(&mut self) -> Option<Element<'a>> {
+        loop {
+            let node_index = match self.stack.last_mut()?.next() {
+                Some(&node_index) => node_index,
+
+                None => {
+                    self.stack.pop();
+                    continue;
+                },
+            };
+
+            self.stack.push(self.document.elements[node_index].children.iter());
+
+            return Some(Element {
+                document: self.document,
+                node_index,
+            });
+        }
+    }
+}
+
+/// Returns true if any of the complex selectors starts with a sibling
+/// combinator (e.g. `+ ref`), so that it can match following siblings of the
+/// element it is relative to.
+pub(crate) fn includes_siblings(selector_list: &[Vec<CompoundSelector>]) -> bool {
+    selector_list.iter().any(|compound_selectors| {
+        compound_selectors.first().is_some_and(|compound_selector| {
+            compound_selector.scope == Scope::AdjacentSibling || compound_selector.scope == Scope::GeneralSibling
+        })
+    })
+}
+
+/// The outcome of matching a complex selector from right to left. The
+/// failures tell the caller how far back it must go before trying another
+/// element can succeed, which keeps matching linear in the depth of the tree
+/// (e.g. for `div div div`) instead of trying every combination of
+/// ancestors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MatchResult {
+    Matched,
+    /// Another element for the closest `~` combinator may still match.
+    NotMatchedAndRestartFromClosestLaterSibling,
+    /// Only another element for the closest descendant combinator may still
+    /// match.
+    NotMatchedAndRestartFromClosestDescendant,
+    /// No other choice of elements can match.
+    NotMatchedGlobally,
+}
+
+impl<'a> Element<'a> {
+    pub(crate) fn descendants(&self) -> Descendants<'a> {
+        Descendants {
+            document: self.document,
+            stack: vec!(self.data().children.iter()),
+        }
+    }
+
+    /// Selects the elements matching any of the complex selectors, relative
+    /// to the element.
+    ///
+    /// The candidates are the element’s descendants, followed by its
+    /// following siblings and their descendants when a selector starts with a
+    /// sibling combinator (e.g. `:has(+ ref)`). Both are walked once in
+    /// document order and only filtered, so the results are in document
+    /// order without duplicates.
+    pub(crate) fn select_all_list<'s, L>(&self, selector_list: L) -> Box<dyn Iterator<Item=Element<'a>> + 's>
+        where 'a: 's, L: Borrow<[Vec<CompoundSelector>]> + 's {
+        let anchor = *self;
+        let includes_siblings = includes_siblings(selector_list.borrow());
+
+        if let Some(node_indices) = self.indexed_candidates(selector_list.borrow(), includes_siblings) {
+            return Box::new(node_indices.into_iter()
+                .map(move |node_index| anchor.element_at(node_index))
+                .filter(move |element| element.matches_list(selector_list.borrow(), Some(anchor))));
+        }
+
+        let candidates: Box<dyn Iterator<Item=Element<'a>> + 'a> = if includes_siblings {
+            let following = self.following_siblings()
+                .flat_map(|sibling| once(sibling).chain(sibling.descendants()));
+
+            Box::new(self.descendants().chain(following))
+        } else {
+            Box::new(self.descendants())
+        };
+
+        Box::new(candidates.filter(move |element| element.matches_list(selector_list.borrow(), Some(anchor))))
+    }
+
+    /// Looks up the candidates for the selectors in the document’s indexes,
+    /// if every selector has an indexed part (e.g. an id or tag name) in its
+    /// last compound selector. The candidates are limited to the node
+    /// indices which can follow the element, relying on node indices being
+    /// in document order.
+    pub(crate) fn indexed_candidates(&self, selector_list: &[Vec<CompoundSelector>], includes_siblings: bool) -> Option<Vec<usize>> {
+        let tables = self.document.index_tables()?;
+
+        let start = self.node_index + 1;
+        let end = self.candidates_end(includes_siblings);
+        let mut node_indices = Vec::new();
+
+        for compound_selectors in selector_list {
+            let candidates = compound_selectors.last()?.parts.iter()
+                .filter_map(|part| tables.candidates(part))
+                .min_by_key(|candidates| candidates.len())?;
+
+            let from = candidates.partition_point(|&node_index| node_index < start);
+            let to = candidates.partition_point(|&node_index| node_index < end);
+
+            node_indices.extend_from_slice(&candidates[from..to]);
+        }
+
+        if selector_list.len() > 1 {
+            node_indices.sort_unstable();
+            node_indices.dedup();
+        }
+
+        Some(node_indices)
+    }
+
+    /// Returns the node index following the last element which can match a
+    /// selector relative to the element: its last descendant, or the last
+    /// descendant of its parent if the selector can match following siblings.
+    pub(crate) fn candidates_end(&self, includes_siblings: bool) -> usize {
+        match self.data().parent {
+            Some(parent_index) if includes_siblings => self.element_at(parent_index).subtree_end(),
+            _ => self.subtree_end(),
+        }
+    }
+
+    /// Returns the node index following the last descendant of the element.
+    fn subtree_end(&self) -> usize {
+        let mut last_index = self.node_index;
+
+        while let Some(&child_index) = self.document.elements[last_index].children.last() {
+            last_index = child_index;
+        }
+
+        last_index + 1
+    }
+
+    /// Returns true if the element matches any of the complex selectors. If
+    /// an anchor is given, the first compound selector must match relative
+    /// to it (e.g. a child of the anchor for `> ref`), and the compound
+    /// selectors can only match elements following the anchor.
+    pub(crate) fn matches_list(&self, selector_list: &[Vec<CompoundSelector>], anchor: Option<Element<'a>>) -> bool {
+        selector_list.iter().any(|compound_selectors| self.match_complex(compound_selectors, anchor) == MatchResult::Matched)
+    }
+
+    /// Matches the last of the compound selectors against the element, then
+    /// the preceding compound selectors against its ancestors or preceding
+    /// siblings as required by their scopes, moving right to left.
+    fn match_complex(&self, compound_selectors: &[CompoundSelector], anchor: Option<Element<'a>>) -> MatchResult {
+        let (compound_selector, preceding_selectors) = match compound_selectors.split_last() {
+            Some(split) => split,
+            None => return MatchResult::NotMatchedGlobally,
+        };
+
+        if !self.matches_compound(compound_selector) {
+            return MatchResult::NotMatchedAndRestartFromClosestLaterSibling;
+        }
+
+        let scope = compound_selector.scope;
+
+        if preceding_selectors.is_empty() {
+            return match anchor {
+                Some(anchor) if !self.is_in_scope_of(anchor, scope) =>
+                    MatchResult::NotMatchedAndRestartFromClosestLaterSibling,
+
+                _ => MatchResult::Matched,
+            };
+        }
+
+        let candidates: Box<dyn Iterator<Item=Element<'a>> + 'a> = match scope {
+            Scope::IndirectChild => self.ancestors(),
+            Scope::DirectChild => Box::new(self.parent().into_iter()),
+            Scope::AdjacentSibling => Box::new(self.previous_sibling().into_iter()),
+            Scope::GeneralSibling => self.preceding_siblings(),
+        };
+
+        for candidate in candidates.take_while(|&candidate| Some(candidate) != anchor) {
+            let result = candidate.match_complex(preceding_selectors, anchor);
+
+            match (result, scope) {
+                (MatchResult::Matched, _) |
+                (MatchResult::NotMatchedGlobally, _) |
+                (_, Scope::AdjacentSibling) =>
+                    return result,
+
+                (_, Scope::DirectChild) =>
+                    return MatchResult::NotMatchedAndRestartFromClosestDescendant,
+
+                (MatchResult::NotMatchedAndRestartFromClosestDescendant, Scope::GeneralSibling) =>
+                    return result,
+
+                _ => { },
+            }
+        }
+
+        match scope {
+            Scope::IndirectChild | Scope::DirectChild => MatchResult::NotMatchedGlobally,
+            Scope::AdjacentSibling | Scope::GeneralSibling => MatchResult::NotMatchedAndRestartFromClosestDescendant,
+        }
+    }
+
+    /// Returns true if the element is related to the anchor as required by
+    /// the scope of the first compound selector.
+    fn is_in_scope_of(&self, anchor: Element<'a>, scope: Scope) -> bool {
+        match scope {
+            Scope::IndirectChild => {
+                let mut parent = self.data().parent;
+
+                while let Some(parent_index) = parent {
+                    if parent_index == anchor.node_index {
+                        return true;
+                    }
+
+                    parent = self.document.elements[parent_index].parent;
+                }
+
+                false
+            },
+
+            Scope::DirectChild => self.data().parent == Some(anchor.node_index),
+            Scope::AdjacentSibling => self.previous_sibling() == Some(anchor),
+            Scope::GeneralSibling => self.preceding_siblings().any(|sibling| sibling == anchor),
+        }
+    }
+
+    pub(crate) fn matches_pseudo_class(&self, pseudo_class: &PseudoClass) -> bool {
+        match *pseudo_class {
+            PseudoClass::Root =>
+                self.data().parent == Some(0),
+
+            PseudoClass::Empty =>
+                self.data().child_nodes.iter().all(|child_node| {
+                    matches!(*child_node, ChildNode::Comment(_) | ChildNode::ProcessingInstruction(_, _))
+                }),
+
+            PseudoClass::FirstChild =>
+                self.sibling_position(false, false) == 1,
+
+            PseudoClass::LastChild =>
+                self.sibling_position(true, false) == 1,
+
+            PseudoClass::OnlyChild =>
+                self.sibling_position(false, false) == 1 && self.sibling_position(true, false) == 1,
+
+            PseudoClass::FirstOfType =>
+                self.sibling_position(false, true) == 1,
+
+            PseudoClass::LastOfType =>
+                self.sibling_position(true, true) == 1,
+
+            PseudoClass::OnlyOfType =>
+                self.sibling_position(false, true) == 1 && self.sibling_position(true, true) == 1,
+
+            PseudoClass::NthChild(nth) =>
+                nth.matches(self.sibling_position(false, false)),
+
+            PseudoClass::NthLastChild(nth) =>
+                nth.matches(self.sibling_position(true, false)),
+
+            PseudoClass::NthOfType(nth) =>
+                nth.matches(self.sibling_position(false, true)),
+
+            PseudoClass::NthLastOfType(nth) =>
+                nth.matches(self.sibling_position(true, true)),
+
+            PseudoClass::Not(ref selector_list) =>
+                !self.matches_list(selector_list, None),
+
+            PseudoClass::Is(ref selector_list) | PseudoClass::Where(ref selector_list) =>
+                self.matches_list(selector_list, None),
+
+            PseudoClass::Has(ref selector_list) =>
+                self.select_all_list(selector_list.as_slice()).next().is_some(),
+        }
+    }
+
+    /// Returns the 1-based position of the element among its siblings,
+    /// optionally counting from the last sibling or only counting siblings
+    /// with the same tag name.
+    fn sibling_position(&self, from_end: bool, of_type: bool) -> usize {
+        let siblings: &[usize] = match self.data().parent {
+            Some(parent_index) => &self.document.elements[parent_index].children,
+            None => return 1,
+        };
+
+        let position = siblings.iter().position(|&node_index| node_index == self.node_index).unwrap();
+
+        let candidates = if from_end {
+            &siblings[position + 1..]
+        } else {
+            &siblings[..position]
+        };
+
+        let count = if of_type {
+            let tag_name = self.tag_name();
+
+            candidates.iter()
+                .filter(|&&node_index| &*self.document.elements[node_index].tag_name == tag_name)
+                .count()
+        } else {
+            candidates.len()
+        };
+
+        count + 1
+    }
+}
@@ -0,0 +1,53 @@
+use rayon::prelude::*;
+
+use super::{ Document, Element, SelectError, SelectorList };
+use super::matching::includes_siblings;
+
+impl<'a> Element<'a> {
+    /// Just like `select_all`, but matches the candidate elements in
+    /// parallel. The elements are returned in document order.
+    pub fn par_select_all(&self, selector: &str) -> Result<Vec<Element<'a>>, SelectError> {
+        SelectorList::parse(selector)
+            .map_err(SelectError::ParseError)
+            .map(|selector_list| self.par_select_all_compiled(&selector_list))
+    }
+
+    /// Just like `select_all_compiled`, but matches the candidate elements in
+    /// parallel. The elements are returned in document order.
+    ///
+    /// Since node indices are in document order, the candidates are a range
+    /// of node indices covering whole subtrees, which is split between
+    /// threads and collected back in order.
+    pub fn par_select_all_compiled(&self, selector_list: &SelectorList) -> Vec<Element<'a>> {
+        let anchor = *self;
+        let selector_list = selector_list.selectors.as_slice();
+        let includes_siblings = includes_siblings(selector_list);
+        let matches = |element: &Element<'a>| element.matches_list(selector_list, Some(anchor));
+
+        match self.indexed_candidates(selector_list, includes_siblings) {
+            Some(node_indices) => node_indices.into_par_iter()
+                .map(|node_index| anchor.element_at(node_index))
+                .filter(matches)
+                .collect(),
+
+            None => (self.node_index + 1..self.candidates_end(includes_siblings)).into_par_iter()
+                .map(|node_index| anchor.element_at(node_index))
+                .filter(matches)
+                .collect(),
+        }
+    }
+}
+
+impl Document {
+    /// Just like `select_all`, but matches the candidate elements in
+    /// parallel. The elements are returned in document order.
+    pub fn par_select_all<'a>(&'a self, selector: &str) -> Result<Vec<Element<'a>>, SelectError> {
+        self.root().par_select_all(selector)
+    }
+
+    /// Just like `select_all_compiled`, but matches the candidate elements in
+    /// parallel. The elements are returned in document order.
+    pub fn par_select_all_compiled<'a>(&'a self, selector_list: &SelectorList) -> Vec<Element<'a>> {
+        self.root().par_select_all_compiled(selector_list)
+    }
+}
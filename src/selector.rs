@@ -1,14 +1,85 @@
-use std::str::Chars;
-use std::iter::Peekable;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
 
 /// An error which is returned when parsing a selector encounters an unexpected
-/// token
+/// token.
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct UnexpectedTokenError(pub char);
+pub struct UnexpectedTokenError {
+    /// The byte offset of the unexpected token in the selector.
+    pub offset: usize,
+    /// The unexpected character, or `None` at the end of the selector.
+    pub found: Option<char>,
+    /// A description of what was expected instead (e.g. "`]`").
+    pub expected: &'static str,
+}
+
+impl UnexpectedTokenError {
+    fn new(offset: usize, found: Option<char>, expected: &'static str) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            offset,
+            found,
+            expected,
+        }
+    }
+}
+
+/// Writes the error as e.g. "expected `]` but found `x` at offset 5".
+impl fmt::Display for UnexpectedTokenError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.found {
+            Some(c) => write!(formatter, "expected {} but found `{}` at offset {}", self.expected, c, self.offset),
+            None => write!(formatter, "expected {} but found the end of the selector at offset {}", self.expected, self.offset),
+        }
+    }
+}
+
+impl Error for UnexpectedTokenError { }
+
+/// A parsed, comma-separated list of selectors (e.g. `title, item > ref`),
+/// which can be used to query many elements without parsing the selector
+/// again each time.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SelectorList {
+    pub(crate) selectors: Vec<Vec<CompoundSelector>>,
+}
+
+impl SelectorList {
+    /// Parses the selector list.
+    pub fn parse(selector: &str) -> Result<SelectorList, UnexpectedTokenError> {
+        CompoundSelector::parse_list(selector).map(|selectors| SelectorList { selectors })
+    }
+
+    /// Returns the alternatives in the list, each as the sequence of
+    /// `CompoundSelector`s separated by combinators.
+    pub fn selectors(&self) -> &[Vec<CompoundSelector>] {
+        &self.selectors
+    }
+
+    /// Resolves the namespace prefixes in the selectors to the namespace
+    /// URIs in the given map from prefix to URI, just like
+    /// `CompoundSelector::resolve_namespaces`.
+    pub fn resolve_namespaces(&mut self, namespaces: &HashMap<String, String>) {
+        for compound_selectors in &mut self.selectors {
+            for compound_selector in compound_selectors {
+                compound_selector.resolve_namespaces(namespaces);
+            }
+        }
+    }
+}
+
+impl FromStr for SelectorList {
+    type Err = UnexpectedTokenError;
+
+    fn from_str(selector: &str) -> Result<SelectorList, UnexpectedTokenError> {
+        SelectorList::parse(selector)
+    }
+}
 
 /// Represents a component of a parsed CSS selector is used to match a single
 /// element.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct CompoundSelector {
     /// The scope of the selector.
     pub scope: Scope,
@@ -17,7 +88,7 @@ pub struct CompoundSelector {
 }
 
 /// The scope of the `CompoundSelector`.
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Scope {
     /// Implies that the selector must be a direct descendent of the previous
     /// match (e.g. `body > header`).
@@ -25,189 +96,1128 @@ pub enum Scope {
     /// Implies that the selector is a descendent of the previous match (e.g.,
     /// `body header`).
     IndirectChild,
+    /// Implies that the selector must be the sibling immediately following
+    /// the previous match (e.g. `title + ref`).
+    AdjacentSibling,
+    /// Implies that the selector is any sibling following the previous match
+    /// (e.g. `note ~ div`).
+    GeneralSibling,
 }
 
 /// The individual parts of the `CompoundSelector`. For example, the selector
 /// `input[type="radio"]` has two parts, the `TagName` and `Attribute`
 /// selectors.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Selector {
     /// Represents an id selector (e.g. `#the-id`)
     Id(String),
     /// Represents a tag name selector (e.g. `input`)
     TagName(String),
-    /// Represents an attribute selector (e.g. `[type="radio"]`)
-    Attribute(String, MatchType, String),
+    /// Represents the universal selector (e.g. `*`)
+    Universal,
+    /// Represents a class selector (e.g. `.warning`), which matches any of
+    /// the whitespace-separated words in the `class` attribute
+    Class(String),
+    /// Represents the namespace qualifier of the type or universal selector
+    /// which follows it (e.g. the `svg|` in `svg|rect`)
+    Namespace(NamespacePrefix),
+    /// Represents an attribute selector (e.g. `[type="radio"]`), with an
+    /// optional namespace qualifier (e.g. `[xlink|href]`). Attributes without
+    /// a namespace qualifier only match attributes without a namespace. The
+    /// value is empty for the `MatchType::Exists` match type.
+    Attribute(Option<NamespacePrefix>, String, MatchType, String, CaseSensitivity),
+    /// Represents a pseudo-class selector (e.g. `:first-child`)
+    PseudoClass(PseudoClass),
+}
+
+/// The namespace qualifier of a type, universal or attribute selector.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NamespacePrefix {
+    /// Matches nodes in any namespace (e.g. `*|*`)
+    Any,
+    /// Matches nodes without a namespace (e.g. `|*`)
+    NoNamespace,
+    /// Matches nodes written with the given prefix in the document (e.g.
+    /// `svg|*`), used when the prefix has not been registered on the query
+    Named(String),
+    /// Matches nodes in the namespace URI registered for the prefix on the
+    /// query, regardless of the prefix used in the document
+    Resolved(String, String),
+}
+
+impl NamespacePrefix {
+    /// Returns true if a node with the given prefix and namespace URI
+    /// satisfies the namespace qualifier.
+    pub fn matches(&self, prefix: Option<&str>, namespace_uri: Option<&str>) -> bool {
+        match *self {
+            NamespacePrefix::Any => true,
+            NamespacePrefix::NoNamespace => namespace_uri.is_none(),
+            NamespacePrefix::Named(ref name) => prefix == Some(name.as_str()),
+            NamespacePrefix::Resolved(_, ref uri) => namespace_uri == Some(uri.as_str()),
+        }
+    }
+
+    fn resolve(&mut self, namespaces: &HashMap<String, String>) {
+        let resolved = match *self {
+            NamespacePrefix::Named(ref prefix) =>
+                namespaces.get(prefix).map(|uri| NamespacePrefix::Resolved(prefix.clone(), uri.clone())),
+
+            _ => None,
+        };
+
+        if let Some(resolved) = resolved {
+            *self = resolved;
+        }
+    }
+}
+
+/// The pseudo-classes which can be used in a selector.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PseudoClass {
+    /// Matches the document’s top-level element (`:root`)
+    Root,
+    /// Matches elements without child elements or text (`:empty`)
+    Empty,
+    /// Matches the first element among its siblings (`:first-child`)
+    FirstChild,
+    /// Matches the last element among its siblings (`:last-child`)
+    LastChild,
+    /// Matches elements without siblings (`:only-child`)
+    OnlyChild,
+    /// Matches the first element of its type among its siblings
+    /// (`:first-of-type`)
+    FirstOfType,
+    /// Matches the last element of its type among its siblings
+    /// (`:last-of-type`)
+    LastOfType,
+    /// Matches elements without siblings of the same type (`:only-of-type`)
+    OnlyOfType,
+    /// Matches elements by position among their siblings (e.g.
+    /// `:nth-child(2n+1)`)
+    NthChild(Nth),
+    /// Matches elements by position among their siblings, counting from the
+    /// last sibling (e.g. `:nth-last-child(2)`)
+    NthLastChild(Nth),
+    /// Matches elements by position among siblings of the same type (e.g.
+    /// `:nth-of-type(odd)`)
+    NthOfType(Nth),
+    /// Matches elements by position among siblings of the same type,
+    /// counting from the last sibling (e.g. `:nth-last-of-type(-n+3)`)
+    NthLastOfType(Nth),
+    /// Matches elements which do not match any of the selectors in the list
+    /// (e.g. `:not([id=id-2])`)
+    Not(Vec<Vec<CompoundSelector>>),
+    /// Matches elements which match any of the selectors in the list (e.g.
+    /// `:is(title, ref)`)
+    Is(Vec<Vec<CompoundSelector>>),
+    /// Just like `Is`, but does not contribute to the selector’s specificity
+    /// (e.g. `:where(title, ref)`)
+    Where(Vec<Vec<CompoundSelector>>),
+    /// Matches elements which have an element matching any of the relative
+    /// selectors in the list, anchored at the element (e.g. `:has(> ref)`)
+    Has(Vec<Vec<CompoundSelector>>),
+}
+
+/// Represents the `an+b` argument of the `:nth-*` pseudo-classes, which
+/// matches the 1-based positions `a*n + b` for every `n >= 0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Nth {
+    /// The step between matching positions.
+    pub a: i32,
+    /// The offset of the first matching position.
+    pub b: i32,
+}
+
+impl Nth {
+    /// Returns true if the 1-based position is matched by the expression.
+    pub fn matches(&self, position: usize) -> bool {
+        let offset = position as i64 - self.b as i64;
+        let step = self.a as i64;
+
+        if step == 0 {
+            offset == 0
+        } else {
+            offset % step == 0 && offset / step >= 0
+        }
+    }
+
+    /// Parses the `an+b` microsyntax, including the `odd` and `even`
+    /// keywords. The offsets of errors are relative to the given string.
+    pub fn parse(string: &str) -> Result<Nth, UnexpectedTokenError> {
+        let leading_spaces = string.len() - string.trim_start().len();
+
+        Nth::parse_trimmed(&string.trim().to_ascii_lowercase())
+            .map_err(|error| UnexpectedTokenError { offset: leading_spaces + error.offset, ..error })
+    }
+
+    /// Parses a lowercase `an+b` expression without surrounding whitespace.
+    fn parse_trimmed(string: &str) -> Result<Nth, UnexpectedTokenError> {
+        match string {
+            "" => return Err(UnexpectedTokenError::new(0, None, "an integer")),
+            "odd" => return Ok(Nth { a: 2, b: 1 }),
+            "even" => return Ok(Nth { a: 2, b: 0 }),
+            _ => { },
+        }
+
+        if let Some(n_position) = string.find('n') {
+            let a = match &string[..n_position] {
+                "" | "+" => 1,
+                "-" => -1,
+                coefficient => parse_integer(coefficient, 0)?,
+            };
+
+            let sign_position = skip_spaces(string, n_position + 1);
+
+            let b = match string[sign_position..].chars().next() {
+                None => 0,
+
+                Some(sign) => {
+                    let digits_position = skip_spaces(string, sign_position + sign.len_utf8());
+                    let digits = &string[digits_position..];
+
+                    if digits.starts_with(['+', '-']) {
+                        return Err(UnexpectedTokenError::new(digits_position, digits.chars().next(), "a digit"));
+                    }
+
+                    match sign {
+                        '+' => parse_integer(digits, digits_position)?,
+                        '-' => -parse_integer(digits, digits_position)?,
+                        _ => return Err(UnexpectedTokenError::new(sign_position, Some(sign), "`+` or `-`")),
+                    }
+                },
+            };
+
+            Ok(Nth { a, b })
+        } else {
+            parse_integer(string, 0).map(|b| Nth { a: 0, b })
+        }
+    }
+}
+
+/// Returns the position of the first non-whitespace character at or after
+/// `from`.
+fn skip_spaces(string: &str, from: usize) -> usize {
+    string.len() - string[from..].trim_start().len()
+}
+
+/// Parses an optionally signed integer found at `offset` in the selector.
+fn parse_integer(string: &str, offset: usize) -> Result<i32, UnexpectedTokenError> {
+    let digits = string.trim_start_matches(['+', '-']);
+    let sign_length = string.len() - digits.len();
+
+    if sign_length > 1 {
+        return Err(UnexpectedTokenError::new(offset + 1, string[1..].chars().next(), "a digit"));
+    }
+
+    if let Some((index, c)) = digits.char_indices().find(|&(_, c)| !c.is_ascii_digit()) {
+        return Err(UnexpectedTokenError::new(offset + sign_length + index, Some(c), "a digit"));
+    }
+
+    string.parse().map_err(|_| UnexpectedTokenError::new(offset + sign_length, digits.chars().next(), "an integer"))
 }
 
 /// The match type for an attribute selector.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum MatchType {
-    /// Indicates that the match must be identical
+    /// Indicates that the attribute must be present (e.g. `[href]`)
+    Exists,
+    /// Indicates that the match must be identical (e.g. `[type="radio"]`)
     Equals,
+    /// Indicates that the value must be one of the whitespace-separated words
+    /// in the attribute (e.g. `[class~="warning"]`)
+    Includes,
+    /// Indicates that the attribute must be identical to the value or start
+    /// with the value followed by a `-` (e.g. `[lang|="en"]`)
+    DashMatch,
+    /// Indicates that the attribute must start with the value (e.g.
+    /// `[href^="http"]`)
+    Prefix,
+    /// Indicates that the attribute must end with the value (e.g.
+    /// `[href$=".xml"]`)
+    Suffix,
+    /// Indicates that the attribute must contain the value (e.g.
+    /// `[title*="sample"]`)
+    Substring,
+}
+
+/// Controls how attribute values are compared in an attribute selector.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CaseSensitivity {
+    /// Values must match exactly. This is the default, and can be requested
+    /// explicitly with the `s` flag (e.g. `[type="radio" s]`).
+    CaseSensitive,
+    /// Values are compared ignoring ASCII case, requested with the `i` flag
+    /// (e.g. `[type="radio" i]`).
+    AsciiCaseInsensitive,
+}
+
+impl MatchType {
+    /// Returns true if the attribute value satisfies the match against the
+    /// selector value.
+    pub fn matches(&self, attribute_value: &str, selector_value: &str, case_sensitivity: CaseSensitivity) -> bool {
+        if case_sensitivity == CaseSensitivity::AsciiCaseInsensitive {
+            return self.matches(
+                &attribute_value.to_ascii_lowercase(),
+                &selector_value.to_ascii_lowercase(),
+                CaseSensitivity::CaseSensitive,
+            );
+        }
+
+        match *self {
+            MatchType::Exists =>
+                true,
+
+            MatchType::Equals =>
+                attribute_value == selector_value,
+
+            MatchType::Includes =>
+                !selector_value.is_empty() &&
+                    !selector_value.contains(char::is_whitespace) &&
+                    attribute_value.split_whitespace().any(|word| word == selector_value),
+
+            MatchType::DashMatch =>
+                attribute_value == selector_value ||
+                    (attribute_value.starts_with(selector_value) &&
+                     attribute_value[selector_value.len()..].starts_with('-')),
+
+            MatchType::Prefix =>
+                !selector_value.is_empty() && attribute_value.starts_with(selector_value),
+
+            MatchType::Suffix =>
+                !selector_value.is_empty() && attribute_value.ends_with(selector_value),
+
+            MatchType::Substring =>
+                !selector_value.is_empty() && attribute_value.contains(selector_value),
+        }
+    }
 }
 
 macro_rules! expect_token {
-    ($token_option: expr, $token: expr) => {
-        match $token_option {
-            Some($token) => { },
-            Some(token) => return Err(UnexpectedTokenError(token)),
-            None => return Err(UnexpectedTokenError(' ')),
+    ($chars: expr, $token: expr) => {
+        if $chars.peek() == Some($token) {
+            $chars.next().unwrap();
+        } else {
+            return Err($chars.error(concat!("`", $token, "`")));
+        }
+    }
+}
+
+/// The position reached while parsing a selector string. NUL characters are
+/// read as U+FFFD, like the CSS Syntax spec preprocesses its input, while
+/// the offset still counts the bytes of the original string.
+#[derive(Clone)]
+struct Cursor<'s> {
+    selector: &'s str,
+    offset: usize,
+}
+
+impl<'s> Cursor<'s> {
+    fn new(selector: &'s str) -> Cursor<'s> {
+        Cursor {
+            selector,
+            offset: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.selector[self.offset..].chars().next()?;
+        self.offset += c.len_utf8();
+        Some(preprocess(c))
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.selector[self.offset..].chars().nth(n).map(preprocess)
+    }
+
+    /// Returns true if the `n`th character ahead starts a valid escape.
+    fn starts_escape(&self, n: usize) -> bool {
+        self.peek_at(n) == Some('\\') && !self.peek_at(n + 1).is_some_and(is_newline)
+    }
+
+    /// Returns true if the next characters would start an identifier, as
+    /// defined by the CSS Syntax spec.
+    fn starts_identifier(&self) -> bool {
+        match self.peek() {
+            Some('-') => self.peek_at(1).is_some_and(|c| is_name_start(c) || c == '-') || self.starts_escape(1),
+            Some('\\') => self.starts_escape(0),
+            Some(c) => is_name_start(c),
+            None => false,
         }
     }
+
+    /// Returns an error for the token at the current position.
+    fn error(&self, expected: &'static str) -> UnexpectedTokenError {
+        UnexpectedTokenError::new(self.offset, self.peek(), expected)
+    }
 }
 
 #[inline]
-fn non_digit(c: char) -> bool {
-    ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z')
+fn preprocess(c: char) -> char {
+    if c == '\0' { '\u{FFFD}' } else { c }
 }
 
 #[inline]
-fn allowed_character(c: char) -> bool {
-    non_digit(c) || ('0' <= c && c <= '9') || c == '-' || c == '_'
+fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || is_newline(c)
+}
+
+#[inline]
+fn is_newline(c: char) -> bool {
+    c == '\n' || c == '\r' || c == '\x0C'
+}
+
+/// Returns true if the character can start an identifier. Non-ASCII
+/// characters are always allowed.
+#[inline]
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c >= '\u{80}'
+}
+
+#[inline]
+fn is_name_character(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
 }
 
 #[inline]
 fn valid_start_token(c: char) -> bool {
-    c == '#' || c == '['
+    c == '#' || c == '[' || c == ':' || c == '.' || c == '*' || c == '|'
 }
 
-fn extract_valid_string(chars: &mut Peekable<Chars>) -> Result<String, UnexpectedTokenError> {
-    extract_valid_string_until_token(chars, ' ')
+#[inline]
+fn combinator_scope(c: char) -> Option<Scope> {
+    match c {
+        '>' => Some(Scope::DirectChild),
+        '+' => Some(Scope::AdjacentSibling),
+        '~' => Some(Scope::GeneralSibling),
+        _ => None,
+    }
 }
 
-fn extract_valid_string_until_token(chars: &mut Peekable<Chars>, stop_token: char) -> Result<String, UnexpectedTokenError> {
-    let mut string = String::new();
+fn skip_whitespace(chars: &mut Cursor) -> bool {
+    let mut skipped = false;
 
-    while let Some(&c) = chars.peek() {
-        if c == stop_token {
+    while let Some(c) = chars.peek() {
+        if is_whitespace(c) {
             chars.next().unwrap();
-            break;
-        } else if allowed_character(c) {
-            string.push(chars.next().unwrap());
-        } else if valid_start_token(c) {
-            break;
+            skipped = true;
         } else {
-            return Err(UnexpectedTokenError(c));
+            break;
+        }
+    }
+
+    skipped
+}
+
+/// Consumes the escape following a `\` (e.g. `\31 ` or `\.`), returning the
+/// escaped character.
+fn consume_escape(chars: &mut Cursor) -> char {
+    match chars.next() {
+        Some(c) if c.is_ascii_hexdigit() => {
+            let mut code_point = c.to_digit(16).unwrap();
+
+            for _ in 1..6 {
+                match chars.peek().and_then(|c| c.to_digit(16)) {
+                    Some(digit) => {
+                        chars.next().unwrap();
+                        code_point = code_point * 16 + digit;
+                    },
+
+                    None => break,
+                }
+            }
+
+            if chars.peek().is_some_and(is_whitespace) && chars.next() == Some('\r') && chars.peek() == Some('\n') {
+                chars.next().unwrap();
+            }
+
+            match ::std::char::from_u32(code_point) {
+                Some(c) if code_point != 0 => c,
+                _ => '\u{FFFD}',
+            }
+        },
+
+        Some(c) => c,
+        None => '\u{FFFD}',
+    }
+}
+
+/// Consumes the name characters and escapes that follow, which may be
+/// nothing at all.
+fn extract_name(chars: &mut Cursor) -> String {
+    let mut name = String::new();
+
+    loop {
+        match chars.peek() {
+            Some(c) if is_name_character(c) => name.push(chars.next().unwrap()),
+
+            Some('\\') if chars.starts_escape(0) => {
+                chars.next().unwrap();
+                name.push(consume_escape(chars));
+            },
+
+            _ => return name,
         }
     }
+}
 
-    return Ok(string);
+fn extract_identifier(chars: &mut Cursor, expected: &'static str) -> Result<String, UnexpectedTokenError> {
+    if chars.starts_identifier() {
+        Ok(extract_name(chars))
+    } else {
+        Err(chars.error(expected))
+    }
+}
+
+fn extract_quoted_string(chars: &mut Cursor, quote: char) -> Result<String, UnexpectedTokenError> {
+    let mut string = String::new();
+
+    loop {
+        match chars.peek() {
+            Some(c) if c == quote => {
+                chars.next().unwrap();
+                return Ok(string);
+            },
+
+            Some('\\') => {
+                chars.next().unwrap();
+
+                match chars.peek() {
+                    Some('\r') => {
+                        chars.next().unwrap();
+
+                        if chars.peek() == Some('\n') {
+                            chars.next().unwrap();
+                        }
+                    },
+
+                    Some(c) if is_newline(c) => { chars.next().unwrap(); },
+                    Some(_) => string.push(consume_escape(chars)),
+                    None => { },
+                }
+            },
+
+            Some(c) if !is_newline(c) => {
+                chars.next().unwrap();
+                string.push(c);
+            },
+
+            _ => return Err(chars.error(if quote == '"' { "`\"`" } else { "`'`" })),
+        }
+    }
+}
+
+/// Extracts a name with an optional namespace qualifier (e.g. `svg|rect`,
+/// `*|title` or `|*`). The name is `None` for the universal selector `*`.
+fn extract_qualified_name(chars: &mut Cursor) -> Result<(Option<NamespacePrefix>, Option<String>), UnexpectedTokenError> {
+    let name = extract_name_or_asterisk(chars)?;
+
+    let mut lookahead = chars.clone();
+    let is_namespace_separator = lookahead.next() == Some('|') && lookahead.peek() != Some('=');
+
+    if !is_namespace_separator {
+        return match name {
+            Some(ref name) if name.is_empty() => Err(chars.error("a name")),
+            _ => Ok((None, name)),
+        };
+    }
+
+    chars.next().unwrap();
+
+    let namespace_prefix = match name {
+        None => NamespacePrefix::Any,
+        Some(ref prefix) if prefix.is_empty() => NamespacePrefix::NoNamespace,
+        Some(prefix) => NamespacePrefix::Named(prefix),
+    };
+
+    match extract_name_or_asterisk(chars)? {
+        Some(ref name) if name.is_empty() => Err(chars.error("a name or `*`")),
+        name => Ok((Some(namespace_prefix), name)),
+    }
+}
+
+fn extract_name_or_asterisk(chars: &mut Cursor) -> Result<Option<String>, UnexpectedTokenError> {
+    if Some('*') == chars.peek() {
+        chars.next().unwrap();
+        Ok(None)
+    } else {
+        Ok(Some(if chars.starts_identifier() { extract_name(chars) } else { String::new() }))
+    }
+}
+
+fn extract_nth_argument(chars: &mut Cursor) -> Result<Nth, UnexpectedTokenError> {
+    expect_token!(chars, '(');
+
+    let start = chars.offset;
+
+    loop {
+        match chars.peek() {
+            Some(')') => break,
+            Some(_) => { chars.next().unwrap(); },
+            None => return Err(chars.error("`)`")),
+        }
+    }
+
+    // the argument always ends at the closing parenthesis
+    let nth = Nth::parse(&chars.selector[start..chars.offset])
+        .map_err(|error| UnexpectedTokenError {
+            offset: start + error.offset,
+            found: error.found.or(Some(')')),
+            ..error
+        })?;
+
+    chars.next().unwrap();
+
+    Ok(nth)
 }
 
 impl Selector {
-    fn create_list(string: &str) -> Result<Vec<Selector>, UnexpectedTokenError> {
+    fn create_list(chars: &mut Cursor) -> Result<Vec<Selector>, UnexpectedTokenError> {
         let mut selectors = Vec::new();
 
-        let mut chars = string.chars().peekable();
-        while let Some(&c) = chars.peek() {
-            match Selector::next_selector(c, &mut chars) {
-                Ok(selector) =>
-                    selectors.push(selector),
-
-                Err(err) =>
-                    return Err(err),
+        while let Some(c) = chars.peek() {
+            if selectors.is_empty() && (chars.starts_identifier() || c == '*' || c == '|') {
+                selectors.append(&mut Selector::create_type_selector(chars)?);
+            } else if valid_start_token(c) {
+                selectors.push(Selector::next_selector(c, chars)?);
+            } else {
+                break;
             }
         }
 
-        return Ok(selectors);
+        Ok(selectors)
     }
 
-    fn next_selector(c: char, chars: &mut Peekable<Chars>) -> Result<Selector, UnexpectedTokenError> {
-        if non_digit(c) {
-            Selector::create_tag_name(chars)
-        } else if c == '#' {
+    fn next_selector(c: char, chars: &mut Cursor) -> Result<Selector, UnexpectedTokenError> {
+        if c == '#' {
             Selector::create_id(chars)
         } else if c == '[' {
             Selector::create_attribute(chars)
+        } else if c == ':' {
+            Selector::create_pseudo_class(chars)
+        } else if c == '.' {
+            Selector::create_class(chars)
         } else {
-            Err(UnexpectedTokenError(c))
+            Err(chars.error("`#`, `[`, `:` or `.`"))
         }
     }
 
-    fn create_tag_name(chars: &mut Peekable<Chars>) -> Result<Selector, UnexpectedTokenError> {
-        extract_valid_string(chars).map(|s| Selector::TagName(s))
+    /// Parses a tag name or universal selector, preceded by the `Namespace`
+    /// part if it has a namespace qualifier (e.g. `svg|rect`).
+    fn create_type_selector(chars: &mut Cursor) -> Result<Vec<Selector>, UnexpectedTokenError> {
+        let (namespace_prefix, name) = extract_qualified_name(chars)?;
+
+        let type_selector = match name {
+            Some(name) => Selector::TagName(name),
+            None => Selector::Universal,
+        };
+
+        match namespace_prefix {
+            Some(namespace_prefix) => Ok(vec!(Selector::Namespace(namespace_prefix), type_selector)),
+            None => Ok(vec!(type_selector)),
+        }
     }
 
-    fn create_id(chars: &mut Peekable<Chars>) -> Result<Selector, UnexpectedTokenError> {
-        match chars.next() {
-            Some('#') =>
-                return extract_valid_string(chars).map(|s| Selector::Id(s)),
+    fn create_class(chars: &mut Cursor) -> Result<Selector, UnexpectedTokenError> {
+        expect_token!(chars, '.');
 
-            Some(token) =>
-                return Err(UnexpectedTokenError(token)),
+        extract_identifier(chars, "a class name").map(Selector::Class)
+    }
 
-            None =>
-                return Err(UnexpectedTokenError(' ')),
-        }
+    fn create_id(chars: &mut Cursor) -> Result<Selector, UnexpectedTokenError> {
+        expect_token!(chars, '#');
+
+        extract_identifier(chars, "an id").map(Selector::Id)
     }
 
-    fn create_attribute(chars: &mut Peekable<Chars>) -> Result<Selector, UnexpectedTokenError> {
-        expect_token!(chars.next(), '[');
+    fn create_pseudo_class(chars: &mut Cursor) -> Result<Selector, UnexpectedTokenError> {
+        expect_token!(chars, ':');
+
+        let start = chars.clone();
+        let name = extract_identifier(chars, "a pseudo-class name")?.to_ascii_lowercase();
+
+        let pseudo_class = match name.as_str() {
+            "root" => PseudoClass::Root,
+            "empty" => PseudoClass::Empty,
+            "first-child" => PseudoClass::FirstChild,
+            "last-child" => PseudoClass::LastChild,
+            "only-child" => PseudoClass::OnlyChild,
+            "first-of-type" => PseudoClass::FirstOfType,
+            "last-of-type" => PseudoClass::LastOfType,
+            "only-of-type" => PseudoClass::OnlyOfType,
+            "nth-child" => PseudoClass::NthChild(extract_nth_argument(chars)?),
+            "nth-last-child" => PseudoClass::NthLastChild(extract_nth_argument(chars)?),
+            "nth-of-type" => PseudoClass::NthOfType(extract_nth_argument(chars)?),
+            "nth-last-of-type" => PseudoClass::NthLastOfType(extract_nth_argument(chars)?),
+            "not" => PseudoClass::Not(CompoundSelector::parse_nested_list(chars, false)?),
+            "is" => PseudoClass::Is(CompoundSelector::parse_nested_list(chars, false)?),
+            "where" => PseudoClass::Where(CompoundSelector::parse_nested_list(chars, false)?),
+            "has" => PseudoClass::Has(CompoundSelector::parse_nested_list(chars, true)?),
+            _ => return Err(start.error("a supported pseudo-class")),
+        };
 
-        extract_valid_string_until_token(chars, '=').and_then(|attribute| {
-            Ok((attribute, MatchType::Equals))
-        }).and_then(|(attribute, match_type)| {
-            let result = if Some(&'"') == chars.peek() {
+        Ok(Selector::PseudoClass(pseudo_class))
+    }
+
+    fn create_attribute(chars: &mut Cursor) -> Result<Selector, UnexpectedTokenError> {
+        expect_token!(chars, '[');
+        skip_whitespace(chars);
+
+        let start = chars.clone();
+
+        let (namespace_prefix, attribute) = match extract_qualified_name(chars)? {
+            (namespace_prefix, Some(attribute)) => (namespace_prefix, attribute),
+            (_, None) => return Err(start.error("an attribute name")),
+        };
+
+        skip_whitespace(chars);
+
+        let match_type = match chars.peek() {
+            Some(']') => {
                 chars.next().unwrap();
-                let result = extract_valid_string_until_token(chars, '"');
-                expect_token!(chars.next(), ']');
+                return Ok(Selector::Attribute(namespace_prefix, attribute, MatchType::Exists, String::new(), CaseSensitivity::CaseSensitive));
+            },
 
-                result
-            } else {
-                extract_valid_string_until_token(chars, ']')
-            };
+            Some('=') => MatchType::Equals,
+            Some('~') => MatchType::Includes,
+            Some('|') => MatchType::DashMatch,
+            Some('^') => MatchType::Prefix,
+            Some('$') => MatchType::Suffix,
+            Some('*') => MatchType::Substring,
+            _ => return Err(chars.error("`]` or an attribute operator")),
+        };
+
+        chars.next().unwrap();
+
+        if match_type != MatchType::Equals {
+            expect_token!(chars, '=');
+        }
+
+        skip_whitespace(chars);
+
+        let value = match chars.peek() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                chars.next().unwrap();
+                extract_quoted_string(chars, quote)?
+            },
+
+            _ => extract_identifier(chars, "a string or an identifier")?,
+        };
+
+        let skipped_whitespace = skip_whitespace(chars);
+
+        let case_sensitivity = match chars.peek() {
+            Some(flag) if skipped_whitespace && (flag == 'i' || flag == 'I') => {
+                chars.next().unwrap();
+                CaseSensitivity::AsciiCaseInsensitive
+            },
 
-            result.map(|value| {
-                Selector::Attribute(attribute, match_type, value)
-            })
-        })
+            Some(flag) if skipped_whitespace && (flag == 's' || flag == 'S') => {
+                chars.next().unwrap();
+                CaseSensitivity::CaseSensitive
+            },
+
+            _ => CaseSensitivity::CaseSensitive,
+        };
+
+        skip_whitespace(chars);
+        expect_token!(chars, ']');
+
+        Ok(Selector::Attribute(namespace_prefix, attribute, match_type, value, case_sensitivity))
     }
 }
 
-struct SelectorParts<I: Iterator<Item=String>> {
-    inner_iter: I,
-}
+impl CompoundSelector {
+    /// Resolves the namespace prefixes in the selector (e.g. the `svg` in
+    /// `svg|rect`) to the namespace URIs in the given map from prefix to
+    /// URI, including those in nested selectors. Prefixes missing from the
+    /// map are left untouched.
+    pub fn resolve_namespaces(&mut self, namespaces: &HashMap<String, String>) {
+        for part in &mut self.parts {
+            match *part {
+                Selector::Namespace(ref mut namespace_prefix) |
+                Selector::Attribute(Some(ref mut namespace_prefix), _, _, _, _) =>
+                    namespace_prefix.resolve(namespaces),
 
-impl<I: Iterator<Item=String>> Iterator for SelectorParts<I> {
-    type Item = (Scope, String);
+                Selector::PseudoClass(PseudoClass::Not(ref mut selector_list)) |
+                Selector::PseudoClass(PseudoClass::Is(ref mut selector_list)) |
+                Selector::PseudoClass(PseudoClass::Where(ref mut selector_list)) |
+                Selector::PseudoClass(PseudoClass::Has(ref mut selector_list)) => {
+                    for compound_selectors in selector_list {
+                        for compound_selector in compound_selectors {
+                            compound_selector.resolve_namespaces(namespaces);
+                        }
+                    }
+                },
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner_iter.next().and_then(|next_part| {
-            if &next_part == ">" {
-                Some((Scope::DirectChild, self.inner_iter.next().unwrap()))
-            } else {
-                Some((Scope::IndirectChild, next_part))
+                _ => { },
             }
-        })
+        }
     }
-}
 
-impl CompoundSelector {
     /// Parses the string and converts it to a list of `CompoundSelector`s.
     pub fn parse(selector: &str) -> Result<Vec<CompoundSelector>, UnexpectedTokenError> {
-        let normalized_selector = selector.split(">")
-            .collect::<Vec<&str>>()
-            .join(" > ");
+        let mut chars = Cursor::new(selector);
+
+        let compound_selectors = CompoundSelector::parse_complex(&mut chars, true)?;
+
+        if chars.peek().is_some() {
+            Err(chars.error("the end of the selector"))
+        } else {
+            Ok(compound_selectors)
+        }
+    }
+
+    /// Parses a comma-separated selector list (e.g. `title, ref`), returning
+    /// the list of `CompoundSelector`s for each alternative.
+    pub fn parse_list(selector: &str) -> Result<Vec<Vec<CompoundSelector>>, UnexpectedTokenError> {
+        let mut chars = Cursor::new(selector);
+
+        CompoundSelector::parse_list_until(&mut chars, None, true)
+    }
+
+    /// Parses the parenthesised selector list argument of a pseudo-class.
+    fn parse_nested_list(chars: &mut Cursor, relative: bool) -> Result<Vec<Vec<CompoundSelector>>, UnexpectedTokenError> {
+        expect_token!(chars, '(');
+
+        CompoundSelector::parse_list_until(chars, Some(')'), relative)
+    }
+
+    fn parse_list_until(chars: &mut Cursor, terminator: Option<char>, relative: bool) -> Result<Vec<Vec<CompoundSelector>>, UnexpectedTokenError> {
+        let mut selector_list = Vec::new();
+
+        loop {
+            let compound_selectors = CompoundSelector::parse_complex(chars, relative)?;
+
+            if compound_selectors.is_empty() {
+                return Err(chars.error("a selector"));
+            }
+
+            selector_list.push(compound_selectors);
+
+            match chars.peek() {
+                Some(',') => { },
+                Some(c) if Some(c) == terminator => { },
+                None if terminator.is_none() => return Ok(selector_list),
+                _ if terminator.is_none() => return Err(chars.error("`,` or the end of the selector")),
+                _ => return Err(chars.error("`,` or `)`")),
+            }
+
+            if chars.next() == terminator {
+                return Ok(selector_list);
+            }
+        }
+    }
+
+    /// Parses a sequence of compound selectors separated by combinators,
+    /// stopping at the end of the input, at a `,` or at a `)`. Relative
+    /// selectors may start with a combinator (e.g. `> ref`).
+    fn parse_complex(chars: &mut Cursor, relative: bool) -> Result<Vec<CompoundSelector>, UnexpectedTokenError> {
+        let mut compound_selectors = Vec::new();
+
+        skip_whitespace(chars);
+
+        let mut scope = Scope::IndirectChild;
+        let mut expects_compound = false;
+
+        if let Some(combinator) = chars.peek().and_then(combinator_scope) {
+            if !relative {
+                return Err(chars.error("a selector"));
+            }
+
+            chars.next().unwrap();
+            skip_whitespace(chars);
+            scope = combinator;
+            expects_compound = true;
+        }
+
+        loop {
+            let parts = Selector::create_list(chars)?;
+
+            if parts.is_empty() {
+                if expects_compound {
+                    return Err(chars.error("a selector"));
+                }
 
-        let selector_parts = SelectorParts {
-            inner_iter: normalized_selector.split_whitespace().into_iter().map(|s| s.to_string()),
+                return Ok(compound_selectors);
+            }
+
+            compound_selectors.push(CompoundSelector {
+                scope,
+                parts,
+            });
+
+            let skipped_whitespace = skip_whitespace(chars);
+
+            match chars.peek() {
+                None | Some(',') | Some(')') =>
+                    return Ok(compound_selectors),
+
+                Some(c) => {
+                    if let Some(combinator) = combinator_scope(c) {
+                        chars.next().unwrap();
+                        skip_whitespace(chars);
+                        scope = combinator;
+                    } else if skipped_whitespace {
+                        scope = Scope::IndirectChild;
+                    } else {
+                        return Err(chars.error("a combinator or `,`"));
+                    }
+
+                    expects_compound = true;
+                },
+            }
+        }
+    }
+}
+
+/// Returns the specificity of the most specific selector in the list, as
+/// used by `:is()`, `:not()` and `:has()`.
+fn list_specificity(selector_list: &[Vec<CompoundSelector>]) -> (u32, u32, u32) {
+    selector_list.iter()
+        .map(|compound_selectors| compound_selectors.iter().fold((0, 0, 0), |total, compound_selector| {
+            add_specificity(total, compound_selector.specificity())
+        }))
+        .max()
+        .unwrap_or((0, 0, 0))
+}
+
+fn add_specificity(left: (u32, u32, u32), right: (u32, u32, u32)) -> (u32, u32, u32) {
+    (left.0 + right.0, left.1 + right.1, left.2 + right.2)
+}
+
+impl Selector {
+    /// Returns the specificity of the selector as the CSS `(a, b, c)` tuple,
+    /// counting ids, then classes, attributes and pseudo-classes, then type
+    /// selectors.
+    pub fn specificity(&self) -> (u32, u32, u32) {
+        match *self {
+            Selector::Id(_) => (1, 0, 0),
+            Selector::Class(_) | Selector::Attribute(..) => (0, 1, 0),
+            Selector::TagName(_) => (0, 0, 1),
+            Selector::Universal | Selector::Namespace(_) => (0, 0, 0),
+
+            Selector::PseudoClass(PseudoClass::Not(ref selector_list)) |
+            Selector::PseudoClass(PseudoClass::Is(ref selector_list)) |
+            Selector::PseudoClass(PseudoClass::Has(ref selector_list)) =>
+                list_specificity(selector_list),
+
+            Selector::PseudoClass(PseudoClass::Where(_)) => (0, 0, 0),
+            Selector::PseudoClass(_) => (0, 1, 0),
+        }
+    }
+}
+
+impl CompoundSelector {
+    /// Returns the specificity of the compound selector as the CSS
+    /// `(a, b, c)` tuple. Tuples compare in the order of precedence, so the
+    /// greater tuple wins between competing rules.
+    pub fn specificity(&self) -> (u32, u32, u32) {
+        self.parts.iter().fold((0, 0, 0), |total, part| add_specificity(total, part.specificity()))
+    }
+}
+
+/// Writes the name as a CSS identifier, escaping the characters which cannot
+/// appear in it literally.
+fn write_identifier(formatter: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    let starts_with_hyphen = name.starts_with('-');
+
+    for (index, c) in name.chars().enumerate() {
+        let leading_digit = c.is_ascii_digit() && (index == 0 || (index == 1 && starts_with_hyphen));
+
+        if c == '\0' {
+            formatter.write_str("\u{FFFD}")?;
+        } else if (c.is_control() && c < '\u{80}') || leading_digit {
+            write!(formatter, "\\{:x} ", c as u32)?;
+        } else if c == '-' && index == 0 && name.len() == 1 {
+            formatter.write_str("\\-")?;
+        } else if is_name_character(c) {
+            write!(formatter, "{}", c)?;
+        } else {
+            write!(formatter, "\\{}", c)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the value as a double-quoted CSS string.
+fn write_string(formatter: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    formatter.write_str("\"")?;
+
+    for c in value.chars() {
+        if c == '\0' {
+            formatter.write_str("\u{FFFD}")?;
+        } else if c.is_control() && c < '\u{80}' {
+            write!(formatter, "\\{:x} ", c as u32)?;
+        } else if c == '"' || c == '\\' {
+            write!(formatter, "\\{}", c)?;
+        } else {
+            write!(formatter, "{}", c)?;
+        }
+    }
+
+    formatter.write_str("\"")
+}
+
+fn write_selector_list(formatter: &mut fmt::Formatter, selector_list: &[Vec<CompoundSelector>]) -> fmt::Result {
+    for (index, compound_selectors) in selector_list.iter().enumerate() {
+        if index > 0 {
+            formatter.write_str(", ")?;
+        }
+
+        for (index, compound_selector) in compound_selectors.iter().enumerate() {
+            if index > 0 {
+                formatter.write_str(" ")?;
+            }
+
+            write!(formatter, "{}", compound_selector)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the canonical form of the selector list, which parses back to an
+/// equal `SelectorList`.
+impl fmt::Display for SelectorList {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write_selector_list(formatter, &self.selectors)
+    }
+}
+
+/// Writes the compound selector preceded by its combinator, if any (e.g.
+/// `> title`), so that the compound selectors of a complex selector can be
+/// joined with spaces.
+impl fmt::Display for CompoundSelector {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.scope {
+            Scope::DirectChild => formatter.write_str("> ")?,
+            Scope::AdjacentSibling => formatter.write_str("+ ")?,
+            Scope::GeneralSibling => formatter.write_str("~ ")?,
+            Scope::IndirectChild => { },
+        }
+
+        for part in &self.parts {
+            write!(formatter, "{}", part)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::Id(ref id) => {
+                formatter.write_str("#")?;
+                write_identifier(formatter, id)
+            },
+
+            Selector::TagName(ref name) => write_identifier(formatter, name),
+            Selector::Universal => formatter.write_str("*"),
+
+            Selector::Class(ref class) => {
+                formatter.write_str(".")?;
+                write_identifier(formatter, class)
+            },
+
+            Selector::Namespace(ref namespace_prefix) => write!(formatter, "{}", namespace_prefix),
+
+            Selector::Attribute(ref namespace_prefix, ref name, match_type, ref value, case_sensitivity) => {
+                formatter.write_str("[")?;
+
+                if let Some(ref namespace_prefix) = *namespace_prefix {
+                    write!(formatter, "{}", namespace_prefix)?;
+                }
+
+                write_identifier(formatter, name)?;
+
+                let operator = match match_type {
+                    MatchType::Exists => return formatter.write_str("]"),
+                    MatchType::Equals => "=",
+                    MatchType::Includes => "~=",
+                    MatchType::DashMatch => "|=",
+                    MatchType::Prefix => "^=",
+                    MatchType::Suffix => "$=",
+                    MatchType::Substring => "*=",
+                };
+
+                formatter.write_str(operator)?;
+                write_string(formatter, value)?;
+
+                if case_sensitivity == CaseSensitivity::AsciiCaseInsensitive {
+                    formatter.write_str(" i")?;
+                }
+
+                formatter.write_str("]")
+            },
+
+            Selector::PseudoClass(ref pseudo_class) => write!(formatter, "{}", pseudo_class),
+        }
+    }
+}
+
+/// Writes the prefix followed by the `|` separator (e.g. `svg|`). Resolved
+/// prefixes are written as the prefix itself.
+impl fmt::Display for NamespacePrefix {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NamespacePrefix::Any => formatter.write_str("*|"),
+            NamespacePrefix::NoNamespace => formatter.write_str("|"),
+
+            NamespacePrefix::Named(ref prefix) | NamespacePrefix::Resolved(ref prefix, _) => {
+                write_identifier(formatter, prefix)?;
+                formatter.write_str("|")
+            },
+        }
+    }
+}
+
+impl fmt::Display for PseudoClass {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let (name, selector_list) = match *self {
+            PseudoClass::Root => return formatter.write_str(":root"),
+            PseudoClass::Empty => return formatter.write_str(":empty"),
+            PseudoClass::FirstChild => return formatter.write_str(":first-child"),
+            PseudoClass::LastChild => return formatter.write_str(":last-child"),
+            PseudoClass::OnlyChild => return formatter.write_str(":only-child"),
+            PseudoClass::FirstOfType => return formatter.write_str(":first-of-type"),
+            PseudoClass::LastOfType => return formatter.write_str(":last-of-type"),
+            PseudoClass::OnlyOfType => return formatter.write_str(":only-of-type"),
+            PseudoClass::NthChild(nth) => return write!(formatter, ":nth-child({})", nth),
+            PseudoClass::NthLastChild(nth) => return write!(formatter, ":nth-last-child({})", nth),
+            PseudoClass::NthOfType(nth) => return write!(formatter, ":nth-of-type({})", nth),
+            PseudoClass::NthLastOfType(nth) => return write!(formatter, ":nth-last-of-type({})", nth),
+            PseudoClass::Not(ref selector_list) => ("not", selector_list),
+            PseudoClass::Is(ref selector_list) => ("is", selector_list),
+            PseudoClass::Where(ref selector_list) => ("where", selector_list),
+            PseudoClass::Has(ref selector_list) => ("has", selector_list),
         };
 
-        selector_parts
-           .fold(Ok(Vec::new()), |result_so_far, (scope, part)| {
-               if let Ok(mut compound_selectors) = result_so_far {
-                   Selector::create_list(&part).map(|parts| {
-                       compound_selectors.push(CompoundSelector {
-                           scope: scope,
-                           parts: parts
-                       });
-
-                       compound_selectors
-                   })
-               } else {
-                   result_so_far
-               }
-           })
+        write!(formatter, ":{}(", name)?;
+        write_selector_list(formatter, selector_list)?;
+        formatter.write_str(")")
+    }
+}
+
+/// Writes the expression in its shortest form (e.g. `2n+1`, `-n+3` or `4`).
+impl fmt::Display for Nth {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.a {
+            0 => return write!(formatter, "{}", self.b),
+            1 => formatter.write_str("n")?,
+            -1 => formatter.write_str("-n")?,
+            a => write!(formatter, "{}n", a)?,
+        }
+
+        match self.b {
+            0 => Ok(()),
+            b if b > 0 => write!(formatter, "+{}", b),
+            b => write!(formatter, "{}", b),
+        }
     }
 }
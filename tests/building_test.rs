@@ -0,0 +1,153 @@
+use rquery::{ BuildError, Document, DocumentBuilder, Element, ElementBuilder, Node };
+
+fn new_parsed_document() -> Document {
+    Document::new_from_xml_string(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- A fixture -->
+<sample type="simple"><title>Simple Sample</title><related><item id="id-1"><title>Another Sample</title><ref>http://path.to.somewhere</ref></item><item id="id-2"><title>Other Sample</title><ref>http://some.other.path</ref><note/></item></related></sample>"#).unwrap()
+}
+
+fn new_item(id: &str, title: &str, reference: &str) -> ElementBuilder {
+    ElementBuilder::new("item")
+        .attr("id", id)
+        .child(ElementBuilder::new("title").text(title))
+        .child(ElementBuilder::new("ref").text(reference))
+}
+
+fn new_built_document() -> Document {
+    DocumentBuilder::new()
+        .comment(" A fixture ")
+        .child(ElementBuilder::new("sample")
+            .attr("type", "simple")
+            .child(ElementBuilder::new("title").text("Simple Sample"))
+            .child(ElementBuilder::new("related")
+                .child(new_item("id-1", "Another Sample", "http://path.to.somewhere"))
+                .child(new_item("id-2", "Other Sample", "http://some.other.path")
+                    .child(ElementBuilder::new("note")))))
+        .build()
+        .unwrap()
+}
+
+fn describe(document: &Document, selector: &str) -> Vec<(usize, String)> {
+    document.select_all(selector).unwrap()
+        .map(|element| (element.node_index(), element.tag_name().to_string()))
+        .collect()
+}
+
+#[test]
+fn it_builds_the_same_document_as_parsing() {
+    let parsed = new_parsed_document();
+    let built = new_built_document();
+
+    assert_eq!(built.to_string(), parsed.to_string());
+    assert_eq!(built.number_of_elements(), parsed.number_of_elements());
+
+    for selector in &["*", "item > ref", "related title", "item:last-child > :empty", "[id=id-2] ~ *"] {
+        assert_eq!(describe(&built, selector), describe(&parsed, selector));
+    }
+}
+
+#[test]
+fn it_assigns_node_indices_in_document_order() {
+    let document = new_built_document();
+
+    let elements: Vec<Element> = document.select_all("*").unwrap().collect();
+    let node_indices: Vec<usize> = elements.iter().map(|element| element.node_index()).collect();
+
+    assert_eq!(node_indices, (1..11).collect::<Vec<usize>>());
+    assert_eq!(elements[3].parent().unwrap().tag_name(), "related");
+    assert_eq!(document.select("title").unwrap().text(), "Simple Sample");
+}
+
+#[test]
+fn it_requires_exactly_one_top_level_element() {
+    let empty = DocumentBuilder::new().comment(" nothing ").build();
+    assert_eq!(empty.unwrap_err(), BuildError::NoTopLevelElement);
+
+    let several = DocumentBuilder::new()
+        .child(ElementBuilder::new("a"))
+        .processing_instruction("target", None)
+        .child(ElementBuilder::new("b"))
+        .build();
+    assert_eq!(several.unwrap_err(), BuildError::MultipleTopLevelElements);
+
+    let single = DocumentBuilder::new().processing_instruction("target", None).child(ElementBuilder::new("a")).build();
+    assert!(single.unwrap().to_string().ends_with("<?target?>\n<a/>"));
+}
+
+#[test]
+fn it_builds_mixed_content() {
+    let document = ElementBuilder::new("p")
+        .text("a")
+        .child(ElementBuilder::new("b").text("x"))
+        .comment(" note ")
+        .cdata("<d>")
+        .processing_instruction("target", Some("data"))
+        .build();
+
+    let p = document.select("p").unwrap();
+    let b = document.select("b").unwrap();
+
+    assert_eq!(p.child_nodes().collect::<Vec<Node>>(), vec!(
+        Node::Text("a"),
+        Node::Element(b),
+        Node::Comment(" note "),
+        Node::CData("<d>"),
+        Node::ProcessingInstruction("target", Some("data")),
+    ));
+    assert_eq!(p.outer_xml(), "<p>a<b>x</b><!-- note --><![CDATA[<d>]]><?target data?></p>");
+}
+
+#[test]
+fn it_resolves_namespaces_from_xmlns_attributes() {
+    let document = ElementBuilder::new("root")
+        .attr("xmlns", "urn:default")
+        .attr("xmlns:x", "urn:x")
+        .child(ElementBuilder::new("x:item").attr("x:id", "1").attr("plain", "2"))
+        .build();
+
+    let item = document.select("item").unwrap();
+    assert_eq!(item.namespace_uri(), Some("urn:x"));
+    assert_eq!(item.attr_ns(Some("urn:x"), "id").unwrap(), "1");
+    assert_eq!(item.attr_ns(None, "plain").unwrap(), "2");
+    assert_eq!(document.select("root").unwrap().namespace_uri(), Some("urn:default"));
+    assert_eq!(document.select("root").unwrap().attributes().count(), 0);
+
+    let reparsed = Document::new_from_xml_string(&document.to_string()).unwrap();
+    assert_eq!(reparsed.to_string(), document.to_string());
+}
+
+#[test]
+fn it_builds_documents_with_the_xml_macro() {
+    let document = xml! {
+        sample(type = "simple") {
+            title { "Simple Sample" }
+            related {
+                item(id = "id-1") {
+                    title { "Another Sample" }
+                    ref { "http://path.to.somewhere" }
+                }
+                item(id = format!("id-{}", 2),) {
+                    title { "Other Sample" }
+                    ref { "http://some.other.path" }
+                    note
+                }
+            }
+        }
+    };
+
+    let parsed = Document::new_from_xml_string(&new_parsed_document().select("sample").unwrap().outer_xml()).unwrap();
+
+    assert_eq!(document.to_string(), parsed.to_string());
+    assert_eq!(describe(&document, "*"), describe(&parsed, "*"));
+}
+
+#[test]
+fn it_supports_literal_names_and_text_in_the_xml_macro() {
+    let document = xml! {
+        p("data-index" = 1, class = "intro") { "Some " b { "bold" } " text" br() }
+    };
+
+    let p = document.select("p.intro").unwrap();
+    assert_eq!(p.attr("data-index").unwrap(), "1");
+    assert_eq!(p.outer_xml(), r#"<p data-index="1" class="intro">Some <b>bold</b> text<br/></p>"#);
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use rquery::{ Document, Element, SelectorList };
+
+use super::querying_by_selectors_test::new_document;
+
+fn node_indexes<'a, I: Iterator<Item=Element<'a>>>(iterator: I) -> Vec<usize> {
+    iterator.map(|element| element.node_index()).collect()
+}
+
+#[test]
+fn it_parses_selector_lists_with_parse_and_from_str() {
+    let selector_list = SelectorList::parse("item > title, ref").unwrap();
+
+    assert_eq!("item > title, ref".parse::<SelectorList>().unwrap(), selector_list);
+    assert_eq!(selector_list.selectors().len(), 2);
+    assert_eq!(selector_list.to_string(), "item > title, ref");
+    assert_eq!(selector_list.to_string().parse::<SelectorList>().unwrap(), selector_list);
+
+    let error = "item >".parse::<SelectorList>().unwrap_err();
+    assert_eq!(error.offset, 6);
+    assert_eq!(error.found, None);
+}
+
+#[test]
+fn it_selects_the_same_elements_as_the_string_selector() {
+    let document = new_document();
+
+    let selectors = vec!(
+        "title",
+        "item > title, ref",
+        "related item:first-child ~ item",
+        "div[type] div",
+        "title + ref, note ~ div",
+        "item:has(> ref):not([id=id-1])",
+    );
+
+    for selector in selectors {
+        let selector_list = SelectorList::parse(selector).unwrap();
+
+        assert_eq!(
+            node_indexes(document.select_all_compiled(&selector_list)),
+            node_indexes(document.select_all(selector).unwrap()),
+            "{}", selector,
+        );
+    }
+}
+
+#[test]
+fn it_can_reuse_a_compiled_selector_across_elements() {
+    let document = new_document();
+    let selector_list = SelectorList::parse("title").unwrap();
+
+    let titles: Vec<String> = document.select_all("item").unwrap()
+        .flat_map(|item| item.select_all_compiled(&selector_list))
+        .map(|title| title.text().into_owned())
+        .collect();
+
+    assert_eq!(titles, vec!("Another Sample", "Other Sample"));
+
+    let item = document.select("#id-2").unwrap();
+    assert_eq!(item.select_compiled(&selector_list).unwrap().text(), "Other Sample");
+    assert_eq!(item.select_compiled(&SelectorList::parse("note").unwrap()), None);
+}
+
+#[test]
+fn it_knows_whether_an_element_matches_a_compiled_selector() {
+    let document = new_document();
+    let title = document.select("#id-2 > title").unwrap();
+
+    assert!(title.matches(&SelectorList::parse("title").unwrap()));
+    assert!(title.matches(&SelectorList::parse("related > item title").unwrap()));
+    assert!(title.matches(&SelectorList::parse("ref, #id-2 > *").unwrap()));
+    assert!(!title.matches(&SelectorList::parse("#id-1 title").unwrap()));
+    assert!(!title.matches(&SelectorList::parse("ref + title").unwrap()));
+}
+
+#[test]
+fn it_can_resolve_namespaces_in_a_compiled_selector() {
+    let document = Document::new_from_xml_string(r#"
+<drawing xmlns:s="http://www.w3.org/2000/svg">
+  <s:svg><s:rect/></s:svg>
+</drawing>
+"#).unwrap();
+
+    let mut namespaces = HashMap::new();
+    namespaces.insert("svg".to_string(), "http://www.w3.org/2000/svg".to_string());
+
+    let mut selector_list = SelectorList::parse("svg|rect").unwrap();
+    assert_eq!(document.select_all_compiled(&selector_list).count(), 0);
+
+    selector_list.resolve_namespaces(&namespaces);
+    assert_eq!(document.select_compiled(&selector_list).unwrap().tag_name(), "rect");
+}
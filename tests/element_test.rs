@@ -41,4 +41,76 @@ fn it_knows_its_node_indices() {
     
     let element = document.select("main").unwrap();
     assert_eq!(element.node_index(), 1);
-}
\ No newline at end of file
+}
+
+fn new_namespaced_document() -> Document {
+    Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+  <media:content url="http://path.to.somewhere" media:url="http://some.other.path"/>
+</feed>
+"#).unwrap()
+}
+
+#[test]
+fn it_knows_its_namespace() {
+    let document = new_namespaced_document();
+
+    let feed = document.select("feed").unwrap();
+    assert_eq!(feed.prefix(), None);
+    assert_eq!(feed.namespace_uri(), Some("http://www.w3.org/2005/Atom"));
+
+    let content = document.select("content").unwrap();
+    assert_eq!(content.prefix(), Some("media"));
+    assert_eq!(content.namespace_uri(), Some("http://search.yahoo.com/mrss/"));
+}
+
+#[test]
+fn it_keeps_attributes_from_different_namespaces_apart() {
+    let document = new_namespaced_document();
+
+    let element = document.select("content").unwrap();
+    assert_eq!(element.attr("url").unwrap(), "http://path.to.somewhere");
+    assert_eq!(element.attr("media:url").unwrap(), "http://some.other.path");
+    assert_eq!(element.attr_ns(Some("http://search.yahoo.com/mrss/"), "url").unwrap(), "http://some.other.path");
+    assert_eq!(element.attr_ns(None, "url").unwrap(), "http://path.to.somewhere");
+
+    let attributes: Vec<(Option<&str>, &str, Option<&str>)> = element.attributes()
+        .map(|attribute| (attribute.prefix(), attribute.name(), attribute.namespace_uri()))
+        .collect();
+    assert_eq!(attributes, vec!(
+        (None, "url", None),
+        (Some("media"), "url", Some("http://search.yahoo.com/mrss/")),
+    ));
+}
+
+fn new_mixed_content_document() -> Document {
+    Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<p>
+  Some   <b>bold
+  text</b> and <![CDATA[<raw>]]> <!-- ignored --><i>more</i>
+</p>
+"#).unwrap()
+}
+
+#[test]
+fn it_knows_the_text_content_of_its_descendants() {
+    let document = new_mixed_content_document();
+
+    let element = document.select("p").unwrap();
+    assert_eq!(element.text_content(), "\n  Some   bold\n  text and <raw> more\n");
+
+    let texts: Vec<&str> = element.text_descendants().collect();
+    assert_eq!(texts, vec!("\n  Some   ", "bold\n  text", " and ", "<raw>", " ", "more", "\n"));
+}
+
+#[test]
+fn it_knows_its_normalized_text() {
+    let document = new_mixed_content_document();
+
+    let element = document.select("p").unwrap();
+    assert_eq!(element.normalized_text(), "Some bold text and <raw> more");
+    assert_eq!(element.own_text(), "Some and <raw>");
+    assert_eq!(document.select("b").unwrap().own_text(), "bold text");
+}
@@ -0,0 +1,163 @@
+use rquery::{ Document, Element };
+
+fn tag_names<'a, I: Iterator<Item=Element<'a>>>(iterator: I) -> Vec<&'a str> {
+    iterator.map(|element| element.tag_name()).collect()
+}
+
+fn new_document() -> Document {
+    Document::new_from_html_file("tests/fixtures/sample.html").unwrap()
+}
+
+#[test]
+fn it_implies_the_html_head_and_body_elements() {
+    let document = Document::new_from_html_string("<p>Hello").unwrap();
+
+    assert_eq!(tag_names(document.select_all("*").unwrap()), vec!("html", "head", "body", "p"));
+    assert_eq!(document.select("body > p").unwrap().text(), "Hello");
+}
+
+#[test]
+fn it_places_metadata_in_the_head() {
+    let document = new_document();
+
+    assert_eq!(tag_names(document.select("head").unwrap().children_iter()), vec!("title", "meta", "link", "script"));
+    assert_eq!(document.select("title").unwrap().text(), "Sample & Page");
+    assert_eq!(document.select("html").unwrap().attr("lang").unwrap(), "en");
+}
+
+#[test]
+fn it_keeps_raw_text_elements_as_text() {
+    let document = new_document();
+
+    let script = document.select("script").unwrap();
+    assert_eq!(script.text(), r#"if (a < b && c) { document.write("</p>"); }"#);
+    assert_eq!(script.children_iter().count(), 0);
+}
+
+#[test]
+fn it_keeps_everything_after_a_plaintext_start_tag_as_text() {
+    let document = Document::new_from_html_string("<plaintext>a</>b</plaintext><p>&amp;").unwrap();
+
+    assert_eq!(document.select("plaintext").unwrap().text(), "a</>b</plaintext><p>&amp;");
+    assert!(document.select("p").is_err());
+}
+
+#[test]
+fn it_folds_the_case_of_names_and_decodes_entities() {
+    let document = new_document();
+
+    let heading = document.select("h1.main").unwrap();
+    assert_eq!(heading.text(), "Welcome\u{a0}home");
+    assert_eq!(document.select("img").unwrap().attr("alt").unwrap(), "A © 2024");
+}
+
+#[test]
+fn it_decodes_every_named_character_reference() {
+    let document = Document::new_from_html_string("<p title='&NotEqualTilde;'>&fjlig;ords &Ropf; &lang; &copy &notit;</p>").unwrap();
+
+    let paragraph = document.select("p").unwrap();
+    assert_eq!(paragraph.text(), "fjords \u{211D} \u{27E8} \u{A9} \u{AC}it;");
+    assert_eq!(paragraph.attr("title").unwrap(), "\u{2242}\u{338}");
+}
+
+#[test]
+fn it_closes_implied_end_tags() {
+    let document = new_document();
+
+    let paragraphs: Vec<Element> = document.select_all("body > p").unwrap().collect();
+    assert_eq!(paragraphs.len(), 2);
+    assert_eq!(paragraphs[0].text(), "First paragraph\n");
+
+    assert_eq!(document.select_all("ul > li").unwrap().count(), 2);
+    assert_eq!(document.select("li:last-child").unwrap().text(), "Two\n");
+}
+
+#[test]
+fn it_implies_table_sections_and_closes_cells() {
+    let document = new_document();
+
+    assert_eq!(document.select_all("table > tbody > tr").unwrap().count(), 2);
+    assert_eq!(document.select_all("tr > td").unwrap().map(|td| td.text_content()).collect::<Vec<String>>(), vec!(
+        "a", "b\n  ", "c", "d\n",
+    ));
+}
+
+#[test]
+fn it_supports_void_elements_and_attributes_without_values() {
+    let document = new_document();
+
+    let input = document.select("input[checked]").unwrap();
+    assert_eq!(input.attr("type").unwrap(), "checkbox");
+    assert_eq!(input.attr("disabled").unwrap(), "");
+    assert_eq!(input.children_iter().count(), 0);
+    assert_eq!(document.select("input + img").unwrap().attr("src").unwrap(), "a.png");
+    assert_eq!(document.select_all("p br").unwrap().count(), 1);
+}
+
+#[test]
+fn it_recovers_from_misnested_and_stray_tags() {
+    let document = Document::new_from_html_string("<div><span>a</div>b</span></em><p>c</p></p>").unwrap();
+
+    assert_eq!(tag_names(document.select_all("body *").unwrap()), vec!("div", "span", "p", "p"));
+    assert_eq!(document.select("body").unwrap().text(), "b");
+    assert_eq!(document.select("p:empty").unwrap().node_index(), 7);
+}
+
+#[test]
+fn it_reopens_misnested_formatting_elements() {
+    let inner_html = |html| Document::new_from_html_string(html).unwrap().select("body").unwrap().inner_xml();
+
+    assert_eq!(inner_html("<b><i></b></i>"), "<b><i/></b>");
+    assert_eq!(inner_html("<b><i>a</b>b</i>c"), "<b><i>a</i></b><i>b</i>c");
+    assert_eq!(inner_html("<b>1<p>2</b>3</p>"), "<b>1</b><p><b>2</b>3</p>");
+    assert_eq!(inner_html("<a href=x>1<div>2</a>3</div>"), r#"<a href="x">1</a><div><a href="x">2</a>3</div>"#);
+    assert_eq!(inner_html("<a>1<a>2"), "<a>1</a><a>2</a>");
+}
+
+#[test]
+fn it_moves_content_which_does_not_belong_in_a_table_before_it() {
+    let document = Document::new_from_html_string("<table><tr><td>1</td></tr>text<div>2</div> </table>").unwrap();
+
+    assert_eq!(document.select("body").unwrap().inner_xml(), "text<div>2</div><table><tbody><tr><td>1</td></tr> </tbody></table>");
+    assert_eq!(tag_names(document.select_all("body *").unwrap()), vec!("div", "table", "tbody", "tr", "td"));
+    assert_eq!(document.select("div + table").unwrap().node_index(), 5);
+
+    let document = Document::new_from_html_string("<table><b><tr><td>a</td></tr>b</table>c").unwrap();
+
+    assert_eq!(document.select("body").unwrap().inner_xml(), "<b/><b>b</b><table><tbody><tr><td>a</td></tr></tbody></table><b>c</b>");
+}
+
+#[test]
+fn it_keeps_comments_and_ignores_the_doctype() {
+    let document = Document::new_from_html_string("<!DOCTYPE html><!-- top --><p>a<!-- inner -->b").unwrap();
+
+    assert_eq!(document.child_nodes().count(), 2);
+    assert_eq!(document.select("p").unwrap().text(), "ab");
+}
+
+#[test]
+fn it_honors_self_closing_tags_in_foreign_content() {
+    let document = Document::new_from_html_string("<svg><circle r=1 /><rect/></svg><div/>text").unwrap();
+
+    assert_eq!(tag_names(document.select("svg").unwrap().children_iter()), vec!("circle", "rect"));
+    assert_eq!(document.select("div").unwrap().text(), "text");
+}
+
+#[test]
+fn it_restores_the_case_of_names_in_svg_and_mathml_content() {
+    let document = Document::new_from_html_string(concat!(
+        "<svg VIEWBOX='0 0 10 10'><clipPath id=a><rect/></clippath><linearGradient/></svg>",
+        "<math definitionurl=x></math><div viewBox=b></div>",
+    )).unwrap();
+
+    let svg = document.select("svg").unwrap();
+    assert_eq!(svg.attr("viewBox").unwrap(), "0 0 10 10");
+    assert_eq!(tag_names(svg.children_iter()), vec!("clipPath", "linearGradient"));
+    assert_eq!(document.select("math").unwrap().attr("definitionURL").unwrap(), "x");
+    assert_eq!(document.select("div").unwrap().attr("viewbox").unwrap(), "b");
+}
+
+#[test]
+fn it_returns_an_error_for_non_existent_html_files() {
+    assert!(Document::new_from_html_file("non-existent.why").is_err());
+}
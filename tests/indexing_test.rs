@@ -0,0 +1,119 @@
+use rquery::{ Document, Element, Node, ParseOptions };
+
+const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sample type="simple">
+  <title id="main">Simple Sample</title>
+  <form>
+    <input type="radio" id="first"/>
+    <input type="text"/>
+    <group id="group">
+      <input type="radio"/>
+      <title>Group Title</title>
+    </group>
+    <input type="radio" id="first"/>
+  </form>
+  <related>
+    <item id="id-1">
+      <title>Another Sample</title>
+      <ref>http://path.to.somewhere</ref>
+    </item>
+    <item id="id-2">
+      <title>Other Sample</title>
+      <ref>http://some.other.path</ref>
+    </item>
+  </related>
+</sample>
+"#;
+
+fn new_indexed_document() -> Document {
+    let options = ParseOptions {
+        index: true,
+        indexed_attributes: vec!("type".to_string()),
+        ..ParseOptions::default()
+    };
+
+    Document::new_from_xml_string_with_options(XML, &options).unwrap()
+}
+
+fn node_indexes<'a, I: Iterator<Item=Element<'a>>>(iterator: I) -> Vec<usize> {
+    iterator.map(|element| element.node_index()).collect()
+}
+
+#[test]
+fn it_gets_elements_by_id() {
+    let indexed = new_indexed_document();
+    let unindexed = Document::new_from_xml_string(XML).unwrap();
+
+    for document in &[indexed, unindexed] {
+        assert_eq!(document.get_element_by_id("main").unwrap().text(), "Simple Sample");
+        assert_eq!(document.get_element_by_id("id-2").unwrap().tag_name(), "item");
+        assert_eq!(document.get_element_by_id("first").unwrap().node_index(), 4);
+        assert_eq!(document.get_element_by_id("missing"), None);
+    }
+}
+
+#[test]
+fn it_selects_the_same_elements_with_and_without_indexes() {
+    let indexed = new_indexed_document();
+    let unindexed = Document::new_from_xml_string(XML).unwrap();
+
+    let selectors = vec!(
+        "#first", "#id-2 > title", "title", "item title, #main", "input[type=radio]",
+        "[type='radio']", "[type=radio i]", "form > input + input", "title:not(#main)",
+        "input ~ #first", "group title", "*", "ref, input[type=text]",
+    );
+
+    for selector in selectors {
+        assert_eq!(
+            node_indexes(indexed.select_all(selector).unwrap()),
+            node_indexes(unindexed.select_all(selector).unwrap()),
+            "{}", selector,
+        );
+
+        for anchor in indexed.select_all("*").unwrap() {
+            let unindexed_anchor = unindexed.select_all("*").unwrap().find(|element| element.node_index() == anchor.node_index()).unwrap();
+
+            for relative_selector in &[selector.to_string(), format!("> {}", selector), format!("~ {}", selector)] {
+                assert_eq!(
+                    node_indexes(anchor.select_all(relative_selector).unwrap()),
+                    node_indexes(unindexed_anchor.select_all(relative_selector).unwrap()),
+                    "{} from {}", relative_selector, anchor.node_index(),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn it_keeps_the_indexes_up_to_date_when_the_document_changes() {
+    let mut document = new_indexed_document();
+
+    document.select_mut("#main").unwrap().set_attr("id", "renamed");
+    assert_eq!(document.get_element_by_id("main"), None);
+    assert_eq!(document.get_element_by_id("renamed").unwrap().text(), "Simple Sample");
+
+    document.select_mut("#id-1").unwrap().remove().unwrap();
+    assert_eq!(document.get_element_by_id("id-1"), None);
+    assert_eq!(document.select_all("item title").unwrap().count(), 1);
+    assert_eq!(document.get_element_by_id("id-2").unwrap().node_index(), 11);
+
+    document.select_mut("group").unwrap().set_tag_name("fieldset");
+    assert_eq!(document.select_all("group").unwrap().count(), 0);
+    assert_eq!(document.select_all("fieldset > [type=radio]").unwrap().count(), 1);
+
+    let new_item = Document::new_from_xml_string(r#"<item id="id-3"><title>New</title></item>"#).unwrap();
+    document.select_mut("related").unwrap().append_child(Node::Element(new_item.select("item").unwrap()));
+    assert_eq!(document.select("#id-3 > title").unwrap().text(), "New");
+    assert_eq!(document.select_all("related title").unwrap().count(), 2);
+}
+
+#[test]
+fn it_can_index_documents_built_in_other_ways() {
+    let mut document = Document::new_from_html_string(r#"<p id=intro>Hello<ul><li class=x>One<li id=two>Two</ul><input type=radio>"#).unwrap();
+    let expected = node_indexes(document.select_all("li, #intro, [type=radio]").unwrap());
+
+    document.build_indexes(&["type"]);
+
+    assert_eq!(node_indexes(document.select_all("li, #intro, [type=radio]").unwrap()), expected);
+    assert_eq!(document.get_element_by_id("two").unwrap().text(), "Two");
+}
@@ -1,3 +1,4 @@
+#[macro_use]
 extern crate rquery;
 
 #[cfg(test)]
@@ -14,3 +15,36 @@ mod document_test;
 
 #[cfg(test)]
 mod querying_by_selectors_test;
+
+#[cfg(test)]
+mod navigating_elements_test;
+
+#[cfg(test)]
+mod nodes_test;
+
+#[cfg(test)]
+mod serializing_test;
+
+#[cfg(test)]
+mod mutating_test;
+
+#[cfg(test)]
+mod building_test;
+
+#[cfg(test)]
+mod html_document_test;
+
+#[cfg(test)]
+mod malformed_input_test;
+
+#[cfg(test)]
+mod compiled_selectors_test;
+
+#[cfg(test)]
+mod selector_matching_test;
+
+#[cfg(test)]
+mod indexing_test;
+
+#[cfg(test)]
+mod parallel_querying_test;
@@ -0,0 +1,246 @@
+use std::error::Error;
+use std::io::ErrorKind;
+
+use rquery::{ Document, DocumentError, ParseOptions, SyntaxError, SyntaxErrorKind };
+
+const SEEDS: &[&str] = &[
+    r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- comment --><?pi data?>
+<sample type="simple" xmlns:x="urn:x">
+  <title x:lang='en'>Simple &amp; Sample</title>
+  <![CDATA[ <raw> ]]>
+  <item id="1"><ref/></item>
+</sample>"#,
+    "<!DOCTYPE html><html><head><title>T</title><script>a < b</script></head><body><p>One<p>Two &nbsp;&#169;<table><tr><td>x</table></body></html>",
+    "<a><b><c>text</c></b></a>",
+];
+
+const FRAGMENTS: &[&str] = &[
+    "<", ">", "</", "/>", "<!--", "-->", "<![CDATA[", "]]>", "<?", "?>", "&", "&amp;", "&#", "&#x", ";", "\"", "'",
+    "=", ":", "xmlns:", "<a>", "</a>", "<b", " ", "\u{0}", "\u{FEFF}", "é", "\r\n", "<!DOCTYPE",
+];
+
+/// A small deterministic pseudo-random number generator (xorshift), so that
+/// failures can be reproduced.
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+fn mutate(random: &mut Random, input: &str) -> String {
+    let mut bytes = input.as_bytes().to_vec();
+
+    for _ in 0..(1 + random.next(4)) {
+        let position = random.next(bytes.len() + 1);
+
+        match random.next(4) {
+            0 => bytes.truncate(position),
+            1 => {
+                let fragment = FRAGMENTS[random.next(FRAGMENTS.len())];
+                bytes.splice(position..position, fragment.bytes());
+            },
+            2 => {
+                let end = (position + random.next(8)).min(bytes.len());
+                bytes.drain(position..end);
+            },
+            _ => {
+                let end = (position + random.next(16)).min(bytes.len());
+                let copy: Vec<u8> = bytes[position..end].to_vec();
+                let target = random.next(bytes.len() + 1);
+                bytes.splice(target..target, copy);
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn exercise(input: &str) {
+    let lossless = ParseOptions {
+        lossless: true,
+        ..ParseOptions::default()
+    };
+
+    if let Ok(document) = Document::new_from_xml_string(input) {
+        let _ = document.to_string();
+        let _ = document.select_all("* > *:not(:empty)").map(|elements| elements.count());
+    }
+
+    if let Ok(document) = Document::new_from_xml_string_with_options(input, &lossless) {
+        assert_eq!(document.to_string(), input);
+    }
+
+    let document = Document::new_from_html_string(input).unwrap();
+    let _ = document.to_string();
+    let _ = document.select_all("body *").map(|elements| elements.count());
+
+    let _ = Document::new_from_xml_stream(input.as_bytes());
+    let _ = Document::new_from_html_stream(input.as_bytes());
+}
+
+#[test]
+fn it_never_panics_on_mutated_input() {
+    let mut random = Random(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..3000 {
+        let seed = SEEDS[random.next(SEEDS.len())];
+        exercise(&mutate(&mut random, seed));
+    }
+}
+
+#[test]
+fn it_never_panics_on_prefixes_of_valid_input() {
+    for seed in SEEDS {
+        for (end, _) in seed.char_indices() {
+            exercise(&seed[..end]);
+        }
+    }
+}
+
+fn syntax_error(xml: &str) -> SyntaxError {
+    match Document::new_from_xml_string(xml) {
+        Err(DocumentError::ParseError(error)) => error,
+        result => panic!("Expected a syntax error, got {:?}", result),
+    }
+}
+
+#[test]
+fn it_returns_an_error_for_empty_documents() {
+    assert_eq!(syntax_error("").kind, SyntaxErrorKind::EmptyDocument);
+    assert_eq!(syntax_error("  <!-- nothing -->  ").kind, SyntaxErrorKind::EmptyDocument);
+    assert_eq!(syntax_error(r#"<?xml version="1.0"?>"#).kind, SyntaxErrorKind::EmptyDocument);
+}
+
+#[test]
+fn it_returns_an_error_for_text_outside_the_root() {
+    assert_eq!(syntax_error("text<a/>").kind, SyntaxErrorKind::TextOutsideRoot);
+    assert_eq!(syntax_error("<a/>text").kind, SyntaxErrorKind::TextOutsideRoot);
+    assert_eq!(syntax_error("<a/><!-- > -->text").kind, SyntaxErrorKind::TextOutsideRoot);
+    assert_eq!(syntax_error("<!DOCTYPE a>\ntext<a/>").kind, SyntaxErrorKind::TextOutsideRoot);
+}
+
+#[test]
+fn it_returns_other_errors_for_malformed_markup_outside_elements() {
+    for xml in &["<a:b/>", "<a x='1' x='2'/>", "<a b/>", "<a/><b x/>", "<!DOCTYPE a [<!ENTITY e 'x'>]><a/>"] {
+        match syntax_error(xml).kind {
+            SyntaxErrorKind::Other(_) => { },
+            kind => panic!("Expected another error for {:?}, got {:?}", xml, kind),
+        }
+    }
+}
+
+#[test]
+fn it_returns_an_error_for_more_than_one_top_level_element() {
+    let error = syntax_error("<a><b/></a>\n<c/>");
+
+    assert_eq!(error.kind, SyntaxErrorKind::MultipleRootElements);
+    assert_eq!((error.line, error.column, error.offset), (2, 1, 12));
+    assert_eq!(syntax_error("<a/><!-- between --><b></b>").kind, SyntaxErrorKind::MultipleRootElements);
+}
+
+#[test]
+fn it_returns_an_error_for_mismatched_tags() {
+    assert_eq!(syntax_error("<a><b></a></b>").kind, SyntaxErrorKind::MismatchedTag {
+        expected: "b".to_string(),
+        found: "a".to_string(),
+    });
+}
+
+#[test]
+fn it_returns_an_error_for_unclosed_elements() {
+    assert_eq!(syntax_error("<a><b>").kind, SyntaxErrorKind::UnexpectedEndOfDocument);
+    assert_eq!(syntax_error("<a").kind, SyntaxErrorKind::UnexpectedEndOfDocument);
+    assert_eq!(syntax_error("<!-- unclosed").kind, SyntaxErrorKind::UnexpectedEndOfDocument);
+}
+
+#[test]
+fn it_returns_an_error_for_invalid_utf8() {
+    let options = ParseOptions {
+        lossless: true,
+        ..ParseOptions::default()
+    };
+
+    match Document::new_from_xml_stream_with_options(&b"<a>\n  caf\xff</a>"[..], &options) {
+        Err(DocumentError::ParseError(error)) => {
+            assert_eq!(error.kind, SyntaxErrorKind::InvalidEncoding);
+            assert_eq!((error.line, error.column, error.offset), (2, 6, 9));
+        },
+        result => panic!("Expected a syntax error, got {:?}", result),
+    }
+}
+
+#[test]
+fn it_locates_invalid_utf8_in_a_stream() {
+    match Document::new_from_xml_stream(&b"<a>\n  caf\xff</a>"[..]) {
+        Err(DocumentError::ParseError(error)) => {
+            assert_eq!(error.kind, SyntaxErrorKind::InvalidEncoding);
+            assert_eq!((error.line, error.column, error.offset), (2, 6, 9));
+        },
+        result => panic!("Expected a syntax error, got {:?}", result),
+    }
+}
+
+#[test]
+fn it_returns_a_parse_error_for_other_malformed_input() {
+    match syntax_error("<a>&unknown;</a>").kind {
+        SyntaxErrorKind::Other(_) => { },
+        kind => panic!("Expected another error, got {:?}", kind),
+    }
+}
+
+#[test]
+fn it_locates_syntax_errors_in_the_source() {
+    let error = syntax_error("<root>\n  <a>\n    <b></a>\n</root>");
+
+    assert_eq!((error.line, error.column, error.offset), (3, 11, 23));
+    assert_eq!(error.excerpt.as_deref(), Some("    <b></a>"));
+}
+
+#[test]
+fn it_counts_offsets_in_bytes_and_columns_in_characters() {
+    let error = syntax_error("<a>\n  é</b>");
+
+    assert_eq!((error.line, error.column, error.offset), (2, 7, 11));
+    assert_eq!(error.excerpt.as_deref(), Some("  é</b>"));
+}
+
+#[test]
+fn it_locates_syntax_errors_far_into_a_large_document() {
+    let xml = format!("<a>\n{}  <b></c>\n</a>", "<item>text</item>\n".repeat(20_000));
+    let error = syntax_error(&xml);
+
+    assert_eq!(error.kind, SyntaxErrorKind::MismatchedTag {
+        expected: "b".to_string(),
+        found: "c".to_string(),
+    });
+    assert_eq!((error.line, error.column, error.offset), (20_002, 9, xml.find("</c>").unwrap() + 3));
+    assert_eq!(error.excerpt.as_deref(), Some("  <b></c>"));
+}
+
+#[test]
+fn it_displays_syntax_errors_like_a_compiler_diagnostic() {
+    let error = Document::new_from_xml_string("<root>\n  <a>\n    <b></a>\n</root>").unwrap_err();
+
+    assert_eq!(error.to_string(), "3:11: mismatched end tag, expected </b> but found </a>
+  |
+3 |     <b></a>
+  |           ^");
+    assert!(error.source().is_some());
+}
+
+#[test]
+fn it_keeps_the_io_error_for_missing_files() {
+    match Document::new_from_xml_file("non-existent.why") {
+        Err(DocumentError::UnableToOpenFile(path, error)) => {
+            assert_eq!(path, "non-existent.why");
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+        },
+        result => panic!("Expected an error opening the file, got {:?}", result.map(|_| ())),
+    }
+}
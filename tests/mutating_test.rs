@@ -0,0 +1,289 @@
+use rquery::{ Document, Element, MutationError, Node, ParseOptions, SelectorList };
+
+fn new_document() -> Document {
+    Document::new_from_xml_string(r#"<?xml version="1.0" encoding="UTF-8"?>
+<sample type="simple">
+  <title>Simple Sample</title>
+  <related>
+    <item id="id-1">
+      <title>Another Sample</title>
+      <ref>http://path.to.somewhere</ref>
+    </item>
+    <item id="id-2">
+      <title>Other Sample</title>
+      <ref>http://some.other.path</ref>
+    </item>
+  </related>
+</sample>
+"#).unwrap()
+}
+
+fn tag_names(document: &Document) -> Vec<String> {
+    document.select_all("*").unwrap()
+        .map(|element| element.tag_name().to_string())
+        .collect()
+}
+
+fn assert_node_indices_are_in_document_order(document: &Document) {
+    let node_indices: Vec<usize> = document.select_all("*").unwrap()
+        .map(|element| element.node_index())
+        .collect();
+
+    assert_eq!(node_indices, (1..document.number_of_elements() + 1).collect::<Vec<usize>>());
+
+    for element in document.select_all("*").unwrap() {
+        for child in element.children_iter() {
+            assert_eq!(child.parent(), Some(element));
+        }
+    }
+}
+
+#[test]
+fn it_sets_and_removes_attributes() {
+    let mut document = new_document();
+
+    {
+        let mut item = document.select_mut("item").unwrap();
+        item.set_attr("id", "first");
+        item.set_attr("lang", "en");
+        assert_eq!(item.remove_attr("missing"), None);
+    }
+
+    let item = document.select("item").unwrap();
+    assert_eq!(item.attr("id").unwrap(), "first");
+    assert_eq!(item.attr("lang").unwrap(), "en");
+
+    assert_eq!(document.select_mut("sample").unwrap().remove_attr("type"), Some("simple".to_string()));
+    assert_eq!(document.select("sample").unwrap().attr("type"), None);
+    assert_eq!(document.select_all("item[lang=en]").unwrap().count(), 1);
+}
+
+#[test]
+fn it_renames_elements() {
+    let mut document = new_document();
+
+    document.select_mut("related").unwrap().set_tag_name("items");
+
+    assert!(document.select("related").is_err());
+    assert_eq!(document.select_all("items > item").unwrap().count(), 2);
+}
+
+#[test]
+fn it_renames_only_the_edited_element_of_those_sharing_a_name() {
+    let mut document = new_document();
+
+    {
+        let mut item = document.select_mut("item").unwrap();
+        item.set_tag_name("entry");
+        item.set_attr("id", "renamed");
+    }
+
+    assert_eq!(document.select_all("item").unwrap().count(), 1);
+    assert_eq!(document.select("entry").unwrap().attr("id").unwrap(), "renamed");
+    assert_eq!(document.select("item").unwrap().attr("id").unwrap(), "id-2");
+}
+
+#[test]
+fn it_sets_the_text_of_an_element() {
+    let mut document = new_document();
+
+    document.select_mut("item").unwrap().set_text("Replaced & escaped");
+
+    let item = document.select("item").unwrap();
+    assert_eq!(item.text(), "Replaced & escaped");
+    assert_eq!(item.children_iter().count(), 0);
+    assert_eq!(document.number_of_elements(), 7);
+    assert_node_indices_are_in_document_order(&document);
+}
+
+#[test]
+fn it_appends_and_inserts_nodes() {
+    let fragment = Document::new_from_xml_string(r#"<note kind="new"><b>bold</b></note>"#).unwrap();
+    let note = fragment.select("note").unwrap();
+
+    let mut document = new_document();
+
+    {
+        let mut item = document.select_mut("item").unwrap();
+        item.append_child(Node::Element(note));
+        item.insert_before(Node::Comment(" first ")).unwrap();
+        item.insert_before(Node::Element(note)).unwrap();
+
+        assert_eq!(item.as_element().tag_name(), "item");
+        assert_eq!(item.node_index(), 6);
+    }
+
+    assert_eq!(tag_names(&document), vec!(
+        "sample", "title", "related", "note", "b", "item", "title", "ref", "note", "b", "item", "title", "ref",
+    ));
+
+    let first_item = document.select("item").unwrap();
+    assert_eq!(first_item.previous_sibling().unwrap().tag_name(), "note");
+    assert_eq!(first_item.select("note > b").unwrap().text(), "bold");
+    assert_eq!(document.select_all("note[kind=new]").unwrap().count(), 2);
+    assert_node_indices_are_in_document_order(&document);
+}
+
+#[test]
+fn it_removes_and_replaces_elements() {
+    let mut document = new_document();
+
+    document.select_mut("item").unwrap().remove().unwrap();
+    document.select_mut("sample > title").unwrap().replace_with(Node::Text("Untitled")).unwrap();
+
+    assert_eq!(tag_names(&document), vec!("sample", "related", "item", "title", "ref"));
+    assert_eq!(document.select("item").unwrap().attr("id").unwrap(), "id-2");
+    assert!(document.select("sample").unwrap().text().contains("Untitled"));
+    assert_node_indices_are_in_document_order(&document);
+}
+
+#[test]
+fn it_keeps_the_top_level_element() {
+    let mut document = new_document();
+
+    assert_eq!(document.select_mut("sample").unwrap().remove(), Err(MutationError::RemovesTopLevelElement));
+    assert_eq!(document.select_mut("sample").unwrap().replace_with(Node::Text("gone")), Err(MutationError::RemovesTopLevelElement));
+    assert_eq!(document.select_all_mut("item, sample").unwrap().remove(), Err(MutationError::RemovesTopLevelElement));
+    assert_eq!(document.number_of_elements(), 9);
+
+    let fragment = Document::new_from_xml_string("<replaced/>").unwrap();
+    let replaced = fragment.select("replaced").unwrap();
+
+    {
+        let mut sample = document.select_mut("sample").unwrap();
+        assert_eq!(sample.insert_before(Node::Element(replaced)), Err(MutationError::AddsTopLevelNode));
+        assert_eq!(sample.insert_before(Node::Text("stray")), Err(MutationError::AddsTopLevelNode));
+        sample.insert_before(Node::Comment(" kept ")).unwrap();
+        sample.insert_before(Node::ProcessingInstruction("target", Some("data"))).unwrap();
+    }
+
+    assert_eq!(document.child_nodes().count(), 3);
+    assert!(Document::new_from_xml_string(&document.to_string()).is_ok());
+
+    document.select_mut("sample").unwrap().replace_with(Node::Element(replaced)).unwrap();
+
+    assert_eq!(tag_names(&document), vec!("replaced"));
+    assert!(Document::new_from_xml_string(&document.to_string()).is_ok());
+}
+
+#[test]
+fn it_edits_all_selected_elements() {
+    let mut document = new_document();
+
+    let selection = document.select_all_mut("item > ref").unwrap();
+    assert_eq!(selection.len(), 2);
+    selection.set_attr("checked", "true");
+
+    assert_eq!(document.select_all("ref[checked]").unwrap().count(), 2);
+
+    document.select_all_mut("item").unwrap().for_each(|mut item| {
+        let id = item.as_element().attr("id").unwrap().clone();
+        item.insert_before(Node::Comment(&id)).unwrap();
+        item.set_text(&id);
+    });
+
+    let items: Vec<Element> = document.select_all("item").unwrap().collect();
+    assert_eq!(items.iter().map(|item| item.text()).collect::<Vec<_>>(), vec!("id-1", "id-2"));
+
+    document.select_all_mut("item").unwrap().remove().unwrap();
+    assert_eq!(tag_names(&document), vec!("sample", "title", "related"));
+    assert_node_indices_are_in_document_order(&document);
+}
+
+#[test]
+fn it_navigates_siblings_while_editing_selected_elements() {
+    let fragment = Document::new_from_xml_string("<note/>").unwrap();
+    let note = fragment.select("note").unwrap();
+    let first_child = SelectorList::parse(":first-child").unwrap();
+
+    let mut document = new_document();
+
+    document.select_all_mut("item").unwrap().for_each(|mut item| {
+        item.insert_before(Node::Element(note)).unwrap();
+
+        let element = item.as_element();
+        assert_eq!(element.previous_sibling().unwrap().tag_name(), "note");
+        assert!(!element.matches(&first_child));
+    });
+
+    assert_eq!(tag_names(&document)[2..6], ["related", "note", "item", "title"]);
+    assert_node_indices_are_in_document_order(&document);
+}
+
+#[test]
+fn it_edits_selected_elements_nested_in_one_another() {
+    let mut document = new_document();
+
+    document.select_all_mut("related, item, ref").unwrap().for_each(|mut element| {
+        element.append_child(Node::Comment(" edited "));
+    });
+    assert_eq!(document.select("related").unwrap().child_nodes().count(), 6);
+    assert_node_indices_are_in_document_order(&document);
+
+    document.select_all_mut("item, item > title").unwrap().remove().unwrap();
+    assert_eq!(tag_names(&document), vec!("sample", "title", "related"));
+    assert_node_indices_are_in_document_order(&document);
+}
+
+#[test]
+fn it_resolves_namespaces_when_renaming_and_setting_attributes() {
+    let mut document = Document::new_from_xml_string(r#"<root xmlns:x="urn:x"><a/></root>"#).unwrap();
+
+    {
+        let mut a = document.select_mut("a").unwrap();
+        a.set_tag_name("x:b");
+        a.set_attr("x:id", "1");
+    }
+
+    let b = document.select("b").unwrap();
+    assert_eq!(b.namespace_uri(), Some("urn:x"));
+    assert_eq!(b.attr_ns(Some("urn:x"), "id").unwrap(), "1");
+    assert_eq!(b.outer_xml(), r#"<x:b xmlns:x="urn:x" x:id="1"/>"#);
+    assert!(document.to_string().ends_with(r#"<root xmlns:x="urn:x"><x:b x:id="1"/></root>"#));
+}
+
+#[test]
+fn it_changes_only_the_edited_parts_in_lossless_mode() {
+    let xml = "<?xml version='1.0'?>\n<config>\n    <item  id='a' >one</item>\n    <item id='b'/>\n    <!-- keep -->\n    <item id='c'>three</item>\n</config>\n";
+    let options = ParseOptions {
+        lossless: true,
+        ..ParseOptions::default()
+    };
+    let mut document = Document::new_from_xml_string_with_options(xml, &options).unwrap();
+
+    document.select_mut("item[id=b]").unwrap().remove().unwrap();
+    document.select_mut("item[id=c]").unwrap().set_attr("enabled", "no");
+
+    {
+        let mut config = document.select_mut("config").unwrap();
+        config.append_child(Node::Comment(" added "));
+    }
+
+    assert_eq!(document.to_string(), "<?xml version='1.0'?>\n<config>\n    <item  id='a' >one</item>\n    \n    <!-- keep -->\n    <item id='c' enabled=\"no\">three</item>\n<!-- added --></config>\n");
+}
+
+#[test]
+fn it_changes_only_the_edited_attributes_in_lossless_mode() {
+    let xml = "<list xmlns:x='urn:x'>\n  <item id='1' x:kind='a'  type = 'old' lang='en' >one</item>\n  <item id='2' class='b' />\n</list>";
+    let options = ParseOptions {
+        lossless: true,
+        ..ParseOptions::default()
+    };
+    let mut document = Document::new_from_xml_string_with_options(xml, &options).unwrap();
+
+    {
+        let mut item = document.select_mut("item").unwrap();
+        item.set_attr("type", "new");
+        item.set_attr("x:kind", "b");
+        item.remove_attr("lang");
+    }
+
+    {
+        let mut item = document.select_mut("item[id='2']").unwrap();
+        item.set_tag_name("entry");
+        item.append_child(Node::Text("two"));
+    }
+
+    assert_eq!(document.to_string(), "<list xmlns:x='urn:x'>\n  <item id='1' x:kind=\"b\"  type=\"new\" >one</item>\n  <entry id='2' class='b' >two</entry>\n</list>");
+    assert_eq!(Document::new_from_xml_string(&document.to_string()).unwrap().select("item").unwrap().attr("type").unwrap(), "new");
+}
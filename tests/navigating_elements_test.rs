@@ -0,0 +1,92 @@
+use rquery::{ Document, Element, SelectError };
+
+fn new_document() -> Document {
+    Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<sample type="simple">
+  <title>Simple Sample</title>
+  <related>
+    <item id="id-1">
+      <title>Another Sample</title>
+      <ref>http://path.to.somewhere</ref>
+    </item>
+    <item id="id-2">
+      <title>Other Sample</title>
+      <ref>http://some.other.path</ref>
+      <note/>
+    </item>
+  </related>
+</sample>
+"#).unwrap()
+}
+
+fn tag_names<'a, I: Iterator<Item=Element<'a>>>(iterator: I) -> Vec<&'a str> {
+    iterator.map(|element| element.tag_name()).collect()
+}
+
+#[test]
+fn it_knows_its_parent() {
+    let document = new_document();
+
+    let title = document.select("item title").unwrap();
+    let item = title.parent().unwrap();
+    assert_eq!(item.attr("id").unwrap(), "id-1");
+
+    let sample = document.select("sample").unwrap();
+    assert_eq!(sample.parent(), None);
+}
+
+#[test]
+fn it_can_iterate_over_its_ancestors() {
+    let document = new_document();
+
+    let title = document.select("item title").unwrap();
+    assert_eq!(tag_names(title.ancestors()), vec!("item", "related", "sample"));
+
+    let sample = document.select("sample").unwrap();
+    assert_eq!(sample.ancestors().count(), 0);
+}
+
+#[test]
+fn it_can_find_the_closest_matching_element() {
+    let document = new_document();
+
+    let title = document.select("#id-2 > title").unwrap();
+
+    assert_eq!(title.closest("item").unwrap().attr("id").unwrap(), "id-2");
+    assert_eq!(title.closest("title").unwrap(), title);
+    assert_eq!(title.closest("related > item, sample").unwrap().tag_name(), "item");
+    assert_eq!(title.closest("[type=simple]").unwrap().tag_name(), "sample");
+    assert_eq!(title.closest("ref"), Err(SelectError::NoMatchError));
+    assert!(title.closest("?").is_err());
+}
+
+#[test]
+fn it_knows_its_adjacent_siblings() {
+    let document = new_document();
+
+    let title = document.select("#id-2 > title").unwrap();
+    assert_eq!(title.previous_sibling(), None);
+    assert_eq!(title.next_sibling().unwrap().tag_name(), "ref");
+
+    let note = document.select("note").unwrap();
+    assert_eq!(note.next_sibling(), None);
+    assert_eq!(note.previous_sibling().unwrap().tag_name(), "ref");
+
+    let sample = document.select("sample").unwrap();
+    assert_eq!(sample.next_sibling(), None);
+    assert_eq!(sample.previous_sibling(), None);
+}
+
+#[test]
+fn it_can_iterate_over_its_siblings() {
+    let document = new_document();
+
+    let reference = document.select("#id-2 > ref").unwrap();
+    assert_eq!(tag_names(reference.preceding_siblings()), vec!("title"));
+    assert_eq!(tag_names(reference.following_siblings()), vec!("note"));
+
+    let note = document.select("note").unwrap();
+    assert_eq!(tag_names(note.preceding_siblings()), vec!("ref", "title"));
+    assert_eq!(note.following_siblings().count(), 0);
+}
@@ -0,0 +1,75 @@
+use rquery::{ Document, Node };
+
+fn new_document() -> Document {
+    Document::new_from_xml_string(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- A leading comment -->
+<?xml-stylesheet href="style.css"?>
+<body><p>a<b>x</b>c<!-- note --><![CDATA[<d>]]>e &amp; f</p><empty><!-- only a comment --></empty></body>
+<!-- A trailing comment -->
+"#).unwrap()
+}
+
+#[test]
+fn it_preserves_the_order_of_mixed_content() {
+    let document = new_document();
+
+    let p = document.select("p").unwrap();
+    let b = document.select("b").unwrap();
+
+    let nodes: Vec<Node> = p.child_nodes().collect();
+    assert_eq!(nodes, vec!(
+        Node::Text("a"),
+        Node::Element(b),
+        Node::Text("c"),
+        Node::Comment(" note "),
+        Node::CData("<d>"),
+        Node::Text("e & f"),
+    ));
+}
+
+#[test]
+fn it_keeps_the_text_behaviour_for_mixed_content() {
+    let document = new_document();
+
+    let p = document.select("p").unwrap();
+    assert_eq!(p.text(), "ace & f");
+}
+
+#[test]
+fn it_preserves_nodes_outside_the_top_level_element() {
+    let document = new_document();
+
+    let body = document.select("body").unwrap();
+
+    let nodes: Vec<Node> = document.child_nodes().collect();
+    assert_eq!(nodes, vec!(
+        Node::Comment(" A leading comment "),
+        Node::ProcessingInstruction("xml-stylesheet", Some("href=\"style.css\"")),
+        Node::Element(body),
+        Node::Comment(" A trailing comment "),
+    ));
+}
+
+#[test]
+fn it_can_convert_nodes_to_elements() {
+    let document = new_document();
+
+    let elements: Vec<&str> = document.select("body").unwrap().child_nodes()
+        .filter_map(|node| node.as_element())
+        .map(|element| element.tag_name())
+        .collect();
+
+    assert_eq!(elements, vec!("p", "empty"));
+    assert_eq!(Node::Text("a").as_element(), None);
+}
+
+#[test]
+fn it_treats_elements_with_only_comments_as_empty() {
+    let document = new_document();
+
+    let empty_elements: Vec<&str> = document.select_all(":empty").unwrap()
+        .map(|element| element.tag_name())
+        .collect();
+
+    assert_eq!(empty_elements, vec!("empty"));
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+use std::thread;
+
+use rquery::{ Document, Element, ParseOptions, SelectorList };
+
+const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sample type="simple">
+  <title id="main">Simple Sample</title>
+  <related>
+    <item id="id-1">
+      <title>Another Sample</title>
+      <ref>http://path.to.somewhere</ref>
+    </item>
+    <item id="id-2">
+      <title>Other Sample</title>
+      <ref>http://some.other.path</ref>
+    </item>
+  </related>
+</sample>
+"#;
+
+fn assert_send_and_sync<T: Send + Sync>() {}
+
+#[test]
+fn it_shares_documents_between_threads() {
+    assert_send_and_sync::<Document>();
+    assert_send_and_sync::<Element<'static>>();
+    assert_send_and_sync::<SelectorList>();
+
+    let options = ParseOptions {
+        index: true,
+        ..ParseOptions::default()
+    };
+    let document = Arc::new(Document::new_from_xml_string_with_options(XML, &options).unwrap());
+    let selector_list = Arc::new(SelectorList::parse("item > title").unwrap());
+
+    let threads: Vec<_> = (0..4)
+        .map(|index| {
+            let document = document.clone();
+            let selector_list = selector_list.clone();
+
+            thread::spawn(move || {
+                let id = format!("id-{}", index % 2 + 1);
+                let item = document.get_element_by_id(&id).unwrap();
+
+                (item.select("title").unwrap().text().into_owned(), document.select_all_compiled(&selector_list).count())
+            })
+        })
+        .collect();
+
+    let results: Vec<(String, usize)> = threads.into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect();
+
+    assert_eq!(results, vec!(
+        ("Another Sample".to_string(), 2),
+        ("Other Sample".to_string(), 2),
+        ("Another Sample".to_string(), 2),
+        ("Other Sample".to_string(), 2),
+    ));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn it_selects_in_parallel_in_document_order() {
+    use super::malformed_input_test::Random;
+    use super::selector_matching_test::{ random_selector, random_tree };
+
+    let node_indexes = |elements: Vec<Element>| -> Vec<usize> {
+        elements.iter().map(|element| element.node_index()).collect()
+    };
+
+    let mut random = Random(0xD0C0_0DE2);
+
+    for iteration in 0..100 {
+        let mut xml = String::new();
+        random_tree(&mut random, &mut xml, 6);
+
+        let options = ParseOptions {
+            index: iteration % 2 == 0,
+            ..ParseOptions::default()
+        };
+        let document = Document::new_from_xml_string_with_options(&xml, &options).unwrap();
+        let elements: Vec<Element> = document.select_all("*").unwrap().collect();
+
+        for _ in 0..10 {
+            let selector = random_selector(&mut random, false);
+            let expected: Vec<Element> = document.select_all(&selector).unwrap().collect();
+
+            assert_eq!(node_indexes(document.par_select_all(&selector).unwrap()), node_indexes(expected), "{} in {}", selector, xml);
+
+            let anchor = elements[random.next(elements.len())];
+            let selector = random_selector(&mut random, true);
+            let expected: Vec<Element> = anchor.select_all(&selector).unwrap().collect();
+
+            assert_eq!(node_indexes(anchor.par_select_all(&selector).unwrap()), node_indexes(expected), "{} from {} in {}", selector, anchor.node_index(), xml);
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn it_rejects_invalid_selectors_when_selecting_in_parallel() {
+    let document = Document::new_from_xml_string(XML).unwrap();
+
+    assert!(document.par_select_all("item >").is_err());
+    assert_eq!(document.par_select_all("item > title, title#main").unwrap().len(), 3);
+}
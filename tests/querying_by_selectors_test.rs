@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::error::Error;
+
 use rquery::{ Document, Element, SelectError, UnexpectedTokenError };
 
 pub fn new_document() -> Document {
@@ -44,7 +47,7 @@ pub fn new_document() -> Document {
 fn it_supports_the_tag_selector() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all("note").unwrap().collect();
+    let elements: Vec<Element> = document.select_all("note").unwrap().collect();
 
     assert_eq!(elements.len(), 1);
     assert_eq!(elements[0].tag_name(), "note");
@@ -54,7 +57,7 @@ fn it_supports_the_tag_selector() {
 fn it_supports_the_nested_tag_selector() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all("related title").unwrap().collect();
+    let elements: Vec<Element> = document.select_all("related title").unwrap().collect();
 
     assert_eq!(elements.len(), 2);
 
@@ -68,7 +71,7 @@ fn it_supports_the_nested_tag_selector() {
 fn it_supports_nesting_selectors() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all("related").unwrap()
+    let elements: Vec<Element> = document.select_all("related").unwrap()
         .flat_map(|element| element.select_all("title").unwrap())
         .collect();
 
@@ -84,7 +87,7 @@ fn it_supports_nesting_selectors() {
 fn it_supports_the_direct_child_tag_selector() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all("sample > title").unwrap().collect();
+    let elements: Vec<Element> = document.select_all("sample > title").unwrap().collect();
 
     assert_eq!(elements.len(), 1);
 
@@ -112,17 +115,30 @@ fn it_returns_a_parse_error_when_the_selector_is_invalid() {
     let result = document.select_all("?");
 
     if let Err(err) = result {
-        assert_eq!(err, SelectError::ParseError(UnexpectedTokenError('?')));
+        assert_eq!(err, SelectError::ParseError(UnexpectedTokenError { offset: 0, found: Some('?'), expected: "a selector" }));
     } else {
         panic!("The invalid selector did not result in an error!");
     }
 }
 
+#[test]
+fn it_describes_select_errors() {
+    let document = new_document();
+
+    let parse_error = document.select("item[").unwrap_err();
+    assert_eq!(parse_error.to_string(), "invalid selector: expected a name but found the end of the selector at offset 5");
+    assert!(parse_error.source().is_some());
+
+    let no_match_error = document.select("nonexistentelement").unwrap_err();
+    assert_eq!(no_match_error.to_string(), "no element matches the selector");
+    assert!(no_match_error.source().is_none());
+}
+
 #[test]
 fn it_supports_the_attribute_selector() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all(r#"[long="false"]"#).unwrap().collect();
+    let elements: Vec<Element> = document.select_all(r#"[long="false"]"#).unwrap().collect();
 
     assert_eq!(elements.len(), 1);
 
@@ -134,7 +150,7 @@ fn it_supports_the_attribute_selector() {
 fn it_supports_the_id_selector() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all("#id-1").unwrap().collect();
+    let elements: Vec<Element> = document.select_all("#id-1").unwrap().collect();
 
     assert_eq!(elements.len(), 1);
 
@@ -147,7 +163,7 @@ fn it_supports_the_id_selector() {
 fn it_supports_the_compound_selectors() {
     let document = new_document();
 
-    let elements: Vec<&Element> = document.select_all("div[type=three]").unwrap().collect();
+    let elements: Vec<Element> = document.select_all("div[type=three]").unwrap().collect();
 
     assert_eq!(elements.len(), 1);
 
@@ -169,3 +185,303 @@ fn it_does_not_repeat_elements() {
     let nested_count = document.select_all("div div").unwrap().count();
     assert_eq!(nested_count, 6);
 }
+
+#[test]
+fn it_supports_selector_lists_in_document_order() {
+    let document = new_document();
+
+    let texts: Vec<String> = document.select_all("ref, item > title").unwrap()
+        .map(|element| element.text().into_owned())
+        .collect();
+
+    assert_eq!(texts, vec!(
+        "Another Sample",
+        "http://path.to.somewhere",
+        "Other Sample",
+        "http://some.other.path",
+    ));
+}
+
+#[test]
+fn it_does_not_repeat_elements_matched_by_several_selectors_in_a_list() {
+    let document = new_document();
+
+    let ids: Vec<&String> = document.select_all("#id-2, item, [id=id-1]").unwrap()
+        .map(|element| element.attr("id").unwrap())
+        .collect();
+
+    assert_eq!(ids, vec!("id-1", "id-2"));
+}
+
+#[test]
+fn it_supports_the_adjacent_sibling_selector() {
+    let document = new_document();
+
+    let texts: Vec<String> = document.select_all("title + ref").unwrap()
+        .map(|element| element.text().into_owned())
+        .collect();
+    assert_eq!(texts, vec!("http://path.to.somewhere", "http://some.other.path"));
+
+    let count = document.select_all("title + related").unwrap().count();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn it_supports_the_general_sibling_selector() {
+    let document = new_document();
+
+    let types: Vec<Option<&String>> = document.select_all("note ~ div").unwrap()
+        .map(|element| element.attr("type"))
+        .collect();
+    assert_eq!(types, vec!(None, Some(&"one".to_string())));
+
+    let count = document.select_all("ref ~ title").unwrap().count();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn it_returns_sibling_matches_in_document_order() {
+    let document = new_document();
+
+    let types: Vec<Option<&String>> = document.select_all("div ~ div").unwrap()
+        .map(|element| element.attr("type"))
+        .collect();
+
+    assert_eq!(types, vec!(Some(&"one".to_string()), None, None));
+}
+
+fn select_tag_names(document: &Document, selector: &str) -> Vec<String> {
+    document.select_all(selector).unwrap()
+        .map(|element| element.tag_name().to_string())
+        .collect()
+}
+
+#[test]
+fn it_supports_the_attribute_presence_selector() {
+    let document = new_document();
+
+    assert_eq!(select_tag_names(&document, "[long]"), vec!("note"));
+    assert_eq!(select_tag_names(&document, "related [id]"), vec!("item", "item"));
+}
+
+#[test]
+fn it_supports_the_attribute_substring_selectors() {
+    let document = new_document();
+
+    assert_eq!(select_tag_names(&document, "[id^=id]").len(), 2);
+    assert_eq!(select_tag_names(&document, "[id$='-2']").len(), 1);
+    assert_eq!(select_tag_names(&document, "[type*=hre]"), vec!("other", "div"));
+    assert_eq!(select_tag_names(&document, "[type^='']").len(), 0);
+}
+
+#[test]
+fn it_supports_the_attribute_word_and_dash_selectors() {
+    let document = Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<list>
+  <entry class="first warning" lang="en-GB"/>
+  <entry class="warnings" lang="en"/>
+  <entry class="other" lang="english"/>
+</list>
+"#).unwrap();
+
+    assert_eq!(document.select_all("[class~=warning]").unwrap().count(), 1);
+    assert_eq!(document.select_all("[class~='first warning']").unwrap().count(), 0);
+    assert_eq!(document.select_all("[lang|=en]").unwrap().count(), 2);
+}
+
+#[test]
+fn it_supports_case_insensitive_attribute_selectors() {
+    let document = new_document();
+
+    assert_eq!(document.select_all("[long=FALSE]").unwrap().count(), 0);
+    assert_eq!(document.select_all("[long=FALSE i]").unwrap().count(), 1);
+    assert_eq!(document.select_all("[id^=ID- i]").unwrap().count(), 2);
+}
+
+#[test]
+fn it_supports_the_first_and_last_child_selectors() {
+    let document = new_document();
+
+    assert_eq!(select_tag_names(&document, "related > :first-child"), vec!("item"));
+    assert_eq!(select_tag_names(&document, "item > :last-child"), vec!("ref", "ref"));
+    assert_eq!(select_tag_names(&document, "sample > :last-child"), vec!("div"));
+    assert_eq!(select_tag_names(&document, "other > :only-child"), vec!("div"));
+    assert_eq!(select_tag_names(&document, ":root"), vec!("sample"));
+}
+
+#[test]
+fn it_supports_the_nth_child_selectors() {
+    let document = new_document();
+
+    let ids: Vec<&String> = document.select_all("item:nth-child(2)").unwrap()
+        .map(|element| element.attr("id").unwrap())
+        .collect();
+    assert_eq!(ids, vec!("id-2"));
+
+    assert_eq!(select_tag_names(&document, "sample > :nth-child(odd)"), vec!("title", "related", "div"));
+    assert_eq!(select_tag_names(&document, "sample > :nth-last-child(-n+2)"), vec!("div", "div"));
+}
+
+#[test]
+fn it_supports_the_of_type_selectors() {
+    let document = new_document();
+
+    let types: Vec<Option<&String>> = document.select_all("sample > div:nth-of-type(2)").unwrap()
+        .map(|element| element.attr("type"))
+        .collect();
+    assert_eq!(types, vec!(Some(&"one".to_string())));
+
+    assert_eq!(select_tag_names(&document, "sample > :nth-last-of-type(1)"), vec!("title", "note", "related", "div"));
+    assert_eq!(select_tag_names(&document, "sample > :only-of-type"), vec!("title", "note", "related"));
+    assert_eq!(document.select_all("div:first-of-type").unwrap().count(), 5);
+}
+
+#[test]
+fn it_supports_the_empty_selector() {
+    let document = new_document();
+
+    assert_eq!(document.select_all("div:empty").unwrap().count(), 5);
+    assert_eq!(document.select_all("item:empty").unwrap().count(), 0);
+}
+
+#[test]
+fn it_supports_the_not_selector() {
+    let document = new_document();
+
+    let ids: Vec<&String> = document.select_all("item:not([id=id-2])").unwrap()
+        .map(|element| element.attr("id").unwrap())
+        .collect();
+    assert_eq!(ids, vec!("id-1"));
+
+    assert_eq!(select_tag_names(&document, "sample > :not(div, related)"), vec!("title", "note"));
+    assert_eq!(document.select_all("title:not(item > title)").unwrap().count(), 1);
+}
+
+#[test]
+fn it_supports_the_is_and_where_selectors() {
+    let document = new_document();
+
+    assert_eq!(select_tag_names(&document, "item > :is(title, ref)"), vec!("title", "ref", "title", "ref"));
+    assert_eq!(select_tag_names(&document, ":where(note, related) ~ div:empty"), vec!("div"));
+    assert_eq!(document.select_all("div:is(other div, div > div > div)").unwrap().count(), 5);
+}
+
+#[test]
+fn it_supports_the_has_selector() {
+    let document = new_document();
+
+    assert_eq!(select_tag_names(&document, ":has(> ref)"), vec!("item", "item"));
+    assert_eq!(select_tag_names(&document, "sample > :has(ref)"), vec!("related"));
+    assert_eq!(select_tag_names(&document, "title:has(+ ref)"), vec!("title", "title"));
+    assert_eq!(select_tag_names(&document, "item:has(~ item)"), vec!("item"));
+    assert_eq!(document.select_all("div:has(div[type=two], > div > div)").unwrap().count(), 2);
+}
+
+#[test]
+fn it_supports_the_class_selector() {
+    let document = Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<list>
+  <entry class="first warning"/>
+  <entry class="warnings"/>
+  <note class=" warning "/>
+</list>
+"#).unwrap();
+
+    assert_eq!(select_tag_names(&document, ".warning"), vec!("entry", "note"));
+    assert_eq!(select_tag_names(&document, "entry.warning.first"), vec!("entry"));
+    assert_eq!(document.select_all(".warn").unwrap().count(), 0);
+}
+
+#[test]
+fn it_supports_the_universal_selector() {
+    let document = new_document();
+
+    assert_eq!(select_tag_names(&document, "related > * > title"), vec!("title", "title"));
+    assert_eq!(document.select_all("*").unwrap().count(), document.number_of_elements());
+    assert_eq!(select_tag_names(&document, "other > *"), vec!("div"));
+}
+
+#[test]
+fn it_supports_namespace_qualified_universal_selectors() {
+    let document = Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns:atom="http://www.w3.org/2005/Atom">
+  <atom:link/>
+  <link/>
+</feed>
+"#).unwrap();
+
+    assert_eq!(select_tag_names(&document, "feed > atom|*"), vec!("link"));
+    assert_eq!(select_tag_names(&document, "feed > |*"), vec!("link"));
+    assert_eq!(select_tag_names(&document, "feed > *|*"), vec!("link", "link"));
+}
+
+fn new_namespaced_document() -> Document {
+    Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns:atom="http://www.w3.org/2005/Atom" xmlns:a="http://www.w3.org/2005/Atom" xmlns:xlink="http://www.w3.org/1999/xlink">
+  <atom:link href="http://path.to.somewhere"/>
+  <a:link xlink:href="http://some.other.path"/>
+  <link href="http://local.path" xlink:href="http://linked.path"/>
+</feed>
+"#).unwrap()
+}
+
+#[test]
+fn it_supports_namespace_qualified_tag_selectors() {
+    let document = new_namespaced_document();
+
+    assert_eq!(document.select_all("atom|link").unwrap().count(), 1);
+    assert_eq!(document.select_all("*|link").unwrap().count(), 3);
+    assert_eq!(document.select_all("|link").unwrap().count(), 1);
+    assert_eq!(document.select_all("link").unwrap().count(), 3);
+}
+
+#[test]
+fn it_supports_namespace_qualified_attribute_selectors() {
+    let document = new_namespaced_document();
+
+    let hrefs: Vec<&String> = document.select_all("[xlink|href]").unwrap()
+        .map(|element| element.attr("xlink:href").unwrap())
+        .collect();
+    assert_eq!(hrefs, vec!("http://some.other.path", "http://linked.path"));
+
+    assert_eq!(document.select_all("[href]").unwrap().count(), 2);
+    assert_eq!(document.select_all("[*|href]").unwrap().count(), 3);
+    assert_eq!(document.select_all("[xlink|href$='linked.path']").unwrap().count(), 1);
+}
+
+#[test]
+fn it_supports_registering_namespace_prefixes_on_the_query() {
+    let document = new_namespaced_document();
+
+    let mut namespaces = HashMap::new();
+    namespaces.insert("feed".to_string(), "http://www.w3.org/2005/Atom".to_string());
+    namespaces.insert("l".to_string(), "http://www.w3.org/1999/xlink".to_string());
+
+    assert_eq!(document.select_all_with_namespaces("feed|link", &namespaces).unwrap().count(), 2);
+    assert_eq!(document.select_all_with_namespaces("[l|href]", &namespaces).unwrap().count(), 2);
+    assert_eq!(document.select_all_with_namespaces("atom|link", &namespaces).unwrap().count(), 1);
+
+    let element = document.select_with_namespaces("feed|link:not([href])", &namespaces).unwrap();
+    assert_eq!(element.prefix(), Some("a"));
+}
+
+#[test]
+fn it_supports_attribute_values_containing_spaces() {
+    let document = Document::new_from_xml_string(r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<item>
+  <title alt="Other Sample">Other Sample</title>
+  <title alt="Other">Other</title>
+</item>
+"#).unwrap();
+
+    let title = document.select(r#"item > [alt="Other Sample"]"#).unwrap();
+    assert_eq!(title.text(), "Other Sample");
+
+    let title = document.select(r"[alt='Other\20 Sample' i]").unwrap();
+    assert_eq!(title.text(), "Other Sample");
+}
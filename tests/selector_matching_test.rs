@@ -0,0 +1,163 @@
+use std::time::{ Duration, Instant };
+
+use rquery::{ CompoundSelector, Document, Element, Scope, SelectorList };
+
+use super::malformed_input_test::Random;
+
+const TAGS: &[&str] = &["a", "b", "c"];
+
+pub fn random_tree(random: &mut Random, xml: &mut String, depth: usize) {
+    let tag = TAGS[random.next(TAGS.len())];
+    xml.push_str(&format!("<{}>", tag));
+
+    if depth > 0 {
+        for _ in 0..random.next(4) {
+            random_tree(random, xml, depth - 1);
+        }
+    }
+
+    xml.push_str(&format!("</{}>", tag));
+}
+
+pub fn random_selector(random: &mut Random, relative: bool) -> String {
+    let mut selector = String::new();
+
+    if relative && random.next(2) == 0 {
+        selector.push_str(["> ", "+ ", "~ "][random.next(3)]);
+    }
+
+    for index in 0..random.next(4) + 1 {
+        if index > 0 {
+            selector.push_str([" ", " > ", " + ", " ~ "][random.next(4)]);
+        }
+
+        selector.push_str(["a", "b", "c", "*"][random.next(4)]);
+        selector.push_str(["", "", "", ":first-child", ":not(b)", ":has(> c)"][random.next(6)]);
+    }
+
+    selector
+}
+
+fn document_order<'a>(element: Element<'a>, elements: &mut Vec<Element<'a>>) {
+    for child in element.children_iter() {
+        elements.push(child);
+        document_order(child, elements);
+    }
+}
+
+/// Whether the element is related to the anchor as required by the scope,
+/// where `None` stands for the document itself.
+fn in_scope(element: Element, anchor: Option<Element>, scope: Scope) -> bool {
+    match (scope, anchor) {
+        (Scope::IndirectChild, None) => true,
+        (Scope::DirectChild, None) => element.parent().is_none(),
+        (_, None) => false,
+        (Scope::IndirectChild, Some(anchor)) => element.ancestors().any(|ancestor| ancestor == anchor),
+        (Scope::DirectChild, Some(anchor)) => element.parent() == Some(anchor),
+        (Scope::AdjacentSibling, Some(anchor)) => element.previous_sibling() == Some(anchor),
+        (Scope::GeneralSibling, Some(anchor)) => element.preceding_siblings().any(|sibling| sibling == anchor),
+    }
+}
+
+/// Matches by trying every combination of elements, as a reference for the
+/// selector engine.
+fn naive_matches(element: Element, compound_selectors: &[CompoundSelector], anchor: Option<Element>) -> bool {
+    let (compound_selector, preceding_selectors) = compound_selectors.split_last().unwrap();
+
+    if !element.matches_compound(compound_selector) {
+        return false;
+    }
+
+    if preceding_selectors.is_empty() {
+        return in_scope(element, anchor, compound_selector.scope);
+    }
+
+    let candidates: Vec<Element> = match compound_selector.scope {
+        Scope::IndirectChild => element.ancestors().collect(),
+        Scope::DirectChild => element.parent().into_iter().collect(),
+        Scope::AdjacentSibling => element.previous_sibling().into_iter().collect(),
+        Scope::GeneralSibling => element.preceding_siblings().collect(),
+    };
+
+    candidates.into_iter().any(|candidate| naive_matches(candidate, preceding_selectors, anchor))
+}
+
+fn node_indexes<'a, I: Iterator<Item=Element<'a>>>(iterator: I) -> Vec<usize> {
+    iterator.map(|element| element.node_index()).collect()
+}
+
+#[test]
+fn it_agrees_with_a_naive_matcher_on_random_trees() {
+    let mut random = Random(0x5EED_CAFE);
+
+    for _ in 0..200 {
+        let mut xml = String::new();
+        random_tree(&mut random, &mut xml, 5);
+
+        let document = Document::new_from_xml_string(&xml).unwrap();
+        let root = document.select("*").unwrap();
+        let mut elements = vec!(root);
+        document_order(root, &mut elements);
+
+        for _ in 0..10 {
+            let selector = random_selector(&mut random, false);
+            let selector_list = SelectorList::parse(&selector).unwrap();
+
+            let expected = elements.iter().cloned()
+                .filter(|&element| selector_list.selectors().iter().any(|compound_selectors| naive_matches(element, compound_selectors, None)));
+
+            assert_eq!(node_indexes(document.select_all(&selector).unwrap()), node_indexes(expected), "{} in {}", selector, xml);
+
+            for &element in &elements {
+                assert_eq!(
+                    element.matches(&selector_list),
+                    selector_list.selectors().iter().any(|compound_selectors| naive_matches(element, compound_selectors, None)),
+                    "{} on element {} in {}", selector, element.node_index(), xml,
+                );
+            }
+
+            let anchor = elements[random.next(elements.len())];
+            let selector = random_selector(&mut random, true);
+            let selector_list = SelectorList::parse(&selector).unwrap();
+
+            let expected = elements.iter().cloned()
+                .filter(|&element| selector_list.selectors().iter().any(|compound_selectors| naive_matches(element, compound_selectors, Some(anchor))));
+
+            assert_eq!(node_indexes(anchor.select_all(&selector).unwrap()), node_indexes(expected), "{} from {} in {}", selector, anchor.node_index(), xml);
+        }
+    }
+}
+
+#[test]
+fn it_returns_matches_in_document_order_without_duplicates() {
+    let document = Document::new_from_xml_string(r#"
+<r>
+  <a><b><a><b><c id="1"/></b></a></b><c id="2"/></a>
+  <b/><a><c id="3"/></a><c id="4"/>
+</r>
+"#).unwrap();
+
+    let ids = |selector: &str| -> Vec<String> {
+        document.select_all(selector).unwrap().map(|element| element.attr("id").unwrap().clone()).collect()
+    };
+
+    assert_eq!(ids("a c"), vec!("1", "2", "3"));
+    assert_eq!(ids("a b c, a > c"), vec!("1", "2", "3"));
+    assert_eq!(ids("b ~ *:not(b) c, b ~ c"), vec!("2", "3", "4"));
+    assert_eq!(ids("a c, r > c, [id='1']"), vec!("1", "2", "3", "4"));
+}
+
+#[test]
+fn it_matches_descendant_combinators_on_deep_trees_in_linear_time() {
+    let depth = 2000;
+    let xml = format!("{}{}", "<div>".repeat(depth), "</div>".repeat(depth));
+    let document = Document::new_from_xml_string(&xml).unwrap();
+
+    let start = Instant::now();
+
+    assert_eq!(document.select_all("div div div").unwrap().count(), depth - 2);
+    assert_eq!(document.select_all("div div span").unwrap().count(), 0);
+    assert_eq!(document.select_all("span div div").unwrap().count(), 0);
+
+    assert!(start.elapsed() < Duration::from_secs(10), "took {:?}", start.elapsed());
+}
@@ -1,12 +1,14 @@
-use rquery::{ CompoundSelector, MatchType, Scope, Selector };
+use std::collections::{ HashMap, HashSet };
+
+use rquery::{ CaseSensitivity, CompoundSelector, MatchType, NamespacePrefix, Nth, PseudoClass, Scope, Selector, UnexpectedTokenError };
 
 fn assert_as_single_tag(compound_selector: &CompoundSelector, tag_name: &str) {
     assert_eq!(compound_selector.parts.len(), 1);
 
-    if let &Selector::TagName(ref string) = compound_selector.parts.last().unwrap() {
+    if let Selector::TagName(ref string) = *compound_selector.parts.last().unwrap() {
         assert_eq!(string, tag_name)
     } else {
-        panic!(format!("Did not match tag name \"{}\"", tag_name));
+        panic!("Did not match tag name \"{}\"", tag_name);
     }
 }
 
@@ -53,10 +55,12 @@ fn it_can_parse_the_attribute_selector() {
     assert_eq!(compound_selectors.len(), 1);
     assert_eq!(compound_selectors[0].parts.len(), 1);
 
-    if let Selector::Attribute(ref attribute, match_type, ref value) = compound_selectors[0].parts[0] {
+    if let Selector::Attribute(ref namespace_prefix, ref attribute, match_type, ref value, case_sensitivity) = compound_selectors[0].parts[0] {
+        assert_eq!(*namespace_prefix, None);
         assert_eq!(attribute, "attribute");
         assert_eq!(match_type, MatchType::Equals);
         assert_eq!(value, "true-value");
+        assert_eq!(case_sensitivity, CaseSensitivity::CaseSensitive);
     } else {
         panic!("Could not parse the attribute selector");
     }
@@ -75,3 +79,474 @@ fn it_can_parse_the_id_selector() {
         panic!("Could not parse the ID selector");
     }
 }
+
+#[test]
+fn it_can_parse_a_selector_list() {
+    let selector_list = CompoundSelector::parse_list("basket > apple, pear").unwrap();
+
+    assert_eq!(selector_list.len(), 2);
+
+    assert_eq!(selector_list[0].len(), 2);
+    assert_as_single_tag(&selector_list[0][0], "basket");
+    assert_as_single_tag(&selector_list[0][1], "apple");
+
+    assert_eq!(selector_list[1].len(), 1);
+    assert_eq!(selector_list[1][0].scope, Scope::IndirectChild);
+    assert_as_single_tag(&selector_list[1][0], "pear");
+}
+
+#[test]
+fn it_rejects_empty_alternatives_in_a_selector_list() {
+    assert!(CompoundSelector::parse_list("apple,").is_err());
+    assert!(CompoundSelector::parse_list(", apple").is_err());
+}
+
+#[test]
+fn it_can_parse_sibling_selectors() {
+    let compound_selectors = CompoundSelector::parse("title + ref ~ note").unwrap();
+
+    assert_eq!(compound_selectors.len(), 3);
+
+    assert_eq!(compound_selectors[0].scope, Scope::IndirectChild);
+    assert_as_single_tag(&compound_selectors[0], "title");
+
+    assert_eq!(compound_selectors[1].scope, Scope::AdjacentSibling);
+    assert_as_single_tag(&compound_selectors[1], "ref");
+
+    assert_eq!(compound_selectors[2].scope, Scope::GeneralSibling);
+    assert_as_single_tag(&compound_selectors[2], "note");
+}
+
+#[test]
+fn it_can_parse_combinators_without_surrounding_whitespace() {
+    let compound_selectors = CompoundSelector::parse("basket>apple~pear+plum").unwrap();
+
+    let scopes: Vec<Scope> = compound_selectors.iter()
+        .map(|compound_selector| compound_selector.scope)
+        .collect();
+
+    assert_eq!(scopes, vec!(Scope::IndirectChild, Scope::DirectChild, Scope::GeneralSibling, Scope::AdjacentSibling));
+}
+
+#[test]
+fn it_rejects_dangling_combinators() {
+    assert!(CompoundSelector::parse("basket >").is_err());
+    assert!(CompoundSelector::parse("basket > + apple").is_err());
+}
+
+fn parse_attribute_selector(selector: &str) -> (String, MatchType, String, CaseSensitivity) {
+    let compound_selectors = CompoundSelector::parse(selector).unwrap();
+
+    assert_eq!(compound_selectors.len(), 1);
+    assert_eq!(compound_selectors[0].parts.len(), 1);
+
+    if let Selector::Attribute(_, ref attribute, match_type, ref value, case_sensitivity) = compound_selectors[0].parts[0] {
+        (attribute.clone(), match_type, value.clone(), case_sensitivity)
+    } else {
+        panic!("Could not parse the attribute selector \"{}\"", selector);
+    }
+}
+
+#[test]
+fn it_can_parse_all_attribute_match_types() {
+    assert_eq!(parse_attribute_selector("[href]").1, MatchType::Exists);
+    assert_eq!(parse_attribute_selector("[class~=warning]").1, MatchType::Includes);
+    assert_eq!(parse_attribute_selector("[lang|=en]").1, MatchType::DashMatch);
+    assert_eq!(parse_attribute_selector("[href^=http]").1, MatchType::Prefix);
+    assert_eq!(parse_attribute_selector("[href$=xml]").1, MatchType::Suffix);
+    assert_eq!(parse_attribute_selector("[title*=sample]").1, MatchType::Substring);
+}
+
+#[test]
+fn it_can_parse_single_quoted_attribute_values() {
+    let (attribute, match_type, value, _) = parse_attribute_selector("[ href $= '.xml' ]");
+
+    assert_eq!(attribute, "href");
+    assert_eq!(match_type, MatchType::Suffix);
+    assert_eq!(value, ".xml");
+}
+
+#[test]
+fn it_can_parse_attribute_case_sensitivity_flags() {
+    assert_eq!(parse_attribute_selector(r#"[type="Radio" i]"#).3, CaseSensitivity::AsciiCaseInsensitive);
+    assert_eq!(parse_attribute_selector("[type=radio I]").3, CaseSensitivity::AsciiCaseInsensitive);
+    assert_eq!(parse_attribute_selector(r#"[type="radio" s]"#).3, CaseSensitivity::CaseSensitive);
+}
+
+#[test]
+fn it_rejects_malformed_attribute_selectors() {
+    assert!(CompoundSelector::parse("[]").is_err());
+    assert!(CompoundSelector::parse("[type~radio]").is_err());
+    assert!(CompoundSelector::parse("[type=radio").is_err());
+    assert!(CompoundSelector::parse(r#"[type="radio]"#).is_err());
+    assert!(CompoundSelector::parse("[type=radio x]").is_err());
+}
+
+#[test]
+fn it_can_parse_structural_pseudo_classes() {
+    let compound_selectors = CompoundSelector::parse("item:first-child:nth-of-type(2n + 1) > :root").unwrap();
+
+    assert_eq!(compound_selectors.len(), 2);
+    assert_eq!(compound_selectors[0].parts.len(), 3);
+
+    if let Selector::PseudoClass(ref pseudo_class) = compound_selectors[0].parts[1] {
+        assert_eq!(*pseudo_class, PseudoClass::FirstChild);
+    } else {
+        panic!("Could not parse the :first-child pseudo-class");
+    }
+
+    if let Selector::PseudoClass(ref pseudo_class) = compound_selectors[0].parts[2] {
+        assert_eq!(*pseudo_class, PseudoClass::NthOfType(Nth { a: 2, b: 1 }));
+    } else {
+        panic!("Could not parse the :nth-of-type pseudo-class");
+    }
+
+    if let Selector::PseudoClass(ref pseudo_class) = compound_selectors[1].parts[0] {
+        assert_eq!(*pseudo_class, PseudoClass::Root);
+    } else {
+        panic!("Could not parse the :root pseudo-class");
+    }
+}
+
+#[test]
+fn it_can_parse_the_an_plus_b_syntax() {
+    assert_eq!(Nth::parse("odd").unwrap(), Nth { a: 2, b: 1 });
+    assert_eq!(Nth::parse("EVEN").unwrap(), Nth { a: 2, b: 0 });
+    assert_eq!(Nth::parse("7").unwrap(), Nth { a: 0, b: 7 });
+    assert_eq!(Nth::parse("n").unwrap(), Nth { a: 1, b: 0 });
+    assert_eq!(Nth::parse("+n").unwrap(), Nth { a: 1, b: 0 });
+    assert_eq!(Nth::parse("-n+3").unwrap(), Nth { a: -1, b: 3 });
+    assert_eq!(Nth::parse(" 3n - 2 ").unwrap(), Nth { a: 3, b: -2 });
+    assert_eq!(Nth::parse("-2n+ 1").unwrap(), Nth { a: -2, b: 1 });
+
+    assert!(Nth::parse("").is_err());
+    assert!(Nth::parse("n+").is_err());
+    assert!(Nth::parse("2n+-1").is_err());
+    assert!(Nth::parse("2x").is_err());
+    assert!(Nth::parse("--n").is_err());
+}
+
+#[test]
+fn it_matches_positions_with_the_an_plus_b_syntax() {
+    let positions = |nth: Nth| (1..10).filter(|&position| nth.matches(position)).collect::<Vec<usize>>();
+
+    assert_eq!(positions(Nth { a: 2, b: 1 }), vec!(1, 3, 5, 7, 9));
+    assert_eq!(positions(Nth { a: 0, b: 4 }), vec!(4));
+    assert_eq!(positions(Nth { a: -1, b: 3 }), vec!(1, 2, 3));
+    assert_eq!(positions(Nth { a: 3, b: -2 }), vec!(1, 4, 7));
+}
+
+#[test]
+fn it_rejects_unknown_pseudo_classes() {
+    assert!(CompoundSelector::parse("a:hover").is_err());
+    assert!(CompoundSelector::parse("a:").is_err());
+    assert!(CompoundSelector::parse("a:nth-child").is_err());
+    assert!(CompoundSelector::parse("a:nth-child(2").is_err());
+}
+
+#[test]
+fn it_can_parse_nested_selector_lists_in_logical_pseudo_classes() {
+    let compound_selectors = CompoundSelector::parse("item:not([id=id-2], :first-child title)").unwrap();
+
+    assert_eq!(compound_selectors.len(), 1);
+    assert_eq!(compound_selectors[0].parts.len(), 2);
+
+    if let Selector::PseudoClass(PseudoClass::Not(ref selector_list)) = compound_selectors[0].parts[1] {
+        assert_eq!(*selector_list, CompoundSelector::parse_list("[id=id-2], :first-child title").unwrap());
+    } else {
+        panic!("Could not parse the :not pseudo-class");
+    }
+}
+
+#[test]
+fn it_can_parse_relative_selectors_in_the_has_pseudo_class() {
+    let compound_selectors = CompoundSelector::parse("item:has(> ref, + item)").unwrap();
+
+    if let Selector::PseudoClass(PseudoClass::Has(ref selector_list)) = compound_selectors[0].parts[1] {
+        assert_eq!(selector_list.len(), 2);
+        assert_eq!(selector_list[0][0].scope, Scope::DirectChild);
+        assert_as_single_tag(&selector_list[0][0], "ref");
+        assert_eq!(selector_list[1][0].scope, Scope::AdjacentSibling);
+        assert_as_single_tag(&selector_list[1][0], "item");
+    } else {
+        panic!("Could not parse the :has pseudo-class");
+    }
+}
+
+#[test]
+fn it_rejects_malformed_logical_pseudo_classes() {
+    assert!(CompoundSelector::parse("item:not()").is_err());
+    assert!(CompoundSelector::parse("item:not(> ref)").is_err());
+    assert!(CompoundSelector::parse("item:is(ref,)").is_err());
+    assert!(CompoundSelector::parse("item:is(ref").is_err());
+    assert!(CompoundSelector::parse("item)").is_err());
+}
+
+#[test]
+fn it_can_parse_the_class_selector() {
+    let compound_selectors = CompoundSelector::parse("item.warning.first").unwrap();
+
+    assert_eq!(compound_selectors[0].parts, vec!(
+        Selector::TagName("item".to_string()),
+        Selector::Class("warning".to_string()),
+        Selector::Class("first".to_string()),
+    ));
+}
+
+#[test]
+fn it_can_parse_the_universal_selector() {
+    let compound_selectors = CompoundSelector::parse("related > * > title").unwrap();
+
+    assert_eq!(compound_selectors[1].parts, vec!(Selector::Universal));
+
+    let selector_list = CompoundSelector::parse_list("*.warning").unwrap();
+    assert_eq!(selector_list[0][0].parts, vec!(Selector::Universal, Selector::Class("warning".to_string())));
+}
+
+#[test]
+fn it_can_parse_namespace_qualified_universal_selectors() {
+    let parts = |selector: &str| CompoundSelector::parse(selector).unwrap().remove(0).parts;
+
+    assert_eq!(parts("svg|*"), vec!(Selector::Namespace(NamespacePrefix::Named("svg".to_string())), Selector::Universal));
+    assert_eq!(parts("*|*"), vec!(Selector::Namespace(NamespacePrefix::Any), Selector::Universal));
+    assert_eq!(parts("|*"), vec!(Selector::Namespace(NamespacePrefix::NoNamespace), Selector::Universal));
+}
+
+#[test]
+fn it_rejects_malformed_class_and_universal_selectors() {
+    assert!(CompoundSelector::parse(".").is_err());
+    assert!(CompoundSelector::parse("item.").is_err());
+    assert!(CompoundSelector::parse("item*").is_err());
+    assert!(CompoundSelector::parse("svg|").is_err());
+    assert!(CompoundSelector::parse(".warning|*").is_err());
+}
+
+#[test]
+fn it_can_parse_namespace_qualified_tag_selectors() {
+    let parts = |selector: &str| CompoundSelector::parse(selector).unwrap().remove(0).parts;
+
+    assert_eq!(parts("svg|rect"), vec!(Selector::Namespace(NamespacePrefix::Named("svg".to_string())), Selector::TagName("rect".to_string())));
+    assert_eq!(parts("*|title"), vec!(Selector::Namespace(NamespacePrefix::Any), Selector::TagName("title".to_string())));
+    assert_eq!(parts("|title"), vec!(Selector::Namespace(NamespacePrefix::NoNamespace), Selector::TagName("title".to_string())));
+}
+
+#[test]
+fn it_can_parse_namespace_qualified_attribute_selectors() {
+    let parts = |selector: &str| CompoundSelector::parse(selector).unwrap().remove(0).parts;
+
+    assert_eq!(parts("[xlink|href]"), vec!(Selector::Attribute(
+        Some(NamespacePrefix::Named("xlink".to_string())),
+        "href".to_string(),
+        MatchType::Exists,
+        String::new(),
+        CaseSensitivity::CaseSensitive,
+    )));
+
+    assert_eq!(parts("[*|lang|=en]"), vec!(Selector::Attribute(
+        Some(NamespacePrefix::Any),
+        "lang".to_string(),
+        MatchType::DashMatch,
+        "en".to_string(),
+        CaseSensitivity::CaseSensitive,
+    )));
+
+    assert!(CompoundSelector::parse("[*]").is_err());
+    assert!(CompoundSelector::parse("[xlink|]").is_err());
+}
+
+#[test]
+fn it_resolves_registered_namespace_prefixes() {
+    let mut namespaces = HashMap::new();
+    namespaces.insert("svg".to_string(), "http://www.w3.org/2000/svg".to_string());
+
+    let mut compound_selectors = CompoundSelector::parse("svg|rect:not(math|*)").unwrap();
+    compound_selectors[0].resolve_namespaces(&namespaces);
+
+    assert_eq!(compound_selectors[0].parts[0], Selector::Namespace(NamespacePrefix::Resolved(
+        "svg".to_string(),
+        "http://www.w3.org/2000/svg".to_string(),
+    )));
+
+    if let Selector::PseudoClass(PseudoClass::Not(ref selector_list)) = compound_selectors[0].parts[2] {
+        assert_eq!(selector_list[0][0].parts[0], Selector::Namespace(NamespacePrefix::Named("math".to_string())));
+    } else {
+        panic!("Could not parse the :not pseudo-class");
+    }
+}
+
+fn parse_error(selector: &str) -> UnexpectedTokenError {
+    CompoundSelector::parse_list(selector).unwrap_err()
+}
+
+#[test]
+fn it_locates_the_unexpected_token_in_an_invalid_selector() {
+    assert_eq!(parse_error("item > ?"), UnexpectedTokenError { offset: 7, found: Some('?'), expected: "a selector" });
+    assert_eq!(parse_error("[type=radio"), UnexpectedTokenError { offset: 11, found: None, expected: "`]`" });
+    assert_eq!(parse_error("[type~radio]"), UnexpectedTokenError { offset: 6, found: Some('r'), expected: "`=`" });
+    assert_eq!(parse_error(r#"[type="radio]"#), UnexpectedTokenError { offset: 13, found: None, expected: "`\"`" });
+    assert_eq!(parse_error("item:hover"), UnexpectedTokenError { offset: 5, found: Some('h'), expected: "a supported pseudo-class" });
+    assert_eq!(parse_error("item:is(ref"), UnexpectedTokenError { offset: 11, found: None, expected: "`,` or `)`" });
+    assert_eq!(parse_error("item)"), UnexpectedTokenError { offset: 4, found: Some(')'), expected: "`,` or the end of the selector" });
+    assert_eq!(parse_error("apple,"), UnexpectedTokenError { offset: 6, found: None, expected: "a selector" });
+}
+
+#[test]
+fn it_counts_offsets_in_bytes() {
+    assert_eq!(parse_error("[title=\"é\" ?]"), UnexpectedTokenError { offset: 12, found: Some('?'), expected: "`]`" });
+}
+
+#[test]
+fn it_locates_errors_inside_the_an_plus_b_argument() {
+    assert_eq!(parse_error("li:nth-child(2n+-1)"), UnexpectedTokenError { offset: 16, found: Some('-'), expected: "a digit" });
+    assert_eq!(parse_error("li:nth-child( 2x)"), UnexpectedTokenError { offset: 15, found: Some('x'), expected: "a digit" });
+    assert_eq!(Nth::parse("3n * 1").unwrap_err(), UnexpectedTokenError { offset: 3, found: Some('*'), expected: "`+` or `-`" });
+}
+
+#[test]
+fn it_rejects_an_empty_an_plus_b_argument() {
+    assert_eq!(Nth::parse(" ").unwrap_err(), UnexpectedTokenError { offset: 1, found: None, expected: "an integer" });
+    assert_eq!(parse_error("li:nth-child( )"), UnexpectedTokenError { offset: 14, found: Some(')'), expected: "an integer" });
+    assert_eq!(parse_error("li:nth-child()"), UnexpectedTokenError { offset: 13, found: Some(')'), expected: "an integer" });
+}
+
+#[test]
+fn it_describes_the_unexpected_token() {
+    assert_eq!(parse_error("item > ?").to_string(), "expected a selector but found `?` at offset 7");
+    assert_eq!(parse_error("[type=radio").to_string(), "expected `]` but found the end of the selector at offset 11");
+}
+
+fn attribute_value(selector: &str) -> String {
+    match CompoundSelector::parse(selector).unwrap()[0].parts[0] {
+        Selector::Attribute(_, _, _, ref value, _) => value.clone(),
+        ref part => panic!("Expected an attribute selector, found {:?}", part),
+    }
+}
+
+#[test]
+fn it_parses_quoted_attribute_values_with_spaces_and_escapes() {
+    assert_eq!(attribute_value(r#"[title="Other Sample"]"#), "Other Sample");
+    assert_eq!(attribute_value("[title='Other Sample']"), "Other Sample");
+    assert_eq!(attribute_value(r#"[title="it's \"quoted\""]"#), r#"it's "quoted""#);
+    assert_eq!(attribute_value(r#"[title='a\'b']"#), "a'b");
+    assert_eq!(attribute_value(r#"[title="caf\e9 "]"#), "café");
+    assert_eq!(attribute_value("[title=\"line \\\ncontinued\"]"), "line continued");
+    assert_eq!(attribute_value(r#"[title="a, b) > c"]"#), "a, b) > c");
+
+    assert!(CompoundSelector::parse("[title=\"broken\nstring\"]").is_err());
+}
+
+#[test]
+fn it_parses_identifiers_with_unicode_and_escapes() {
+    let compound_selectors = CompoundSelector::parse(r"überschrift.grüße#\31 23.a\.b\:c").unwrap();
+
+    assert_eq!(compound_selectors[0].parts, vec!(
+        Selector::TagName("überschrift".to_string()),
+        Selector::Class("grüße".to_string()),
+        Selector::Id("123".to_string()),
+        Selector::Class("a.b:c".to_string()),
+    ));
+
+    assert_eq!(attribute_value(r"[data-x=\0 ]"), "\u{FFFD}");
+    assert_eq!(attribute_value("[data-x=a\0b]"), "a\u{FFFD}b");
+    assert_eq!(attribute_value("[data-x=a\\\0b]"), "a\u{FFFD}b");
+    assert_eq!(attribute_value("[data-x='a\0b']"), "a\u{FFFD}b");
+    assert_eq!(attribute_value("[lang=-x-klingon]"), "-x-klingon");
+    assert_eq!(attribute_value("[lang=--custom]"), "--custom");
+
+    assert!(CompoundSelector::parse("#123").is_err());
+    assert!(CompoundSelector::parse(".-1").is_err());
+    assert!(CompoundSelector::parse("[id=1]").is_err());
+    assert!(CompoundSelector::parse("[id=]").is_err());
+}
+
+#[test]
+fn it_handles_css_whitespace_around_combinators() {
+    let compound_selectors = CompoundSelector::parse("basket\t>\napple\r\n+\x0Cpear   ~ plum").unwrap();
+
+    let scopes = compound_selectors.iter().map(|compound_selector| compound_selector.scope).collect::<Vec<Scope>>();
+    assert_eq!(scopes, vec!(Scope::IndirectChild, Scope::DirectChild, Scope::AdjacentSibling, Scope::GeneralSibling));
+
+    assert_eq!(CompoundSelector::parse_list(" title ,\tref ").unwrap().len(), 2);
+    assert_eq!(CompoundSelector::parse("basket\u{A0}apple").unwrap().len(), 1);
+}
+
+fn serialize(compound_selectors: &[CompoundSelector]) -> String {
+    compound_selectors.iter().map(|compound_selector| compound_selector.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+#[test]
+fn it_serializes_selectors_canonically() {
+    let cases = vec!(
+        ("item  >title", "item > title"),
+        ("a+b~c d", "a + b ~ c d"),
+        ("[type=radio]", r#"[type="radio"]"#),
+        ("[ lang |= 'en' I ]", r#"[lang|="en" i]"#),
+        ("[type='radio' s]", r#"[type="radio"]"#),
+        ("svg|*:nth-child( 2n + 1 )", "svg|*:nth-child(2n+1)"),
+        ("li:nth-last-of-type(-n+3):nth-of-type(even):nth-child(0n-2)", "li:nth-last-of-type(-n+3):nth-of-type(2n):nth-child(-2)"),
+        ("*|*, |title", "*|*"),
+        ("item:NOT([id=id-2],title)", r#"item:not([id="id-2"], title)"#),
+        ("item:has(>ref,+ note)", "item:has(> ref, + note)"),
+        (r"#\31 23.a\.b", r"#\31 23.a\.b"),
+        ("n\0ul.a\\\0b[t='\0']", "n\u{FFFD}ul.a\u{FFFD}b[t=\"\u{FFFD}\"]"),
+    );
+
+    for (selector, expected) in cases {
+        assert_eq!(serialize(&CompoundSelector::parse_list(selector).unwrap()[0]), expected);
+    }
+}
+
+#[test]
+fn it_reparses_serialized_selectors_to_an_equal_ast() {
+    let selectors = vec!(
+        "sample > related item + item ~ note",
+        r#"[title="it's \"quoted\"\\"][xlink|href^='http'][data-x$=x i]"#,
+        "überschrift.grüße#\\31 23.a\\.b\\:c.\\-.-\\31 x",
+        "[title=\"tab\\9 and\\0 nul\"]",
+        "n\0ul.a\\\0b#\0, [t\0=\"x\0y\"]",
+        "item:not(:first-child, :last-child):is(title, ref):where(.warning):has(> ref)",
+        ":root:empty:only-child:first-of-type:last-of-type:only-of-type",
+        ":nth-child(-2n-1):nth-last-child(n):nth-of-type(-n):nth-last-of-type(odd)",
+        "|*, *|title, svg|rect, [|lang]",
+    );
+
+    for selector in selectors {
+        for compound_selectors in CompoundSelector::parse_list(selector).unwrap() {
+            let serialized = serialize(&compound_selectors);
+            assert_eq!(CompoundSelector::parse(&serialized).unwrap(), compound_selectors, "{}", serialized);
+        }
+    }
+}
+
+#[test]
+fn it_can_compare_and_hash_selectors() {
+    let mut seen = HashSet::new();
+
+    assert!(seen.insert(CompoundSelector::parse("item > title").unwrap()));
+    assert!(!seen.insert(CompoundSelector::parse("item>title").unwrap()));
+    assert!(seen.insert(CompoundSelector::parse("item title").unwrap()));
+}
+
+#[test]
+fn it_computes_the_specificity_of_selectors() {
+    let specificity = |selector: &str| CompoundSelector::parse(selector).unwrap().iter()
+        .map(|compound_selector| compound_selector.specificity())
+        .fold((0, 0, 0), |total, specificity| (total.0 + specificity.0, total.1 + specificity.1, total.2 + specificity.2));
+
+    assert_eq!(specificity("*"), (0, 0, 0));
+    assert_eq!(specificity("svg|*"), (0, 0, 0));
+    assert_eq!(specificity("li"), (0, 0, 1));
+    assert_eq!(specificity("ul li"), (0, 0, 2));
+    assert_eq!(specificity("ul ol+li"), (0, 0, 3));
+    assert_eq!(specificity("h1 + *[rel=up]"), (0, 1, 1));
+    assert_eq!(specificity("ul ol li.red"), (0, 1, 3));
+    assert_eq!(specificity("li.red.level"), (0, 2, 1));
+    assert_eq!(specificity("#x34y"), (1, 0, 0));
+    assert_eq!(specificity("li:nth-child(2n+1):first-child"), (0, 2, 1));
+    assert_eq!(specificity("#s12:not(FOO)"), (1, 0, 1));
+    assert_eq!(specificity(".foo :is(.bar, #baz)"), (1, 1, 0));
+    assert_eq!(specificity("item:has(> ref.a, title)"), (0, 1, 2));
+    assert_eq!(specificity("item:where(#id, .class)"), (0, 0, 1));
+
+    assert!(specificity("#a") > specificity(".a.b.c.d.e.f.g.h.i.j.k"));
+}
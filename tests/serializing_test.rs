@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::Read;
+
+use rquery::{ Document, ParseOptions, QuoteStyle, WriteOptions };
+
+fn write_to_string(document: &Document, options: &WriteOptions) -> String {
+    let mut output = Vec::new();
+    document.write_to(&mut output, options).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn it_writes_a_document_back_to_xml() {
+    let document = Document::new_from_xml_string(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- A leading comment -->
+<main type="simple"><p>a<b>x</b>c<!-- note --><![CDATA[<d>]]><?target data?><empty/></p></main>"#).unwrap();
+
+    assert_eq!(document.to_string(), r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- A leading comment -->
+<main type="simple"><p>a<b>x</b>c<!-- note --><![CDATA[<d>]]><?target data?><empty/></p></main>"#);
+}
+
+#[test]
+fn it_round_trips_through_the_parser() {
+    let xml = r#"<root a="1 &amp; &lt;2&gt;" b="'&quot;'" c="line&#10;break"><x>&lt;tag&gt; &amp; text</x><![CDATA[a < b]]></root>"#;
+    let document = Document::new_from_xml_string(xml).unwrap();
+
+    let reparsed = Document::new_from_xml_string(&document.to_string()).unwrap();
+    let root = reparsed.select("root").unwrap();
+
+    assert_eq!(root.attr("a").unwrap(), "1 & <2>");
+    assert_eq!(root.attr("b").unwrap(), "'\"'");
+    assert_eq!(root.attr("c").unwrap(), "line\nbreak");
+    assert_eq!(reparsed.select("x").unwrap().text(), "<tag> & text");
+    assert_eq!(root.text_content(), "<tag> & texta < b");
+    assert_eq!(reparsed.to_string(), document.to_string());
+}
+
+#[test]
+fn it_writes_the_outer_and_inner_xml_of_an_element() {
+    let document = Document::new_from_xml_string(r#"<main><p class="x">a<b>b</b></p></main>"#).unwrap();
+
+    let p = document.select("p").unwrap();
+    assert_eq!(p.outer_xml(), r#"<p class="x">a<b>b</b></p>"#);
+    assert_eq!(p.inner_xml(), "a<b>b</b>");
+    assert_eq!(format!("{}", p), p.outer_xml());
+}
+
+#[test]
+fn it_keeps_namespace_declarations() {
+    let document = Document::new_from_xml_string(r#"<root xmlns="urn:default" xmlns:x="urn:x"><x:a x:id="1"><b xmlns="">c</b></x:a></root>"#).unwrap();
+
+    let xml = document.select("root").unwrap().outer_xml();
+    assert_eq!(xml, r#"<root xmlns="urn:default" xmlns:x="urn:x"><x:a x:id="1"><b xmlns="">c</b></x:a></root>"#);
+
+    let reparsed = Document::new_from_xml_string(&xml).unwrap();
+    let a = reparsed.select("a").unwrap();
+    assert_eq!(a.namespace_uri(), Some("urn:x"));
+    assert_eq!(a.attr_ns(Some("urn:x"), "id").unwrap(), "1");
+    assert_eq!(reparsed.select("b").unwrap().namespace_uri(), None);
+}
+
+#[test]
+fn it_declares_inherited_namespaces_when_writing_a_subtree() {
+    let xml = r#"<r xmlns="urn:d" xmlns:a="urn:a" xmlns:b="urn:b"><a:item a:x="1"><a:t/><b:u xmlns:b="urn:c"/></a:item><item/></r>"#;
+
+    let expected_xml = vec!(
+        (ParseOptions::default(), r#"<a:item xmlns:a="urn:a" a:x="1"><a:t/><b:u xmlns:b="urn:c"/></a:item>"#),
+        (lossless(), r#"<a:item a:x="1" xmlns:a="urn:a"><a:t/><b:u xmlns:b="urn:c"/></a:item>"#),
+    );
+
+    for (options, expected_xml) in expected_xml {
+        let document = Document::new_from_xml_string_with_options(xml, &options).unwrap();
+
+        let xml = document.select("item").unwrap().outer_xml();
+        assert_eq!(xml, expected_xml);
+
+        let reparsed = Document::new_from_xml_string(&xml).unwrap();
+        let item = reparsed.select("item").unwrap();
+        assert_eq!(item.namespace_uri(), Some("urn:a"));
+        assert_eq!(item.attr_ns(Some("urn:a"), "x").unwrap(), "1");
+
+        let xml = document.select("r > item:last-child").unwrap().outer_xml();
+        assert_eq!(xml, r#"<item xmlns="urn:d"/>"#);
+        assert_eq!(Document::new_from_xml_string(&xml).unwrap().select("item").unwrap().namespace_uri(), Some("urn:d"));
+    }
+}
+
+#[test]
+fn it_pretty_prints_with_the_given_indentation() {
+    let document = Document::new_from_xml_string(r#"<main>
+    <p>Some <b>text</b></p>
+<!-- note --><empty/></main>"#).unwrap();
+
+    let options = WriteOptions {
+        pretty_print: true,
+        indent: "\t".to_string(),
+        ..WriteOptions::default()
+    };
+
+    assert_eq!(write_to_string(&document, &options), r#"<?xml version="1.0" encoding="UTF-8"?>
+<main>
+	<p>Some <b>text</b></p>
+	<!-- note -->
+	<empty/>
+</main>"#);
+}
+
+#[test]
+fn it_pretty_prints_with_the_alternate_flag() {
+    let document = Document::new_from_xml_string("<main><a><b/></a></main>").unwrap();
+
+    assert_eq!(format!("{:#}", document.select("a").unwrap()), "<a>\n  <b/>\n</a>");
+}
+
+#[test]
+fn it_writes_with_single_quotes_and_without_a_declaration() {
+    let document = Document::new_from_xml_string(r#"<main a="it's &quot;x&quot;"/>"#).unwrap();
+
+    let options = WriteOptions {
+        quote_style: QuoteStyle::Single,
+        xml_declaration: false,
+        ..WriteOptions::default()
+    };
+
+    assert_eq!(write_to_string(&document, &options), r#"<main a='it&apos;s "x"'/>"#);
+}
+
+fn lossless() -> ParseOptions {
+    ParseOptions {
+        lossless: true,
+        ..ParseOptions::default()
+    }
+}
+
+#[test]
+fn it_reproduces_the_input_in_lossless_mode() {
+    let xml = "<?xml version='1.0' encoding='UTF-8' ?>\r\n<!DOCTYPE config SYSTEM \"config.dtd\">\r\n<!-- Settings -->\r\n<config   b = 'x'  a=\"1 &amp; 2\" >\r\n\t<item id='a' />\r\n\t<?target  data ?>\r\n\t<text>&lt;kept&gt; &#65;<![CDATA[ ]]></text>\r\n</config >\r\n\r\n<!-- trailing -->\r\n";
+    let document = Document::new_from_xml_string_with_options(xml, &lossless()).unwrap();
+
+    assert_eq!(document.to_string(), xml);
+    assert_eq!(write_to_string(&document, &WriteOptions::default()), xml);
+}
+
+#[test]
+fn it_ignores_the_write_options_in_lossless_mode() {
+    let xml = "<config   a='1'>\n    <item/>\n</config>";
+    let document = Document::new_from_xml_string_with_options(xml, &lossless()).unwrap();
+
+    let options = WriteOptions {
+        pretty_print: true,
+        quote_style: QuoteStyle::Double,
+        xml_declaration: true,
+        ..WriteOptions::default()
+    };
+
+    assert_eq!(write_to_string(&document, &options), xml);
+    assert_eq!(format!("{:#}", document), xml);
+}
+
+#[test]
+fn it_writes_the_original_outer_and_inner_xml_in_lossless_mode() {
+    let document = Document::new_from_xml_string_with_options("<main><p  class='x' >a <b/></p ></main>", &lossless()).unwrap();
+
+    let p = document.select("p").unwrap();
+    assert_eq!(p.outer_xml(), "<p  class='x' >a <b/></p >");
+    assert_eq!(p.inner_xml(), "a <b/>");
+}
+
+#[test]
+fn it_reproduces_a_file_in_lossless_mode() {
+    let document = Document::new_from_xml_file_with_options("tests/fixtures/sample.xml", &lossless()).unwrap();
+
+    let mut source = String::new();
+    File::open("tests/fixtures/sample.xml").unwrap().read_to_string(&mut source).unwrap();
+
+    assert_eq!(document.to_string(), source);
+}